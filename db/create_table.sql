@@ -20,11 +20,22 @@ create type "Gender" as enum (
 create type "AuthType" as enum (
     'Username',
     'Phone',
-    'Email'
+    'Email',
+    'Oidc',
+    'Ldap'
     );
 
 comment on type "AuthType" is '授权类型';
 
+create type "UserStatus" as enum (
+    'Active',
+    'Disabled',
+    'Pending',
+    'Deleted'
+    );
+
+comment on type "UserStatus" is '用户状态';
+
 -- 用户表
 create table user_info
 (
@@ -39,6 +50,8 @@ create table user_info
     update_time timestamp default now() not null,
     create_time timestamp default now() not null,
     max_role bigint,
+    status "UserStatus" default 'Active' not null,
+    status_reason text,
     constraint user_info_username_unique
         unique (username)
 );
@@ -52,6 +65,8 @@ comment on column user_info.gender is '性别';
 comment on column user_info.birthday is '出生日期';
 comment on column user_info.update_time is '更新时间';
 comment on column user_info.max_role is '最大角色数';
+comment on column user_info.status is '用户状态，只有 Active 的用户可以登录，Deleted 表示已被软删除';
+comment on column user_info.status_reason is '最近一次变更用户状态的原因';
 
 create trigger user_info_on_update
     before update
@@ -93,6 +108,97 @@ create trigger user_auth_on_update
     for each row
 execute procedure update_modified_column();
 
+-- 用户登录方式变更记录表
+create table user_auth_history
+(
+    id bigserial not null
+        constraint user_auth_history_pk
+            primary key,
+    user_id bigint not null
+        constraint user_auth_history_fk_user
+            references user_info,
+    auth_type "AuthType" not null,
+    old_identity varchar(255),
+    new_identity varchar(255),
+    client_ip text,
+    create_time timestamp default now() not null
+);
+
+create index user_auth_history_user_id_index
+    on user_auth_history (user_id);
+
+comment on table user_auth_history is '用户登录方式变更记录表';
+comment on column user_auth_history.id is '记录ID';
+comment on column user_auth_history.user_id is '用户ID';
+comment on column user_auth_history.auth_type is '授权方式';
+comment on column user_auth_history.old_identity is '变更前的身份标识，为空表示绑定';
+comment on column user_auth_history.new_identity is '变更后的身份标识，为空表示解绑';
+comment on column user_auth_history.client_ip is '发起变更的客户端 IP';
+comment on column user_auth_history.create_time is '变更时间';
+
+-- 两步验证密钥表，与登录方式无关，任何用户均可启用
+create table user_totp
+(
+    user_id bigint not null
+        constraint user_totp_pk
+            primary key
+        constraint user_totp_fk_user
+            references user_info,
+    secret varchar(64) not null,
+    create_time timestamp default now() not null
+);
+
+comment on table user_totp is '两步验证密钥表';
+comment on column user_totp.user_id is '用户ID';
+comment on column user_totp.secret is 'Base32 编码的 TOTP 密钥';
+comment on column user_totp.create_time is '启用时间';
+
+-- 两步验证恢复码表
+create table user_recovery_code
+(
+    user_id bigint not null
+        constraint user_recovery_code_fk_user
+            references user_info,
+    code_hash char(64) not null,
+    constraint user_recovery_code_pk
+        primary key (user_id, code_hash)
+);
+
+comment on table user_recovery_code is '两步验证恢复码表';
+comment on column user_recovery_code.user_id is '用户ID';
+comment on column user_recovery_code.code_hash is '恢复码的 SHA-256 hash';
+
+-- 个人访问令牌表
+create table user_token
+(
+    id bigserial not null
+        constraint user_token_pk
+            primary key,
+    user_id bigint not null
+        constraint user_token_fk_user
+            references user_info,
+    name varchar(64) not null,
+    token_hash char(64) not null,
+    scopes text[] not null default '{}',
+    expire_time timestamp,
+    last_used_time timestamp,
+    create_time timestamp default now() not null,
+    constraint user_token_hash_unique
+        unique (token_hash),
+    constraint user_token_user_name_unique
+        unique (user_id, name)
+);
+
+comment on table user_token is '个人访问令牌表';
+comment on column user_token.id is '令牌ID';
+comment on column user_token.user_id is '用户ID';
+comment on column user_token.name is '令牌名称';
+comment on column user_token.token_hash is '令牌的 SHA-256 hash';
+comment on column user_token.scopes is '令牌可使用的权限名';
+comment on column user_token.expire_time is '过期时间，为空表示永不过期';
+comment on column user_token.last_used_time is '最近使用时间';
+comment on column user_token.create_time is '创建时间';
+
 -- 角色表
 create table role
 (
@@ -232,4 +338,22 @@ create table role_permission
 
 comment on table role_permission is '角色权限表';
 comment on column role_permission.role_id is '角色ID';
-comment on column role_permission.permission_id is '权限ID';
\ No newline at end of file
+comment on column role_permission.permission_id is '权限ID';
+
+-- 初始数据: 内置权限及拥有全部内置权限的超级管理员角色，需手动将超级管理员角色授予初始用户
+insert into permission(permission_name)
+values ('role:read'),
+       ('role:write'),
+       ('permission:read'),
+       ('permission:write'),
+       ('user:read'),
+       ('user:write');
+
+insert into role(name, max_user)
+values ('超级管理员', 1);
+
+insert into role_permission(role_id, permission_id)
+select role.id, permission.id
+from role,
+     permission
+where role.name = '超级管理员';
@@ -0,0 +1,73 @@
+//! 头像读取相关控制器
+//!
+//! 头像文件名包含内容的 hash，内容不会改变，因此可以长期缓存
+use crate::error::Error;
+use crate::model::Id;
+use crate::service::avatar::AvatarService;
+use actix_web::http::header::{CacheControl, CacheDirective, ETag, EntityTag, Header, IfNoneMatch};
+use actix_web::web::{Data, Path};
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+
+/// 获取头像相关的所有路由
+pub fn get_avatar_scope() -> Scope {
+    web::scope(AvatarService::PATH)
+        .service(web::resource("/{user_id}/{name}").route(web::get().to(get_avatar)))
+}
+
+/// 读取头像，地址见 `POST /user/avatar` 的返回值，无需登录
+///
+/// 请求带有 `If-None-Match` 头且与头像的 `ETag` 相同时返回 304
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /avatars/5/3f2a9c1d7e8b4a60_64.png
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 9637
+/// cache-control: public, max-age=31536000, immutable
+/// etag: "3f2a9c1d7e8b4a60_64.png"
+/// content-type: image/png
+/// date: Sun, 29 Mar 2020 03:16:10 GMT
+///
+/// <Binary data>
+/// ```
+async fn get_avatar(
+    req: HttpRequest,
+    avatar_svc: Data<AvatarService>,
+    path: Path<(Id, String)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, name) = path.into_inner();
+
+    let data = avatar_svc.load(user_id, &name).await?;
+
+    let etag = EntityTag::strong(name);
+    let cache_control = CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(avatar_svc.cache_max_age() as u32),
+        CacheDirective::Extension("immutable".into(), None),
+    ]);
+
+    let not_modified = match IfNoneMatch::parse(&req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(&etag)),
+        Err(_) => false,
+    };
+
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .set(cache_control)
+            .set(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(AvatarService::CONTENT_TYPE)
+        .set(cache_control)
+        .set(ETag(etag))
+        .body(data))
+}
@@ -0,0 +1,461 @@
+//! 角色约束相关控制器
+//!
+use super::IntoJsonResult;
+use crate::controller::EmptyBody;
+use crate::error::Error;
+use crate::model::{
+    BaseRequiredConstraint, BaseRequiredConstraintContent, Count, Id, MutexConstraint,
+    MutexConstraintContent,
+};
+use crate::service::constraint::ConstraintService;
+use crate::util::db::Pager;
+use actix_web::{web, web::Data, web::Json, web::Path, Scope};
+
+/// 获取角色约束相关的所有路由
+pub fn get_constraint_scope() -> Scope {
+    web::scope("/constraint")
+        .service(web::resource("/mutex").route(web::post().to(create_mutex_constraint)))
+        .service(web::resource("/mutex/count").route(web::get().to(get_mutex_count)))
+        .service(
+            web::resource("/mutex/list/{page}/{rows}").route(web::get().to(list_mutex_constraints)),
+        )
+        .service(
+            web::resource("/mutex/{id}")
+                .route(web::get().to(retrieve_mutex_constraint))
+                .route(web::patch().to(update_mutex_constraint))
+                .route(web::delete().to(delete_mutex_constraint)),
+        )
+        .service(
+            web::resource("/baseRequired").route(web::post().to(create_base_required_constraint)),
+        )
+        .service(web::resource("/baseRequired/count").route(web::get().to(get_base_required_count)))
+        .service(
+            web::resource("/baseRequired/list/{page}/{rows}")
+                .route(web::get().to(list_base_required_constraints)),
+        )
+        .service(
+            web::resource("/baseRequired/{id}")
+                .route(web::get().to(retrieve_base_required_constraint))
+                .route(web::patch().to(update_base_required_constraint))
+                .route(web::delete().to(delete_base_required_constraint)),
+        )
+}
+
+/// 统计互斥约束总数
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/mutex/count
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 11
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 09:20:11 GMT
+///
+/// {
+///   "count": 1
+/// }
+/// ```
+async fn get_mutex_count(cons_svc: Data<ConstraintService>) -> Result<Json<Count>, Error> {
+    cons_svc.query_mutex_count().await.json()
+}
+
+/// 分页查询互斥约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/mutex/list/0/10
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 181
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 09:21:37 GMT
+///
+/// [
+///   {
+///     "id": 1,
+///     "constraint_name": "出纳与审计",
+///     "roles": [
+///       {
+///         "id": 7,
+///         "name": "出纳",
+///         "max_user": null,
+///         "max_permission": null
+///       },
+///       {
+///         "id": 8,
+///         "name": "审计",
+///         "max_user": null,
+///         "max_permission": null
+///       }
+///     ]
+///   }
+/// ]
+/// ```
+async fn list_mutex_constraints(
+    cons_svc: Data<ConstraintService>,
+    pager: Path<Pager>,
+) -> Result<Json<Vec<MutexConstraint>>, Error> {
+    cons_svc.list_mutex_constraints(&pager).await.json()
+}
+
+/// 创建互斥约束，用户不能同时拥有同一互斥约束中的多个角色
+///
+/// 如果已有用户同时拥有其中多个角色，返回错误码 12
+///
+/// ## Example
+///
+/// HTTP 请求:
+///
+/// ```
+/// POST /constraint/mutex
+/// Content-Type: application/json
+///
+/// {"constraint_name": "出纳与审计", "role_ids": [7, 8]}
+/// ```
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 179
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 09:18:45 GMT
+///
+/// {
+///   "id": 1,
+///   "constraint_name": "出纳与审计",
+///   "roles": [
+///     {
+///       "id": 7,
+///       "name": "出纳",
+///       "max_user": null,
+///       "max_permission": null
+///     },
+///     {
+///       "id": 8,
+///       "name": "审计",
+///       "max_user": null,
+///       "max_permission": null
+///     }
+///   ]
+/// }
+/// ```
+async fn create_mutex_constraint(
+    cons_svc: Data<ConstraintService>,
+    params: Json<MutexConstraintContent>,
+) -> Result<Json<MutexConstraint>, Error> {
+    cons_svc.create_mutex_constraint(&params).await.json()
+}
+
+/// 查询互斥约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/mutex/1
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 179
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 09:22:03 GMT
+///
+/// {
+///   "id": 1,
+///   "constraint_name": "出纳与审计",
+///   "roles": [
+///     {
+///       "id": 7,
+///       "name": "出纳",
+///       "max_user": null,
+///       "max_permission": null
+///     },
+///     {
+///       "id": 8,
+///       "name": "审计",
+///       "max_user": null,
+///       "max_permission": null
+///     }
+///   ]
+/// }
+/// ```
+async fn retrieve_mutex_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+) -> Result<Json<MutexConstraint>, Error> {
+    cons_svc
+        .query_mutex_constraint(id.into_inner())
+        .await
+        .json()
+}
+
+/// 修改互斥约束的名称及包含的角色
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// PATCH /constraint/mutex/1
+/// Content-Type: application/json
+///
+/// {"constraint_name": "出纳、会计与审计", "role_ids": [7, 8, 9]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 09:25:40 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn update_mutex_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+    params: Json<MutexConstraintContent>,
+) -> Result<&'static str, Error> {
+    cons_svc
+        .update_mutex_constraint(id.into_inner(), &params)
+        .await
+        .empty_body()
+}
+
+/// 删除互斥约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /constraint/mutex/1
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 09:26:18 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn delete_mutex_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+) -> Result<&'static str, Error> {
+    cons_svc
+        .delete_mutex_constraint(id.into_inner())
+        .await
+        .empty_body()
+}
+
+/// 统计先决条件约束总数
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/baseRequired/count
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 11
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 10:20:11 GMT
+///
+/// {
+///   "count": 1
+/// }
+/// ```
+async fn get_base_required_count(cons_svc: Data<ConstraintService>) -> Result<Json<Count>, Error> {
+    cons_svc.query_base_required_count().await.json()
+}
+
+/// 分页查询先决条件约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/baseRequired/list/0/10
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 118
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 10:21:37 GMT
+///
+/// [
+///   {
+///     "id": 2,
+///     "constraint_name": "高级会计须先成为会计",
+///     "role": {
+///       "id": 10,
+///       "name": "高级会计",
+///       "max_user": null,
+///       "max_permission": null
+///     }
+///   }
+/// ]
+/// ```
+async fn list_base_required_constraints(
+    cons_svc: Data<ConstraintService>,
+    pager: Path<Pager>,
+) -> Result<Json<Vec<BaseRequiredConstraint>>, Error> {
+    cons_svc.list_base_required_constraints(&pager).await.json()
+}
+
+/// 创建先决条件约束，用户必须先拥有角色的所有父角色（见 `/role/{id}/bases`）才能被授予该角色
+///
+/// 如果已有用户拥有该角色却缺少其父角色，返回错误码 13
+///
+/// ## Example
+///
+/// HTTP 请求:
+///
+/// ```
+/// POST /constraint/baseRequired
+/// Content-Type: application/json
+///
+/// {"constraint_name": "高级会计须先成为会计", "role_id": 10}
+/// ```
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 116
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 10:18:45 GMT
+///
+/// {
+///   "id": 2,
+///   "constraint_name": "高级会计须先成为会计",
+///   "role": {
+///     "id": 10,
+///     "name": "高级会计",
+///     "max_user": null,
+///     "max_permission": null
+///   }
+/// }
+/// ```
+async fn create_base_required_constraint(
+    cons_svc: Data<ConstraintService>,
+    params: Json<BaseRequiredConstraintContent>,
+) -> Result<Json<BaseRequiredConstraint>, Error> {
+    cons_svc
+        .create_base_required_constraint(&params)
+        .await
+        .json()
+}
+
+/// 查询先决条件约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /constraint/baseRequired/2
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 116
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 10:22:03 GMT
+///
+/// {
+///   "id": 2,
+///   "constraint_name": "高级会计须先成为会计",
+///   "role": {
+///     "id": 10,
+///     "name": "高级会计",
+///     "max_user": null,
+///     "max_permission": null
+///   }
+/// }
+/// ```
+async fn retrieve_base_required_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+) -> Result<Json<BaseRequiredConstraint>, Error> {
+    cons_svc
+        .query_base_required_constraint(id.into_inner())
+        .await
+        .json()
+}
+
+/// 修改先决条件约束的名称及限制的角色
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// PATCH /constraint/baseRequired/2
+/// Content-Type: application/json
+///
+/// {"constraint_name": "高级会计须先成为会计", "role_id": 11}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 10:25:40 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn update_base_required_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+    params: Json<BaseRequiredConstraintContent>,
+) -> Result<&'static str, Error> {
+    cons_svc
+        .update_base_required_constraint(id.into_inner(), &params)
+        .await
+        .empty_body()
+}
+
+/// 删除先决条件约束
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /constraint/baseRequired/2
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 10:26:18 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn delete_base_required_constraint(
+    cons_svc: Data<ConstraintService>,
+    id: Path<Id>,
+) -> Result<&'static str, Error> {
+    cons_svc
+        .delete_base_required_constraint(id.into_inner())
+        .await
+        .empty_body()
+}
@@ -1,10 +1,14 @@
 //! 控制器（Controller）的实现
 //!
+mod avatar;
+mod constraint;
 mod permission;
 mod role;
 mod user;
+mod users;
 
 use crate::error::Error;
+use crate::util::permission::PermissionFactory;
 use actix_service::ServiceFactory;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
@@ -50,6 +54,9 @@ impl<T, E: Into<Error>> EmptyBody<T, E> for Result<T, E> {
 
 /// 加载所有控制器，已为 `actix_web::app:App` 实现这个 `trait`，
 /// 详见 `main.rs` 中对 `load_all_controllers` 函数的调用
+///
+/// 需要权限的 `Scope` 统一在此处使用 `PermissionFactory` 声明所需权限:
+/// 只读请求需要 `xxx:read`，其他请求需要 `xxx:write`
 pub trait LoadAllControllers {
     fn load_all_controllers(self) -> Self;
 }
@@ -67,7 +74,21 @@ where
 {
     fn load_all_controllers(self) -> Self {
         self.service(user::get_user_scope())
-            .service(role::get_role_scope())
-            .service(permission::get_permission_scope())
+            .service(avatar::get_avatar_scope())
+            .service(
+                role::get_role_scope().wrap(PermissionFactory::new("role:write").read("role:read")),
+            )
+            .service(
+                permission::get_permission_scope()
+                    .wrap(PermissionFactory::new("permission:write").read("permission:read")),
+            )
+            .service(
+                users::get_users_scope()
+                    .wrap(PermissionFactory::new("user:write").read("user:read")),
+            )
+            .service(
+                constraint::get_constraint_scope()
+                    .wrap(PermissionFactory::new("role:write").read("role:read")),
+            )
     }
 }
@@ -3,7 +3,10 @@
 use super::IntoJsonResult;
 use crate::controller::EmptyBody;
 use crate::error::Error;
-use crate::model::{Count, Id, Role, RoleContent};
+use crate::model::{
+    Count, Id, Permission, PermissionIdsParams, RevokeRoleParams, Role, RoleContent, UserIdsParams,
+    UserInfo,
+};
 use crate::service::role::RoleService;
 use crate::util::db::Pager;
 use actix_web::{web, web::Data, web::Json, web::Path, Scope};
@@ -20,6 +23,29 @@ pub fn get_role_scope() -> Scope {
                 .route(web::patch().to(update_role))
                 .route(web::delete().to(delete_role)),
         )
+        .service(web::resource("/{id}/bases").route(web::get().to(list_base_roles)))
+        .service(
+            web::resource("/{id}/bases/{base_id}")
+                .route(web::post().to(add_base_role))
+                .route(web::delete().to(remove_base_role)),
+        )
+        .service(
+            web::resource("/{id}/users")
+                .route(web::get().to(list_role_users))
+                .route(web::post().to(grant_role_to_users))
+                .route(web::delete().to(revoke_role_from_users)),
+        )
+        .service(
+            web::resource("/{id}/permissions")
+                .route(web::get().to(list_role_permissions))
+                .route(web::put().to(replace_permissions)),
+        )
+        .service(
+            web::resource("/{id}/permissions/{permission_id}")
+                .route(web::post().to(grant_permission))
+                .route(web::delete().to(revoke_permission)),
+        )
+        .service(web::resource("/{id}/effectivePermissions").route(web::get().to(get_role_perm)))
 }
 
 /// 统计角色总数
@@ -203,3 +229,366 @@ async fn delete_role(
 ) -> Result<&'static str, Error> {
     role_svc.delete_role(id.into_inner()).await.empty_body()
 }
+
+/// 查询角色的所有直接父角色
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /role/6/bases
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 65
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 08:12:40 GMT
+///
+/// [
+///   {
+///     "id": 5,
+///     "name": "角色名",
+///     "max_user": 121212,
+///     "max_permission": null
+///   }
+/// ]
+/// ```
+async fn list_base_roles(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+) -> Result<Json<Vec<Role>>, Error> {
+    role_svc.list_base_roles(id.into_inner()).await.json()
+}
+
+/// 为角色添加父角色，派生角色会继承父角色（及其祖先角色）的所有权限
+///
+/// 如果添加后继承关系成环，返回错误码 11；
+/// 如果拥有该角色（含通过继承拥有）的用户因此间接拥有了互斥的角色，返回错误码 12；
+/// 如果该角色有先决条件约束，而直接拥有该角色的用户未直接拥有新的父角色，返回错误码 13
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /role/6/bases/5
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 08:10:21 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn add_base_role(
+    role_svc: Data<RoleService>,
+    path: web::Path<(Id, Id)>,
+) -> Result<&'static str, Error> {
+    let (id, base_id) = path.into_inner();
+    role_svc.add_base_role(id, base_id).await.empty_body()
+}
+
+/// 删除角色与父角色的继承关系，删除后直接拥有该角色的用户仍须满足先决条件约束，否则返回错误码 13
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /role/6/bases/5
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 08:15:02 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn remove_base_role(
+    role_svc: Data<RoleService>,
+    path: web::Path<(Id, Id)>,
+) -> Result<&'static str, Error> {
+    let (id, base_id) = path.into_inner();
+    role_svc.remove_base_role(id, base_id).await.empty_body()
+}
+
+/// 查询角色的所有有效权限，包括从所有祖先角色继承的权限
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /role/6/effectivePermissions
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 83
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 08:13:37 GMT
+///
+/// [
+///   {
+///     "id": 1,
+///     "permission_name": "role:read"
+///   },
+///   {
+///     "id": 2,
+///     "permission_name": "role:write"
+///   }
+/// ]
+/// ```
+async fn get_role_perm(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+) -> Result<Json<Vec<Permission>>, Error> {
+    role_svc.query_role_perm(id.into_inner()).await.json()
+}
+
+/// 查询拥有该角色的所有用户
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /role/7/users
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 199
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 11:12:40 GMT
+///
+/// [
+///   {
+///     "id": 5,
+///     "username": "gengteng",
+///     "nickname": "GT",
+///     "avatar": null,
+///     "gender": "Unknown",
+///     "birthday": null,
+///     "create_time": "2020-02-23T13:23:57.305393",
+///     "update_time": "2020-02-23T13:23:57.305393",
+///     "max_role": null,
+///     "status": "Active",
+///     "status_reason": null
+///   }
+/// ]
+/// ```
+async fn list_role_users(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+) -> Result<Json<Vec<UserInfo>>, Error> {
+    role_svc.list_role_users(id.into_inner()).await.json()
+}
+
+/// 将角色批量授予多个用户，任一用户授予失败则全部不生效
+///
+/// 错误码同 `POST /user/{id}/roles/{role_id}`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /role/7/users
+/// Content-Type: application/json
+///
+/// {"user_ids": [5, 6]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 11:15:02 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn grant_role_to_users(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+    params: Json<UserIdsParams>,
+) -> Result<&'static str, Error> {
+    role_svc
+        .grant_role_to_users(id.into_inner(), &params.user_ids)
+        .await
+        .empty_body()
+}
+
+/// 批量收回多个用户的该角色，未拥有该角色的用户会被忽略
+///
+/// `cascade` 的含义同 `DELETE /user/{id}/roles/{role_id}`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /role/7/users?cascade=true
+/// Content-Type: application/json
+///
+/// {"user_ids": [5, 6]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 11:16:45 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_role_from_users(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+    params: Json<UserIdsParams>,
+    query: web::Query<RevokeRoleParams>,
+) -> Result<&'static str, Error> {
+    role_svc
+        .revoke_role_from_users(id.into_inner(), &params.user_ids, query.cascade)
+        .await
+        .empty_body()
+}
+
+/// 查询角色直接拥有的所有权限，不包括从父角色继承的权限
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /role/6/permissions
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 41
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 12:01:17 GMT
+///
+/// [
+///   {
+///     "id": 1,
+///     "permission_name": "role:read"
+///   }
+/// ]
+/// ```
+async fn list_role_permissions(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+) -> Result<Json<Vec<Permission>>, Error> {
+    role_svc.list_role_permissions(id.into_inner()).await.json()
+}
+
+/// 为角色授予权限
+///
+/// 如果超过角色的最大权限数，返回错误码 17
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /role/6/permissions/2
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 40
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 12:03:42 GMT
+///
+/// {
+///   "id": 2,
+///   "permission_name": "role:write"
+/// }
+/// ```
+async fn grant_permission(
+    role_svc: Data<RoleService>,
+    path: web::Path<(Id, Id)>,
+) -> Result<Json<Permission>, Error> {
+    let (id, permission_id) = path.into_inner();
+    role_svc.grant_permission(id, permission_id).await.json()
+}
+
+/// 收回角色的权限
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /role/6/permissions/2
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 12:04:55 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_permission(
+    role_svc: Data<RoleService>,
+    path: web::Path<(Id, Id)>,
+) -> Result<&'static str, Error> {
+    let (id, permission_id) = path.into_inner();
+    role_svc
+        .revoke_permission(id, permission_id)
+        .await
+        .empty_body()
+}
+
+/// 将角色的权限整体替换为请求中的权限列表
+///
+/// 如果超过角色的最大权限数，返回错误码 17
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// PUT /role/6/permissions
+/// Content-Type: application/json
+///
+/// {"permission_ids": [1, 2]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 83
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 12:06:20 GMT
+///
+/// [
+///   {
+///     "id": 1,
+///     "permission_name": "role:read"
+///   },
+///   {
+///     "id": 2,
+///     "permission_name": "role:write"
+///   }
+/// ]
+/// ```
+async fn replace_permissions(
+    role_svc: Data<RoleService>,
+    id: web::Path<Id>,
+    params: Json<PermissionIdsParams>,
+) -> Result<Json<Vec<Permission>>, Error> {
+    role_svc
+        .replace_permissions(id.into_inner(), &params.permission_ids)
+        .await
+        .json()
+}
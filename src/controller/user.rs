@@ -5,14 +5,29 @@ use super::IntoJsonResult;
 use crate::controller::EmptyBody;
 use crate::error::{Error, Kind};
 use crate::model::{
-    AddPasswordParams, AuthType, GetAuthCodeParams, RegisterParams, Role, RolePermission,
-    SignInParams, UserAuth, UserInfo,
+    AccessToken, AddPasswordParams, AuthType, BindEmailParams, BindIdentityParams,
+    ChangePasswordParams, CreateAccessTokenParams, CreatedAccessToken, EmailRegisterParams,
+    GetAuthCodeParams, Id, LoginFailure, OidcAuthorization, OidcAuthorizeParams, OidcLinkParams,
+    OidcSignInParams, Permission, ProfileUpdate, ReauthParams, RecoveryCodes, RegisterParams,
+    ResetPasswordParams, RevokeRoleParams, Role, RoleIdsParams, Session, SignInParams,
+    SignInResult, TotpCodeParams, TotpEnrollment, TotpSignInParams, UploadedAvatar, UserAuth,
+    UserAuthHistory, UserInfo,
 };
+use crate::opt::SessionOpts;
+use crate::service::avatar::AvatarService;
+use crate::service::ldap::LdapService;
+use crate::service::oidc::OidcService;
+use crate::service::token::TokenService;
+use crate::service::totp::TotpService;
 use crate::service::user::UserService;
-use crate::util::types::{AuthCode, Email, Phone, Username};
+use crate::util::http::ClientIp;
+use crate::util::permission::PermissionFactory;
+use crate::util::types::{AuthCode, Email, Nickname, Phone, Username};
 use crate::util::user::User;
-use actix_web::web::Json;
+use actix_multipart::Multipart;
+use actix_web::web::{Json, Path, Query};
 use actix_web::{web, Scope};
+use futures::{StreamExt, TryStreamExt};
 
 /// 获取用户及登录相关的所有路由
 pub fn get_user_scope() -> Scope {
@@ -20,17 +35,88 @@ pub fn get_user_scope() -> Scope {
         .service(web::resource("/phoneAuthCode").route(web::post().to(send_auth_code_to_phone)))
         .service(web::resource("/emailAuthCode").route(web::post().to(send_auth_code_to_email)))
         .service(web::resource("/register").route(web::post().to(register_with_phone)))
+        .service(web::resource("/registerWithEmail").route(web::post().to(register_with_email)))
+        .service(web::resource("/bindEmail").route(web::post().to(bind_email)))
         .service(web::resource("/signIn").route(web::post().to(sign_in)))
+        .service(web::resource("/signIn/totp").route(web::post().to(sign_in_with_totp)))
+        .service(web::resource("/signIn/oidc").route(web::post().to(sign_in_with_oidc)))
+        .service(web::resource("/oidc").route(web::get().to(list_oidc_providers)))
+        .service(web::resource("/oidc/{provider}/authorize").route(web::get().to(authorize_oidc)))
+        .service(web::resource("/oidc/link").route(web::post().to(link_oidc)))
         .service(web::resource("/signOut").route(web::post().to(sign_out)))
-        .service(web::resource("/info").route(web::get().to(get_user_info)))
+        .service(
+            web::resource("/info")
+                .route(web::get().to(get_user_info))
+                .route(web::patch().to(update_user_info)),
+        )
+        .service(
+            web::resource("/avatar")
+                .route(web::post().to(upload_avatar))
+                .route(web::delete().to(delete_avatar)),
+        )
         .service(web::resource("/addPassword").route(web::post().to(add_password)))
+        .service(web::resource("/changePassword").route(web::post().to(change_password)))
+        .service(web::resource("/resetPassword").route(web::post().to(reset_password)))
         .service(web::resource("/roles").route(web::get().to(get_user_role)))
         .service(web::resource("/authentications").route(web::get().to(get_user_auth)))
+        .service(
+            web::resource("/authentications/history").route(web::get().to(get_user_auth_history)),
+        )
+        .service(
+            web::resource("/authentications/{auth_type}")
+                .route(web::put().to(bind_identity))
+                .route(web::delete().to(unbind_identity)),
+        )
         .service(web::resource("/permissions").route(web::get().to(get_user_perm)))
+        .service(
+            web::resource("/totp")
+                .route(web::post().to(begin_totp_enrollment))
+                .route(web::delete().to(disable_totp)),
+        )
+        .service(web::resource("/totp/confirm").route(web::post().to(confirm_totp_enrollment)))
+        .service(
+            web::resource("/sessions")
+                .route(web::get().to(list_sessions))
+                .route(web::delete().to(revoke_all_sessions)),
+        )
+        .service(web::resource("/sessions/{session_id}").route(web::delete().to(revoke_session)))
+        .service(
+            web::resource("/tokens")
+                .route(web::get().to(list_tokens))
+                .route(web::post().to(create_token)),
+        )
+        .service(web::resource("/tokens/{token_id}").route(web::delete().to(delete_token)))
+        .service(
+            web::resource("/{id}/roles")
+                .wrap(PermissionFactory::new("user:write").read("user:read"))
+                .route(web::get().to(list_user_roles))
+                .route(web::post().to(grant_roles))
+                .route(web::delete().to(revoke_roles)),
+        )
+        .service(
+            web::resource("/{id}/roles/{role_id}")
+                .wrap(PermissionFactory::new("user:write"))
+                .route(web::post().to(grant_role))
+                .route(web::delete().to(revoke_role)),
+        )
+        .service(
+            web::resource("/{id}/lock")
+                .wrap(PermissionFactory::new("user:write").read("user:read"))
+                .route(web::get().to(get_login_failure))
+                .route(web::delete().to(unlock_user)),
+        )
+        .service(
+            web::resource("/{id}/totp")
+                .wrap(PermissionFactory::new("user:write"))
+                .route(web::delete().to(reset_totp)),
+        )
 }
 
 /// 发送6位数字验证码到手机号
 ///
+/// 同一手机号 60 秒内只能发送一次，否则返回错误码 19；发送次数超出频率限制时返回错误码 18。
+/// 两种情况都会在 `Retry-After` 响应头中给出需等待的秒数。
+///
 /// ## Example
 ///
 /// HTTP 请求:
@@ -49,23 +135,22 @@ pub fn get_user_scope() -> Scope {
 /// ```
 async fn send_auth_code_to_phone(
     get_auth_param: Json<GetAuthCodeParams>,
+    client_ip: ClientIp,
     user_svc: web::Data<UserService>,
 ) -> Result<&'static str, Error> {
     let phone = Phone::new(&get_auth_param.identity)?;
 
-    let auth_code = AuthCode::default();
-
-    // TODO: send auth code to phone
-    info!("已给手机号 {} 发送数字验证码 {}", phone, auth_code);
-
     user_svc
-        .cache_auth_code(AuthType::Phone, &phone, &auth_code)
+        .send_auth_code_to_phone(&phone, &client_ip)
         .await
         .empty_body()
 }
 
 /// 发送6位数字验证码到邮箱
 ///
+/// 同一邮箱 60 秒内只能发送一次，否则返回错误码 19；发送次数超出频率限制时返回错误码 18。
+/// 两种情况都会在 `Retry-After` 响应头中给出需等待的秒数。
+///
 /// ## Example
 ///
 /// HTTP 请求:
@@ -84,17 +169,13 @@ async fn send_auth_code_to_phone(
 /// ```
 async fn send_auth_code_to_email(
     get_auth_param: Json<GetAuthCodeParams>,
+    client_ip: ClientIp,
     user_svc: web::Data<UserService>,
 ) -> Result<&'static str, Error> {
     let email = Email::new(&get_auth_param.identity)?;
 
-    let auth_code = AuthCode::default();
-
-    // TODO: send auth code to email address
-    info!("已给电子邮箱 {} 发送数字验证码 {}", email, auth_code);
-
     user_svc
-        .cache_auth_code(AuthType::Email, &email, &auth_code)
+        .send_auth_code_to_email(&email, &client_ip)
         .await
         .empty_body()
 }
@@ -127,34 +208,156 @@ async fn send_auth_code_to_email(
 ///   "birthday": null,
 ///   "create_time": "2020-02-23T13:23:57.305393",
 ///   "update_time": "2020-02-23T13:23:57.305393",
-///   "max_role": null
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
 /// }
 /// ```
 async fn register_with_phone(
     reg_param: Json<RegisterParams>,
+    client_ip: ClientIp,
     user_svc: web::Data<UserService>,
 ) -> Result<Json<UserInfo>, Error> {
     let reg_param = reg_param.into_inner();
 
     let username = Username::new(&reg_param.username)?;
+    let nickname = Nickname::new(&reg_param.nickname)?;
     let phone = Phone::new(&reg_param.phone)?;
     let auth_code = AuthCode::new(&reg_param.auth_code)?;
 
     if !user_svc
-        .check_auth_code(AuthType::Phone, &phone, &auth_code)
+        .check_auth_code(AuthType::Phone, &phone, &auth_code, &client_ip)
+        .await?
+    {
+        return Err(Kind::INVALID_AUTH_CODE.into());
+    }
+
+    user_svc
+        .create_user_with_phone(&username, &nickname, &phone)
+        .await
+        .json()
+}
+
+/// 使用电子邮箱注册
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/registerWithEmail
+/// Content-Type: application/json
+///
+/// {"username": "gengteng", "nickname": "GT", "email": "me@gteng.org", "auth_code": "165908"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 197
+/// content-type: application/json
+/// date: Sun, 08 Mar 2020 03:12:31 GMT
+///
+/// {
+///   "id": 6,
+///   "username": "gengteng",
+///   "nickname": "GT",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-03-08T03:12:31.102934",
+///   "update_time": "2020-03-08T03:12:31.102934",
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn register_with_email(
+    reg_param: Json<EmailRegisterParams>,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<UserInfo>, Error> {
+    let reg_param = reg_param.into_inner();
+
+    let username = Username::new(&reg_param.username)?;
+    let nickname = Nickname::new(&reg_param.nickname)?;
+    let email = Email::new(&reg_param.email)?;
+    let auth_code = AuthCode::new(&reg_param.auth_code)?;
+
+    if !user_svc
+        .check_auth_code(AuthType::Email, &email, &auth_code, &client_ip)
         .await?
     {
         return Err(Kind::INVALID_AUTH_CODE.into());
     }
 
     user_svc
-        .create_user_with_phone(&username, &reg_param.nickname, &phone)
+        .create_user_with_email(&username, &nickname, &email)
         .await
         .json()
 }
 
+/// 为当前用户绑定电子邮箱，需先调用 `/user/emailAuthCode` 获取发往该邮箱的验证码
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/bindEmail
+/// Content-Type: application/json
+///
+/// {"email": "me@gteng.org", "auth_code": "165908"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 08 Mar 2020 03:15:47 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn bind_email(
+    bind_params: Json<BindEmailParams>,
+    user: User,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    let email = Email::new(&bind_params.email)?;
+    let auth_code = AuthCode::new(&bind_params.auth_code)?;
+
+    if !user_svc
+        .check_auth_code(AuthType::Email, &email, &auth_code, &client_ip)
+        .await?
+    {
+        return Err(Kind::INVALID_AUTH_CODE.into());
+    }
+
+    user_svc
+        .bind_email(user_id, &email, &client_ip)
+        .await
+        .empty_body()
+}
+
 /// 登录
 ///
+/// 各登录方式的参数含义见 `SignInParams`
+///
+/// 使用密码登录时，同一账号连续失败 5 次后会被临时锁定并返回错误码 21，此后每多失败一次锁定时长翻倍；
+/// 同一 IP 失败次数过多时返回错误码 18。两种情况都会在 `Retry-After` 响应头中给出剩余的锁定秒数，
+/// 管理员可通过 `DELETE /user/{id}/lock` 提前解锁。
+///
+/// 使用 `Ldap` 方式登录时由目录服务校验密码，失败记录及锁定规则与密码登录相同；目录用户首次登录时自动创建并关联用户，
+/// 每次登录后按所属分组同步用户的角色。未配置目录服务时返回错误码 28。
+///
+/// 所有登录方式在验证通过后都会检查用户状态：用户已被禁用时返回错误码 35，尚未启用时返回错误码 36，
+/// 已被删除的用户视为不存在。
+///
+/// `remember_me` 为 `true` 时会话使用配置的 `remember_me_ttl` 有效期并设置持久 Cookie，
+/// 否则会话受空闲超时及绝对超时限制，Cookie 在浏览器关闭后失效。
+///
 /// ## Example
 ///
 /// HTTP 请求:
@@ -165,12 +368,32 @@ async fn register_with_phone(
 /// {"identity": "+8615120049138","auth_type": "Phone","credential1": "490604"}
 /// ```
 ///
+/// 启用了两步验证的用户，第一步验证通过后不会立即登录，而是返回临时令牌，
+/// 须在 5 分钟内调用 `/user/signIn/totp` 完成登录:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 47
+/// content-type: application/json
+///
+/// {
+///   "totp_token": "bM3sW0yq5VfYc8xRzK2nE7uTgH4aJ1dL"
+/// }
+/// ```
+///
+/// 使用电子邮箱及验证码登录:
+/// ```
+/// POST /user/signIn
+/// Content-Type: application/json
+///
+/// {"identity": "me@gteng.org","auth_type": "Email","credential1": "","credential2": "490604","remember_me": true}
+/// ```
+///
 /// HTTP 响应:
 /// ```
 /// HTTP/1.1 200 OK
 /// content-length: 197
 /// content-type: application/json
-/// set-cookie: identity=XtltrLDnewNbRrCBFYd5ZVDdeu3+kNwf/L28W28tsdY=D6lDb0WskxWKSHUczlNJHo3aUi4QEy8n; HttpOnly; Max-Age=86400
+/// set-cookie: identity=XtltrLDnewNbRrCBFYd5ZVDdeu3+kNwf/L28W28tsdY=D6lDb0WskxWKSHUczlNJHo3aUi4QEy8n; HttpOnly; Max-Age=2592000
 /// date: Sun, 23 Feb 2020 13:27:25 GMT
 ///
 /// {
@@ -182,15 +405,21 @@ async fn register_with_phone(
 ///   "birthday": null,
 ///   "create_time": "2020-02-23T13:23:57.305393",
 ///   "update_time": "2020-02-23T13:23:57.305393",
-///   "max_role": null
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
 /// }
 /// ```
 ///
 async fn sign_in(
     sign_in_params: Json<SignInParams>,
     user: User,
+    client_ip: ClientIp,
+    session: web::Data<SessionOpts>,
     user_svc: web::Data<UserService>,
-) -> Result<Json<UserInfo>, Error> {
+    ldap_svc: web::Data<LdapService>,
+    totp_svc: web::Data<TotpService>,
+) -> Result<Json<SignInResult>, Error> {
     let sign_in_params = sign_in_params.into_inner();
 
     let user_info = match sign_in_params.auth_type {
@@ -198,23 +427,288 @@ async fn sign_in(
             let username = Username::new(&sign_in_params.identity)?;
 
             user_svc
-                .sign_in_with_username(&username, &sign_in_params.credential1)
+                .sign_in_with_username(&username, &sign_in_params.credential1, &client_ip)
                 .await?
         }
         AuthType::Phone => {
             let phone = Phone::new(&sign_in_params.identity)?;
             let auth_code = AuthCode::new(&sign_in_params.credential1)?;
 
-            user_svc.sign_in_with_phone(&phone, &auth_code).await?
+            user_svc
+                .sign_in_with_phone(&phone, &auth_code, &client_ip)
+                .await?
+        }
+        AuthType::Ldap => {
+            ldap_svc
+                .sign_in(
+                    &sign_in_params.identity,
+                    &sign_in_params.credential1,
+                    &client_ip,
+                )
+                .await?
+        }
+        // 外部身份须通过 `/user/signIn/oidc` 登录
+        AuthType::Oidc => return Err(Kind::INVALID_PARAMS.into()),
+        AuthType::Email => {
+            let email = Email::new(&sign_in_params.identity)?;
+
+            if let Some(credential2) = &sign_in_params.credential2 {
+                let auth_code = AuthCode::new(credential2)?;
+
+                user_svc
+                    .sign_in_with_email_code(&email, &auth_code, &client_ip)
+                    .await?
+            } else {
+                user_svc
+                    .sign_in_with_email(&email, &sign_in_params.credential1, &client_ip)
+                    .await?
+            }
         }
-        AuthType::Email => unimplemented!(),
     };
 
-    user.sign_in(user_info.id)?;
+    if totp_svc.is_enabled(user_info.id).await? {
+        let totp_token = totp_svc.create_pending_sign_in(user_info.id).await?;
+        return Ok(Json(SignInResult::TotpRequired { totp_token }));
+    }
+
+    if sign_in_params.remember_me {
+        user.sign_in_ttl(user_info.id, session.remember_me_ttl())?;
+    } else {
+        user.sign_in(user_info.id)?;
+    }
+
+    Ok(Json(SignInResult::SignedIn(user_info)))
+}
+
+/// 登录的第二步：使用 `/user/signIn` 返回的 `totp_token` 及验证器应用生成的一次性密码（或恢复码）完成登录
+///
+/// 一次性密码错误时返回错误码 23，`totp_token` 已过期或错误次数过多时返回错误码 24，须重新登录；
+/// 一次性密码错误与密码错误一样计入账号及客户端 IP 的失败次数，账号被锁定时返回错误码 21，
+/// 客户端 IP 被锁定或请求过于频繁时返回错误码 18；用户状态的检查同 `/user/signIn`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/signIn/totp
+/// Content-Type: application/json
+///
+/// {"totp_token": "bM3sW0yq5VfYc8xRzK2nE7uTgH4aJ1dL", "code": "287082"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 197
+/// content-type: application/json
+/// set-cookie: identity=XtltrLDnewNbRrCBFYd5ZVDdeu3+kNwf/L28W28tsdY=D6lDb0WskxWKSHUczlNJHo3aUi4QEy8n; HttpOnly
+/// date: Sun, 08 Mar 2020 10:21:44 GMT
+///
+/// {
+///   "id": 5,
+///   "username": "gengteng",
+///   "nickname": "GT",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-02-23T13:23:57.305393",
+///   "update_time": "2020-02-23T13:23:57.305393",
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn sign_in_with_totp(
+    params: Json<TotpSignInParams>,
+    client_ip: ClientIp,
+    user: User,
+    session: web::Data<SessionOpts>,
+    user_svc: web::Data<UserService>,
+    totp_svc: web::Data<TotpService>,
+) -> Result<Json<UserInfo>, Error> {
+    let user_id = totp_svc
+        .complete_pending_sign_in(&params.totp_token, &params.code, &client_ip)
+        .await?;
+
+    // 两步之间用户可能已被禁用
+    let user_info = user_svc.query_active_user(user_id).await?;
+
+    if params.remember_me {
+        user.sign_in_ttl(user_id, session.remember_me_ttl())?;
+    } else {
+        user.sign_in(user_id)?;
+    }
 
     Ok(Json(user_info))
 }
 
+/// 列出已配置的 OpenID Connect 身份提供方
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/oidc
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 19
+/// content-type: application/json
+/// date: Sat, 28 Mar 2020 08:12:40 GMT
+///
+/// ["github","keycloak"]
+/// ```
+async fn list_oidc_providers(oidc_svc: web::Data<OidcService>) -> Json<Vec<String>> {
+    Json(oidc_svc.list_providers())
+}
+
+/// 发起 OpenID Connect 授权，前端须跳转到返回的 `authorization_url`
+///
+/// 身份提供方不存在时返回错误码 10。授权请求 10 分钟内有效，且只能使用一次。
+/// 查询参数 `link=true` 表示为当前登录用户关联外部身份，此时须已登录。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/oidc/keycloak/authorize
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 418
+/// content-type: application/json
+/// date: Sat, 28 Mar 2020 08:13:02 GMT
+///
+/// {
+///   "authorization_url": "https://sso.gteng.org/realms/admino/protocol/openid-connect/auth?response_type=code&client_id=admino&redirect_uri=https%3A%2F%2Fadmino%2Egteng%2Eorg%2Foidc%2Fcallback&scope=openid%20profile%20email&state=Ffq9Ckp0c3JwQW1RUzE5Y2dLbmZUYlhs&nonce=VHpvdU1Oa1hDd2ZJZ0ZGRlE0S0NxV0l3&code_challenge=W8Cn8RZ0Ah4vN3xqY1y6u9yqz9N7K2Vt0H5b0rQ6bQk&code_challenge_method=S256",
+///   "state": "Ffq9Ckp0c3JwQW1RUzE5Y2dLbmZUYlhs"
+/// }
+/// ```
+async fn authorize_oidc(
+    provider: Path<String>,
+    params: Query<OidcAuthorizeParams>,
+    user: User,
+    oidc_svc: web::Data<OidcService>,
+) -> Result<Json<OidcAuthorization>, Error> {
+    let user_id = if params.link {
+        Some(user.get_session()?)
+    } else {
+        None
+    };
+
+    oidc_svc.authorize(&provider, user_id).await.json()
+}
+
+/// 使用 OpenID Connect 外部身份登录，`code` 和 `state` 为身份提供方回调地址中的参数
+///
+/// 外部身份首次登录时，如果身份提供方配置了 `auto_create` 则自动创建用户，否则返回错误码 30；
+/// `state` 无效、已过期或 ID Token 校验失败时同样返回错误码 30。
+/// 启用了两步验证的用户与 `/user/signIn` 一样返回 `totp_token`。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/signIn/oidc
+/// Content-Type: application/json
+///
+/// {"provider": "keycloak", "code": "6c1f0b2e-7a0d-4d1b-9a4e-3f1c2d5e8b7a", "state": "Ffq9Ckp0c3JwQW1RUzE5Y2dLbmZUYlhs"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 203
+/// content-type: application/json
+/// set-cookie: identity=XtltrLDnewNbRrCBFYd5ZVDdeu3+kNwf/L28W28tsdY=D6lDb0WskxWKSHUczlNJHo3aUi4QEy8n; HttpOnly
+/// date: Sat, 28 Mar 2020 08:13:41 GMT
+///
+/// {
+///   "id": 12,
+///   "username": "gengteng",
+///   "nickname": "Geng Teng",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-03-28T08:13:41.102593",
+///   "update_time": "2020-03-28T08:13:41.102593",
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn sign_in_with_oidc(
+    params: Json<OidcSignInParams>,
+    user: User,
+    session: web::Data<SessionOpts>,
+    oidc_svc: web::Data<OidcService>,
+    totp_svc: web::Data<TotpService>,
+) -> Result<Json<SignInResult>, Error> {
+    let user_info = oidc_svc
+        .sign_in(&params.provider, &params.code, &params.state)
+        .await?;
+
+    if totp_svc.is_enabled(user_info.id).await? {
+        let totp_token = totp_svc.create_pending_sign_in(user_info.id).await?;
+        return Ok(Json(SignInResult::TotpRequired { totp_token }));
+    }
+
+    if params.remember_me {
+        user.sign_in_ttl(user_info.id, session.remember_me_ttl())?;
+    } else {
+        user.sign_in(user_info.id)?;
+    }
+
+    Ok(Json(SignInResult::SignedIn(user_info)))
+}
+
+/// 为当前登录用户关联 OpenID Connect 外部身份，授权须通过 `/user/oidc/{provider}/authorize?link=true` 发起
+///
+/// 外部身份已关联其他用户，或当前用户已关联过外部身份时返回错误码 9
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/oidc/link
+/// Content-Type: application/json
+///
+/// {"provider": "keycloak", "code": "0e9d7c1a-2b3f-4c5d-8e6f-7a8b9c0d1e2f", "state": "Yk5aR0ZxT2dVcHlNd3BzTFd4cTJ6RWlB"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sat, 28 Mar 2020 08:20:17 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn link_oidc(
+    params: Json<OidcLinkParams>,
+    user: User,
+    client_ip: ClientIp,
+    oidc_svc: web::Data<OidcService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    oidc_svc
+        .link(
+            user_id,
+            &params.provider,
+            &params.code,
+            &params.state,
+            &client_ip,
+        )
+        .await
+        .empty_body()
+}
+
 /// 登出
 ///
 /// ## Example
@@ -269,7 +763,9 @@ async fn sign_out(user: User) -> Result<&'static str, Error> {
 ///   "birthday": null,
 ///   "create_time": "2020-02-23T13:23:57.305393",
 ///   "update_time": "2020-02-23T13:23:57.305393",
-///   "max_role": null
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
 /// }
 /// ```
 async fn get_user_info(
@@ -283,42 +779,302 @@ async fn get_user_info(
     }
 }
 
-/// 为当前用户新增登录密码
+/// 修改当前用户的个人信息，返回修改后的用户
+///
+/// 请求中的 `update_time` 须为读取个人信息时得到的值，个人信息在此之后被修改过（如在其他页面中）时返回错误码 33，
+/// 此时应重新读取个人信息后再修改。昵称须为 1 到 32 个字符，否则返回错误码 31；出生日期晚于今天时返回错误码 32。
+/// 头像不能在此修改，须使用 `POST /user/avatar` 上传或 `DELETE /user/avatar` 删除。
 ///
 /// ## Example
 ///
 /// HTTP 请求:
 /// ```
-/// POST /user/addPassword
+/// PATCH /user/info
 /// Content-Type: application/json
 ///
-/// {"password": "!23QweAsd"}
+/// {"nickname": "GT", "gender": "Male", "birthday": "1990-01-01", "update_time": "2020-02-23T13:23:57.305393"}
 /// ```
 ///
 /// HTTP 响应:
 /// ```
 /// HTTP/1.1 200 OK
-/// content-length: 0
-/// content-type: text/plain; charset=utf-8
-/// set-cookie: identity=; Max-Age=0; Expires=Sat, 23 Feb 2019 13:30:39 GMT
-/// date: Sun, 23 Feb 2020 13:30:39 GMT
-///
-/// <Response body is empty>
-/// ```
+/// content-length: 198
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 10:05:12 GMT
 ///
-async fn add_password(
-    add_pwd_params: Json<AddPasswordParams>,
-    user: User,
-    user_svc: web::Data<UserService>,
-) -> Result<&'static str, Error> {
-    if let Some(user_id) = user.get() {
-        user_svc
-            .add_password(user_id, &add_pwd_params.password)
+/// {
+///   "id": 5,
+///   "username": "gengteng",
+///   "nickname": "GT",
+///   "avatar": null,
+///   "gender": "Male",
+///   "birthday": "1990-01-01",
+///   "create_time": "2020-02-23T13:23:57.305393",
+///   "update_time": "2020-03-15T10:05:12.810235",
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn update_user_info(
+    profile: Json<ProfileUpdate>,
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<UserInfo>, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc.update_profile(user_id, &profile).await.json()
+}
+
+/// 上传当前用户的头像，请求体为 `multipart/form-data`，图片放在名为 `file` 的字段中
+///
+/// 支持 PNG、JPEG、GIF 及 WebP 格式，格式不支持或图片已损坏时返回错误码 37，
+/// 文件大小或图片尺寸超过配置的上限时返回错误码 38。
+/// 图片会被居中裁剪为正方形，缩放到配置的各个尺寸并重新编码为 PNG，
+/// 用户的头像（`UserInfo::avatar`）修改为其中最大尺寸的地址。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/avatar
+/// Content-Type: multipart/form-data; boundary=------------------------d74496d66958873e
+///
+/// --------------------------d74496d66958873e
+/// Content-Disposition: form-data; name="file"; filename="me.jpg"
+/// Content-Type: image/jpeg
+///
+/// <Binary data>
+/// --------------------------d74496d66958873e--
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 273
+/// content-type: application/json
+/// date: Sun, 29 Mar 2020 03:15:42 GMT
+///
+/// {
+///   "avatar": "/avatars/5/3f2a9c1d7e8b4a60_256.png",
+///   "images": [
+///     {
+///       "size": 256,
+///       "url": "/avatars/5/3f2a9c1d7e8b4a60_256.png"
+///     },
+///     {
+///       "size": 128,
+///       "url": "/avatars/5/3f2a9c1d7e8b4a60_128.png"
+///     },
+///     {
+///       "size": 64,
+///       "url": "/avatars/5/3f2a9c1d7e8b4a60_64.png"
+///     }
+///   ]
+/// }
+/// ```
+async fn upload_avatar(
+    payload: Multipart,
+    user: User,
+    avatar_svc: web::Data<AvatarService>,
+) -> Result<Json<UploadedAvatar>, Error> {
+    let user_id = user.get_session()?;
+
+    let data = read_file_field(payload, "file", avatar_svc.max_size()).await?;
+
+    avatar_svc.upload(user_id, data).await.json()
+}
+
+/// 读取 `multipart/form-data` 请求体中名为 `name` 的字段，字段不存在时返回 `INVALID_PARAMS`，
+/// 超过 `max_size` 字节时返回 `IMAGE_TOO_LARGE`
+async fn read_file_field(
+    mut payload: Multipart,
+    name: &str,
+    max_size: usize,
+) -> Result<Vec<u8>, Error> {
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|_| Error::from(Kind::INVALID_PARAMS))?
+    {
+        let is_file = field
+            .content_disposition()
+            .is_some_and(|cd| cd.get_name() == Some(name));
+
+        if !is_file {
+            // 跳过其他字段
+            while field.next().await.is_some() {}
+            continue;
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
             .await
-            .empty_body()
-    } else {
-        Err(Kind::USER_NOT_SIGNED_IN.into())
+            .map_err(|_| Error::from(Kind::INVALID_PARAMS))?
+        {
+            if data.len() + chunk.len() > max_size {
+                return Err(Kind::IMAGE_TOO_LARGE.into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        return Ok(data);
     }
+
+    Err(Kind::INVALID_PARAMS.into())
+}
+
+/// 删除当前用户的头像
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/avatar
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 29 Mar 2020 03:18:06 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn delete_avatar(
+    user: User,
+    avatar_svc: web::Data<AvatarService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    avatar_svc.delete(user_id).await.empty_body()
+}
+
+/// 为当前用户新增登录密码，密码须符合配置的密码策略，否则返回错误码 4
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/addPassword
+/// Content-Type: application/json
+///
+/// {"password": "!23QweAsd"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// set-cookie: identity=; Max-Age=0; Expires=Sat, 23 Feb 2019 13:30:39 GMT
+/// date: Sun, 23 Feb 2020 13:30:39 GMT
+///
+/// <Response body is empty>
+/// ```
+///
+async fn add_password(
+    add_pwd_params: Json<AddPasswordParams>,
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .add_password(user_id, &add_pwd_params.password)
+        .await
+        .empty_body()
+}
+
+/// 使用原密码修改当前用户的登录密码，修改成功后所有会话（包括当前会话）均被注销，须重新登录
+///
+/// 原密码错误时返回错误码 22，并计入账号的登录失败次数
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/changePassword
+/// Content-Type: application/json
+///
+/// {"old_password": "!23QweAsd", "new_password": "Zxc#456Rty"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// set-cookie: identity=; Max-Age=0; Expires=Sat, 09 Mar 2019 09:02:11 GMT
+/// date: Sun, 08 Mar 2020 09:02:11 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn change_password(
+    params: Json<ChangePasswordParams>,
+    user: User,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .change_password(
+            user_id,
+            &params.old_password,
+            &params.new_password,
+            &client_ip,
+        )
+        .await?;
+
+    user.sign_out();
+
+    Ok("")
+}
+
+/// 忘记密码时，使用发往已绑定手机号/电子邮箱的验证码重置登录密码
+///
+/// 需先调用 `/user/phoneAuthCode` 或 `/user/emailAuthCode` 获取验证码，重置后账号的登录锁定同时解除，
+/// 该账号的所有会话均被注销
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/resetPassword
+/// Content-Type: application/json
+///
+/// {"auth_type": "Phone", "identity": "+8615120049138", "auth_code": "490604", "password": "Zxc#456Rty"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 08 Mar 2020 09:05:37 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn reset_password(
+    params: Json<ResetPasswordParams>,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let auth_code = AuthCode::new(&params.auth_code)?;
+
+    user_svc
+        .reset_password(
+            params.auth_type,
+            &params.identity,
+            &auth_code,
+            &params.password,
+            &client_ip,
+        )
+        .await
+        .empty_body()
 }
 
 /// 获取当前用户的所有角色
@@ -402,16 +1158,740 @@ async fn get_user_auth(
     }
 }
 
-/// 获取用户所有权限
+/// 为当前用户绑定或更换手机号/电子邮箱，`{auth_type}` 为 `Phone` 或 `Email`
 ///
-/// TODO: 应该返回 `Vec<Permission>`，待修改
-async fn get_user_perm(
+/// 需先调用 `/user/phoneAuthCode` 或 `/user/emailAuthCode` 获取发往新手机号/电子邮箱的验证码 `auth_code`，
+/// 并重新认证：填写当前密码 `password`，或者填写发往已绑定手机号/电子邮箱（`reauth_type`）的验证码 `reauth_code`。
+///
+/// 密码错误返回错误码 22，验证码错误返回错误码 8，新手机号/电子邮箱已被其他用户使用时返回错误码 9。
+/// 变更会记录在 `GET /user/authentications/history` 中。
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// PUT /user/authentications/Phone
+/// Content-Type: application/json
+///
+/// {"identity": "+8615120049139", "auth_code": "165908", "password": "!23QweAsd"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 22 Mar 2020 06:12:40 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn bind_identity(
+    auth_type: Path<AuthType>,
+    params: Json<BindIdentityParams>,
+    user: User,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .bind_identity(user_id, auth_type.into_inner(), &params, &client_ip)
+        .await
+        .empty_body()
+}
+
+/// 解绑当前用户的手机号、电子邮箱或外部身份，`{auth_type}` 为 `Phone`、`Email` 或 `Oidc`
+///
+/// 重新认证的参数及错误码同 `PUT /user/authentications/{auth_type}`；
+/// 未绑定该登录方式时返回错误码 10，解绑后没有其他登录方式时返回错误码 34。
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/authentications/Email
+/// Content-Type: application/json
+///
+/// {"reauth_type": "Phone", "reauth_code": "165908"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 22 Mar 2020 06:15:02 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn unbind_identity(
+    auth_type: Path<AuthType>,
+    params: Json<ReauthParams>,
+    user: User,
+    client_ip: ClientIp,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .unbind_identity(user_id, auth_type.into_inner(), &params, &client_ip)
+        .await
+        .empty_body()
+}
+
+/// 获取当前用户登录方式的变更记录，按时间倒序排列
+///
+/// `old_identity` 为空表示绑定，`new_identity` 为空表示解绑
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/authentications/history
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 176
+/// content-type: application/json
+/// date: Sun, 22 Mar 2020 06:16:31 GMT
+///
+/// [
+///   {
+///     "id": 3,
+///     "user_id": 5,
+///     "auth_type": "Phone",
+///     "old_identity": "+8615120049138",
+///     "new_identity": "+8615120049139",
+///     "client_ip": "192.168.1.20",
+///     "create_time": "2020-03-22T06:12:40.183027"
+///   }
+/// ]
+/// ```
+async fn get_user_auth_history(
     user: User,
     user_svc: web::Data<UserService>,
-) -> Result<Json<Vec<RolePermission>>, Error> {
+) -> Result<Json<Vec<UserAuthHistory>>, Error> {
     if let Some(user_id) = user.get() {
-        user_svc.query_user_perm(user_id).await.json()
+        user_svc.query_auth_history(user_id).await.json()
     } else {
         Err(Kind::USER_NOT_SIGNED_IN.into())
     }
 }
+
+/// 获取当前用户的所有权限
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/permissions
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 83
+/// content-type: application/json
+/// date: Sun, 23 Feb 2020 13:50:12 GMT
+///
+/// [
+///   {
+///     "id": 1,
+///     "permission_name": "role:read"
+///   },
+///   {
+///     "id": 2,
+///     "permission_name": "role:write"
+///   }
+/// ]
+/// ```
+async fn get_user_perm(
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<Vec<Permission>>, Error> {
+    if let Some(user_id) = user.get() {
+        user_svc.query_user_perm(user_id).await.json()
+    } else {
+        Err(Kind::USER_NOT_SIGNED_IN.into())
+    }
+}
+
+/// 为指定用户授予角色（需要 `user:write` 权限）
+///
+/// 如果违反角色互斥约束，返回错误码 12；如果缺少先决条件角色，返回错误码 13；
+/// 如果超过用户的最大角色数，返回错误码 15；如果超过角色的最大用户数，返回错误码 16
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/5/roles/7
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 09:40:52 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn grant_role(
+    path: Path<(Id, Id)>,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let (user_id, role_id) = path.into_inner();
+    user_svc.grant_role(user_id, role_id).await.empty_body()
+}
+
+/// 收回指定用户的角色（需要 `user:write` 权限）
+///
+/// 如果该角色是用户其他角色的先决条件，默认返回错误码 14，
+/// 查询字符串中带上 `cascade=true` 时一并收回这些角色
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/5/roles/7?cascade=true
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 10:02:13 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_role(
+    path: Path<(Id, Id)>,
+    params: Query<RevokeRoleParams>,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let (user_id, role_id) = path.into_inner();
+    user_svc
+        .revoke_role(user_id, role_id, params.cascade)
+        .await
+        .empty_body()
+}
+
+/// 查询指定用户的所有角色（需要 `user:read` 权限）
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/5/roles
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 65
+/// content-type: application/json
+/// date: Sun, 01 Mar 2020 11:02:48 GMT
+///
+/// [
+///   {
+///     "id": 7,
+///     "name": "出纳",
+///     "max_user": null,
+///     "max_permission": null
+///   }
+/// ]
+/// ```
+async fn list_user_roles(
+    id: Path<Id>,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<Vec<Role>>, Error> {
+    user_svc.query_user_roles(id.into_inner()).await.json()
+}
+
+/// 为指定用户批量授予角色（需要 `user:write` 权限），任一角色授予失败则全部不生效
+///
+/// 错误码同 `POST /user/{id}/roles/{role_id}`，先决条件角色须排在依赖它的角色之前
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/5/roles
+/// Content-Type: application/json
+///
+/// {"role_ids": [9, 10]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 11:05:31 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn grant_roles(
+    id: Path<Id>,
+    params: Json<RoleIdsParams>,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    user_svc
+        .grant_roles(id.into_inner(), &params.role_ids)
+        .await
+        .empty_body()
+}
+
+/// 批量收回指定用户的角色（需要 `user:write` 权限），用户未拥有的角色会被忽略
+///
+/// `cascade` 的含义同 `DELETE /user/{id}/roles/{role_id}`
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/5/roles?cascade=true
+/// Content-Type: application/json
+///
+/// {"role_ids": [9, 10]}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 01 Mar 2020 11:07:02 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_roles(
+    id: Path<Id>,
+    params: Json<RoleIdsParams>,
+    query: Query<RevokeRoleParams>,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    user_svc
+        .revoke_roles(id.into_inner(), &params.role_ids, query.cascade)
+        .await
+        .empty_body()
+}
+
+/// 查询指定用户的密码登录失败记录及锁定状态（需要 `user:read` 权限）
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/5/lock
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 130
+/// content-type: application/json
+/// date: Sun, 08 Mar 2020 08:12:40 GMT
+///
+/// {
+///   "failure_count": 5,
+///   "last_failure_time": "2020-03-08T08:11:02",
+///   "last_failure_ip": "192.168.1.20",
+///   "locked_until": "2020-03-08T08:12:02"
+/// }
+/// ```
+async fn get_login_failure(
+    path: Path<Id>,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<LoginFailure>, Error> {
+    user_svc.query_login_failure(path.into_inner()).await.json()
+}
+
+/// 解除指定用户的登录锁定并清空失败记录（需要 `user:write` 权限）
+///
+/// # Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/5/lock
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 08 Mar 2020 08:13:05 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn unlock_user(
+    path: Path<Id>,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    user_svc.unlock_user(path.into_inner()).await.empty_body()
+}
+
+/// 为当前用户开始启用两步验证，生成待确认的密钥
+///
+/// 任何登录方式的用户均可启用（包括只使用 LDAP、OpenID Connect 登录、没有设置登录密码的用户），已启用时返回错误码 9。
+/// 将 `otpauth_uri` 生成二维码后使用验证器应用扫描，再调用 `/user/totp/confirm` 确认，密钥 10 分钟内有效。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/totp
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 198
+/// content-type: application/json
+/// date: Sun, 08 Mar 2020 10:12:03 GMT
+///
+/// {
+///   "secret": "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP",
+///   "otpauth_uri": "otpauth://totp/admino:gengteng?secret=JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP&issuer=admino&algorithm=SHA1&digits=6&period=30"
+/// }
+/// ```
+async fn begin_totp_enrollment(
+    user: User,
+    totp_svc: web::Data<TotpService>,
+) -> Result<Json<TotpEnrollment>, Error> {
+    let user_id = user.get_session()?;
+
+    totp_svc.begin_enrollment(user_id).await.json()
+}
+
+/// 使用验证器应用生成的一次性密码确认启用两步验证
+///
+/// 返回的恢复码只显示这一次，每个只能使用一次，可在无法使用验证器应用时代替一次性密码
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/totp/confirm
+/// Content-Type: application/json
+///
+/// {"code": "287082"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 159
+/// content-type: application/json
+/// date: Sun, 08 Mar 2020 10:13:27 GMT
+///
+/// {
+///   "recovery_codes": [
+///     "k3x9q-7pzm2",
+///     "a8rtw-0c4ny",
+///     "..."
+///   ]
+/// }
+/// ```
+async fn confirm_totp_enrollment(
+    params: Json<TotpCodeParams>,
+    user: User,
+    totp_svc: web::Data<TotpService>,
+) -> Result<Json<RecoveryCodes>, Error> {
+    let user_id = user.get_session()?;
+
+    let recovery_codes = totp_svc.confirm_enrollment(user_id, &params.code).await?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// 使用一次性密码或恢复码关闭当前用户的两步验证
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/totp
+/// Content-Type: application/json
+///
+/// {"code": "287082"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 08 Mar 2020 10:30:12 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn disable_totp(
+    params: Json<TotpCodeParams>,
+    user: User,
+    totp_svc: web::Data<TotpService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    totp_svc.disable(user_id, &params.code).await.empty_body()
+}
+
+/// 重置指定用户的两步验证（需要 `user:write` 权限），用于用户丢失验证器及恢复码的情况
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/5/totp
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 08 Mar 2020 10:35:40 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn reset_totp(
+    path: Path<Id>,
+    totp_svc: web::Data<TotpService>,
+) -> Result<&'static str, Error> {
+    totp_svc.reset(path.into_inner()).await.empty_body()
+}
+
+/// 列出当前用户的所有会话，按最近访问时间倒序，`current` 为 `true` 的是发起本次请求的会话
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/sessions
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 471
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:12:45 GMT
+///
+/// [
+///   {
+///     "id": "k3VbQ8wZtR2mXy7p",
+///     "created_at": "2020-03-15T08:01:10",
+///     "last_seen": "2020-03-15T08:12:02",
+///     "expires_at": "2020-03-16T08:01:10",
+///     "ip": "127.0.0.1",
+///     "user_agent": "Mozilla/5.0 (X11; Linux x86_64; rv:74.0) Gecko/20100101 Firefox/74.0",
+///     "current": true
+///   },
+///   {
+///     "id": "Hq2LdN9sVc4aFe1T",
+///     "created_at": "2020-03-14T21:30:44",
+///     "last_seen": "2020-03-14T22:05:17",
+///     "expires_at": "2020-04-13T21:30:44",
+///     "ip": "192.168.1.23",
+///     "user_agent": "curl/7.68.0",
+///     "current": false
+///   }
+/// ]
+/// ```
+async fn list_sessions(
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<Json<Vec<Session>>, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .list_sessions(user_id, user.token().as_deref())
+        .await
+        .json()
+}
+
+/// 注销当前用户的指定会话，会话不存在时返回错误码 10
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/sessions/Hq2LdN9sVc4aFe1T
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 15 Mar 2020 08:13:20 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_session(
+    path: Path<String>,
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc
+        .revoke_session(user_id, &path.into_inner())
+        .await
+        .empty_body()
+}
+
+/// 注销当前用户的所有会话（包括当前会话），即在所有设备上退出登录
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/sessions
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// set-cookie: identity=; Max-Age=0; Expires=Fri, 15 Mar 2019 08:14:02 GMT
+/// date: Sun, 15 Mar 2020 08:14:02 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn revoke_all_sessions(
+    user: User,
+    user_svc: web::Data<UserService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    user_svc.revoke_all_sessions(user_id).await?;
+
+    user.sign_out();
+
+    Ok("")
+}
+
+/// 列出当前用户的所有个人访问令牌，不包括令牌本身
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /user/tokens
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 190
+/// content-type: application/json
+/// date: Sun, 22 Mar 2020 06:40:12 GMT
+///
+/// [
+///   {
+///     "id": 3,
+///     "user_id": 5,
+///     "name": "ci",
+///     "scopes": ["role:read", "role:write"],
+///     "expire_time": "2020-06-30T00:00:00",
+///     "last_used_time": "2020-03-22T06:31:09.208316",
+///     "create_time": "2020-03-22T06:12:45.117723"
+///   }
+/// ]
+/// ```
+async fn list_tokens(
+    user: User,
+    token_svc: web::Data<TokenService>,
+) -> Result<Json<Vec<AccessToken>>, Error> {
+    let user_id = user.get().ok_or(Kind::USER_NOT_SIGNED_IN)?;
+
+    token_svc.list_tokens(user_id).await.json()
+}
+
+/// 创建个人访问令牌，令牌只在本次响应中返回，之后无法再次查看
+///
+/// 使用令牌时须在请求头中加入 `Authorization: Bearer <token>`，只能访问 `scopes` 中列出的权限，
+/// `scopes` 须为当前用户拥有的权限的子集，否则返回错误码 2；`expire_time` 为空表示永不过期。
+/// 只能在登录会话中创建，使用访问令牌调用时返回错误码 27。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /user/tokens
+/// Content-Type: application/json
+///
+/// {"name": "ci", "scopes": ["role:read", "role:write"], "expire_time": "2020-06-30T00:00:00"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 225
+/// content-type: application/json
+/// date: Sun, 22 Mar 2020 06:12:45 GMT
+///
+/// {
+///   "id": 3,
+///   "user_id": 5,
+///   "name": "ci",
+///   "scopes": ["role:read", "role:write"],
+///   "expire_time": "2020-06-30T00:00:00",
+///   "last_used_time": null,
+///   "create_time": "2020-03-22T06:12:45.117723",
+///   "token": "admino_Jq8WmT3vXr0bLs5KdYh2NcPz7FgEa1UoRi4nVe6B"
+/// }
+/// ```
+async fn create_token(
+    params: Json<CreateAccessTokenParams>,
+    user: User,
+    user_svc: web::Data<UserService>,
+    token_svc: web::Data<TokenService>,
+) -> Result<Json<CreatedAccessToken>, Error> {
+    let user_id = user.get_session()?;
+
+    let permissions = user_svc.query_user_perm(user_id).await?;
+
+    token_svc
+        .create_token(user_id, &params, &permissions)
+        .await
+        .json()
+}
+
+/// 删除当前用户的个人访问令牌，删除后立即失效
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /user/tokens/3
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 22 Mar 2020 06:45:30 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn delete_token(
+    path: Path<Id>,
+    user: User,
+    token_svc: web::Data<TokenService>,
+) -> Result<&'static str, Error> {
+    let user_id = user.get_session()?;
+
+    token_svc
+        .delete_token(user_id, path.into_inner())
+        .await
+        .empty_body()
+}
@@ -0,0 +1,349 @@
+//! 用户管理相关控制器
+//!
+//! 供管理员查询、创建、修改及删除其他用户，用户授予角色、解锁等操作见 `/user/{id}/...`
+use super::IntoJsonResult;
+use crate::controller::EmptyBody;
+use crate::error::{Error, Kind};
+use crate::model::{
+    Count, CreateUserParams, Id, UserAuthHistory, UserFilter, UserInfo, UserInfoContent,
+    UserStatusParams,
+};
+use crate::service::user::UserService;
+use crate::util::db::Pager;
+use crate::util::user::User;
+use actix_web::{web, web::Data, web::Json, web::Path, web::Query, Scope};
+
+/// 获取用户管理相关的所有路由
+pub fn get_users_scope() -> Scope {
+    web::scope("/users")
+        .service(web::resource("").route(web::post().to(create_user)))
+        .service(web::resource("/count").route(web::get().to(get_users_count)))
+        .service(web::resource("/list/{page}/{rows}").route(web::get().to(list_users)))
+        .service(
+            web::resource("/{id}")
+                .route(web::get().to(retrieve_user))
+                .route(web::patch().to(update_user))
+                .route(web::delete().to(delete_user)),
+        )
+        .service(web::resource("/{id}/status").route(web::put().to(set_user_status)))
+        .service(web::resource("/{id}/authHistory").route(web::get().to(get_auth_history)))
+}
+
+/// 统计符合筛选条件的用户数，筛选条件见 `GET /users/list/{page}/{rows}`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /users/count?nickname=gt&created_from=2020-02-01
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 11
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:20:13 GMT
+///
+/// {
+///   "count": 1
+/// }
+/// ```
+async fn get_users_count(
+    user_svc: Data<UserService>,
+    filter: Query<UserFilter>,
+) -> Result<Json<Count>, Error> {
+    user_svc.query_users_count(&filter).await.json()
+}
+
+/// 分页查询用户
+///
+/// 查询字符串中可带上以下筛选条件，均可省略：
+///
+/// * `username`、`nickname`、`phone`、`email`: 包含该字符串，其中用户名、昵称及电子邮箱不区分大小写；
+/// * `role_id`: 直接拥有该角色；
+/// * `created_from`、`created_to`: 创建日期的范围（包含两端），格式为 `yyyy-MM-dd`；
+/// * `status`: 用户状态，省略时查询除已删除（`Deleted`）以外的所有用户。
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /users/list/0/10?nickname=gt&role_id=7
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 199
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:21:40 GMT
+///
+/// [
+///   {
+///     "id": 5,
+///     "username": "gengteng",
+///     "nickname": "GT",
+///     "avatar": null,
+///     "gender": "Unknown",
+///     "birthday": null,
+///     "create_time": "2020-02-23T13:23:57.305393",
+///     "update_time": "2020-02-23T13:23:57.305393",
+///     "max_role": null,
+///     "status": "Active",
+///     "status_reason": null
+///   }
+/// ]
+/// ```
+async fn list_users(
+    user_svc: Data<UserService>,
+    pager: Path<Pager>,
+    filter: Query<UserFilter>,
+) -> Result<Json<Vec<UserInfo>>, Error> {
+    user_svc.list_users(&filter, &pager).await.json()
+}
+
+/// 创建用户
+///
+/// `password`、`phone`、`email` 至少填写一项，否则返回错误码 28；`status` 为用户的初始状态，省略时为 `Active`；
+/// 用户名、手机号或电子邮箱已被使用时返回错误码 9；昵称及出生日期的要求同 `PATCH /user/info`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// POST /users
+/// Content-Type: application/json
+///
+/// {"username": "cashier", "nickname": "出纳", "password": "!23QweAsd", "phone": "+8615120049138", "max_role": 3}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 193
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:25:02 GMT
+///
+/// {
+///   "id": 8,
+///   "username": "cashier",
+///   "nickname": "出纳",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-03-15T08:25:02.417281",
+///   "update_time": "2020-03-15T08:25:02.417281",
+///   "max_role": 3,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn create_user(
+    user_svc: Data<UserService>,
+    params: Json<CreateUserParams>,
+) -> Result<Json<UserInfo>, Error> {
+    user_svc.add_user(&params).await.json()
+}
+
+/// 查询用户
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /users/5
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 197
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:26:47 GMT
+///
+/// {
+///   "id": 5,
+///   "username": "gengteng",
+///   "nickname": "GT",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-02-23T13:23:57.305393",
+///   "update_time": "2020-02-23T13:23:57.305393",
+///   "max_role": null,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn retrieve_user(user_svc: Data<UserService>, id: Path<Id>) -> Result<Json<UserInfo>, Error> {
+    user_svc.query_user_by_id(id.into_inner()).await.json()
+}
+
+/// 修改用户信息，返回修改后的用户
+///
+/// `max_role` 小于用户当前拥有的角色数时返回错误码 15，昵称及出生日期的要求同 `PATCH /user/info`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// PATCH /users/5
+/// Content-Type: application/json
+///
+/// {"nickname": "GT", "avatar": null, "gender": "Male", "birthday": "1990-01-01", "max_role": 5}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 198
+/// content-type: application/json
+/// date: Sun, 15 Mar 2020 08:30:19 GMT
+///
+/// {
+///   "id": 5,
+///   "username": "gengteng",
+///   "nickname": "GT",
+///   "avatar": null,
+///   "gender": "Male",
+///   "birthday": "1990-01-01",
+///   "create_time": "2020-02-23T13:23:57.305393",
+///   "update_time": "2020-03-15T08:30:19.052718",
+///   "max_role": 5,
+///   "status": "Active",
+///   "status_reason": null
+/// }
+/// ```
+async fn update_user(
+    user_svc: Data<UserService>,
+    id: Path<Id>,
+    content: Json<UserInfoContent>,
+) -> Result<Json<UserInfo>, Error> {
+    user_svc.update_user(id.into_inner(), &content).await.json()
+}
+
+/// 软删除用户，即将用户状态修改为 `Deleted` 并注销其所有会话
+///
+/// 用户的登录方式、角色等数据均保留，可通过 `PUT /users/{id}/status` 恢复；
+/// 不能删除当前登录的用户自己，否则返回错误码 28
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// DELETE /users/8
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 0
+/// content-type: text/plain; charset=utf-8
+/// date: Sun, 15 Mar 2020 08:32:54 GMT
+///
+/// <Response body is empty>
+/// ```
+async fn delete_user(
+    user: User,
+    user_svc: Data<UserService>,
+    id: Path<Id>,
+) -> Result<&'static str, Error> {
+    let id = id.into_inner();
+
+    if user.get::<Id>() == Some(id) {
+        return Err(Kind::INVALID_PARAMS.into());
+    }
+
+    user_svc.delete_user(id).await.empty_body()
+}
+
+/// 修改用户状态，返回修改后的用户
+///
+/// `status` 可以为 `Active`、`Disabled`、`Pending`、`Deleted`，只有 `Active` 的用户可以登录，
+/// 修改为其他状态时注销用户的所有会话，其个人访问令牌也随之失效；`reason` 为变更原因，可省略，最长 255 个字符。
+/// 不能修改当前登录的用户自己的状态，否则返回错误码 28
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// PUT /users/8/status
+/// Content-Type: application/json
+///
+/// {"status": "Disabled", "reason": "已离职"}
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 245
+/// content-type: application/json
+/// date: Sun, 22 Mar 2020 09:12:36 GMT
+///
+/// {
+///   "id": 8,
+///   "username": "cashier",
+///   "nickname": "出纳",
+///   "avatar": null,
+///   "gender": "Unknown",
+///   "birthday": null,
+///   "create_time": "2020-03-15T08:25:02.417281",
+///   "update_time": "2020-03-22T09:12:36.582164",
+///   "max_role": 3,
+///   "status": "Disabled",
+///   "status_reason": "已离职"
+/// }
+/// ```
+async fn set_user_status(
+    user: User,
+    user_svc: Data<UserService>,
+    id: Path<Id>,
+    params: Json<UserStatusParams>,
+) -> Result<Json<UserInfo>, Error> {
+    let id = id.into_inner();
+
+    if user.get::<Id>() == Some(id) {
+        return Err(Kind::INVALID_PARAMS.into());
+    }
+
+    user_svc
+        .set_user_status(id, params.status, params.reason.as_deref())
+        .await
+        .json()
+}
+
+/// 查询用户登录方式的变更记录，格式同 `GET /user/authentications/history`
+///
+/// ## Example
+///
+/// HTTP 请求:
+/// ```
+/// GET /users/5/authHistory
+/// ```
+///
+/// HTTP 响应:
+/// ```
+/// HTTP/1.1 200 OK
+/// content-length: 176
+/// content-type: application/json
+/// date: Sun, 22 Mar 2020 06:20:05 GMT
+///
+/// [
+///   {
+///     "id": 3,
+///     "user_id": 5,
+///     "auth_type": "Phone",
+///     "old_identity": "+8615120049138",
+///     "new_identity": "+8615120049139",
+///     "client_ip": "192.168.1.20",
+///     "create_time": "2020-03-22T06:12:40.183027"
+///   }
+/// ]
+/// ```
+async fn get_auth_history(
+    user_svc: Data<UserService>,
+    id: Path<Id>,
+) -> Result<Json<Vec<UserAuthHistory>>, Error> {
+    user_svc.query_auth_history(id.into_inner()).await.json()
+}
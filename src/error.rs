@@ -14,6 +14,7 @@ pub type Exception = Box<dyn StdError + Sync + Send + 'static>;
 pub struct Error {
     kind: &'static Kind,
     detail: Option<Exception>,
+    retry_after: Option<u64>,
 }
 
 impl Error {
@@ -27,9 +28,16 @@ impl Error {
         Self {
             kind,
             detail: Some(Box::new(error)),
+            retry_after: None,
         }
     }
 
+    /// 设置客户端需等待多少秒后才能重试，会在 HTTP 响应中添加 `Retry-After` 头
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
     /// 获取静态错误信息
     pub fn kind(&self) -> &'static Kind {
         self.kind
@@ -39,6 +47,11 @@ impl Error {
     pub fn detail(&self) -> Option<&Exception> {
         self.detail.as_ref()
     }
+
+    /// 获取客户端需等待的秒数
+    pub fn retry_after(&self) -> Option<u64> {
+        self.retry_after
+    }
 }
 
 impl From<tokio_postgres::Error> for Error {
@@ -99,7 +112,11 @@ pub struct Kind {
 
 impl From<&'static Kind> for Error {
     fn from(kind: &'static Kind) -> Self {
-        Self { kind, detail: None }
+        Self {
+            kind,
+            detail: None,
+            retry_after: None,
+        }
     }
 }
 
@@ -116,6 +133,7 @@ impl Kind {
         Error {
             kind: self,
             detail: Some(Box::new(error)),
+            retry_after: None,
         }
     }
 
@@ -175,6 +193,95 @@ impl Kind {
     /// 请求的资源不存在(10)
     pub const EMPTY_RESULT: &'static Kind =
         &Kind::new(10, "请求的资源不存在", StatusCode::NOT_FOUND);
+    /// 角色继承关系成环(11)
+    pub const ROLE_INHERITANCE_CYCLE: &'static Kind =
+        &Kind::new(11, "角色继承关系成环", StatusCode::BAD_REQUEST);
+    /// 违反角色互斥约束(12)
+    pub const ROLE_MUTEX_CONFLICT: &'static Kind =
+        &Kind::new(12, "违反角色互斥约束", StatusCode::BAD_REQUEST);
+    /// 缺少先决条件角色(13)
+    pub const BASE_ROLE_REQUIRED: &'static Kind =
+        &Kind::new(13, "缺少先决条件角色", StatusCode::BAD_REQUEST);
+    /// 角色是用户其他角色的先决条件(14)
+    pub const BASE_ROLE_IN_USE: &'static Kind =
+        &Kind::new(14, "角色是用户其他角色的先决条件", StatusCode::BAD_REQUEST);
+    /// 用户角色数已达上限(15)
+    pub const USER_ROLE_LIMIT: &'static Kind =
+        &Kind::new(15, "用户角色数已达上限", StatusCode::BAD_REQUEST);
+    /// 角色用户数已达上限(16)
+    pub const ROLE_USER_LIMIT: &'static Kind =
+        &Kind::new(16, "角色用户数已达上限", StatusCode::BAD_REQUEST);
+    /// 角色权限数已达上限(17)
+    pub const ROLE_PERMISSION_LIMIT: &'static Kind =
+        &Kind::new(17, "角色权限数已达上限", StatusCode::BAD_REQUEST);
+    /// 请求过于频繁(18)
+    pub const TOO_MANY_REQUESTS: &'static Kind =
+        &Kind::new(18, "请求过于频繁", StatusCode::TOO_MANY_REQUESTS);
+    /// 验证码发送过于频繁(19)
+    pub const AUTH_CODE_COOLDOWN: &'static Kind =
+        &Kind::new(19, "验证码发送过于频繁", StatusCode::TOO_MANY_REQUESTS);
+    /// 验证码错误次数过多，验证码已失效(20)
+    pub const AUTH_CODE_ATTEMPTS_EXCEEDED: &'static Kind = &Kind::new(
+        20,
+        "验证码错误次数过多，验证码已失效",
+        StatusCode::BAD_REQUEST,
+    );
+    /// 登录失败次数过多，账号已被临时锁定(21)
+    pub const ACCOUNT_LOCKED: &'static Kind = &Kind::new(
+        21,
+        "登录失败次数过多，账号已被临时锁定",
+        StatusCode::FORBIDDEN,
+    );
+    /// 原密码错误(22)
+    pub const WRONG_PASSWORD: &'static Kind = &Kind::new(22, "原密码错误", StatusCode::BAD_REQUEST);
+    /// 两步验证码错误(23)
+    pub const INVALID_TOTP_CODE: &'static Kind =
+        &Kind::new(23, "两步验证码错误", StatusCode::BAD_REQUEST);
+    /// 两步验证已过期，请重新登录(24)
+    pub const TOTP_SIGN_IN_EXPIRED: &'static Kind =
+        &Kind::new(24, "两步验证已过期，请重新登录", StatusCode::UNAUTHORIZED);
+    /// 拥有管理权限的用户须先启用两步验证(25)
+    pub const TOTP_NOT_ENABLED: &'static Kind = &Kind::new(
+        25,
+        "拥有管理权限的用户须先启用两步验证",
+        StatusCode::FORBIDDEN,
+    );
+    /// 访问令牌无效或已过期(26)
+    pub const INVALID_ACCESS_TOKEN: &'static Kind =
+        &Kind::new(26, "访问令牌无效或已过期", StatusCode::UNAUTHORIZED);
+    /// 该操作不支持使用访问令牌(27)
+    pub const SESSION_REQUIRED: &'static Kind =
+        &Kind::new(27, "该操作不支持使用访问令牌", StatusCode::FORBIDDEN);
+    /// 参数错误(28)
+    pub const INVALID_PARAMS: &'static Kind = &Kind::new(28, "参数错误", StatusCode::BAD_REQUEST);
+    /// 用户权限已变更，请重试(29)
+    pub const PERMISSION_CHANGED: &'static Kind =
+        &Kind::new(29, "用户权限已变更，请重试", StatusCode::UNAUTHORIZED);
+    /// 第三方登录失败(30)
+    pub const OIDC_LOGIN_FAILED: &'static Kind =
+        &Kind::new(30, "第三方登录失败", StatusCode::UNAUTHORIZED);
+    /// 昵称格式错误(31)
+    pub const INVALID_NICKNAME: &'static Kind =
+        &Kind::new(31, "昵称格式错误", StatusCode::BAD_REQUEST);
+    /// 出生日期错误(32)
+    pub const INVALID_BIRTHDAY: &'static Kind =
+        &Kind::new(32, "出生日期错误", StatusCode::BAD_REQUEST);
+    /// 数据已被修改，请刷新后重试(33)
+    pub const UPDATE_CONFLICT: &'static Kind =
+        &Kind::new(33, "数据已被修改，请刷新后重试", StatusCode::CONFLICT);
+    /// 至少须保留一种登录方式(34)
+    pub const LAST_AUTH_METHOD: &'static Kind =
+        &Kind::new(34, "至少须保留一种登录方式", StatusCode::BAD_REQUEST);
+    /// 用户已被禁用(35)
+    pub const USER_DISABLED: &'static Kind = &Kind::new(35, "用户已被禁用", StatusCode::FORBIDDEN);
+    /// 用户尚未启用(36)
+    pub const USER_PENDING: &'static Kind = &Kind::new(36, "用户尚未启用", StatusCode::FORBIDDEN);
+    /// 不支持的图片格式或图片已损坏(37)
+    pub const INVALID_IMAGE: &'static Kind =
+        &Kind::new(37, "不支持的图片格式或图片已损坏", StatusCode::BAD_REQUEST);
+    /// 图片文件或尺寸过大(38)
+    pub const IMAGE_TOO_LARGE: &'static Kind =
+        &Kind::new(38, "图片文件或尺寸过大", StatusCode::PAYLOAD_TOO_LARGE);
 
     /// 未知服务器错误(-1)
     pub const UNKNOWN: &'static Kind =
@@ -203,6 +310,18 @@ impl Kind {
     /// 工作线程错误(-9)
     pub const WORKER_THREAD_ERROR: &'static Kind =
         &Kind::new(-9, "工作线程错误", StatusCode::INTERNAL_SERVER_ERROR);
+    /// 验证码发送失败(-10)
+    pub const SEND_AUTH_CODE_FAILED: &'static Kind =
+        &Kind::new(-10, "验证码发送失败", StatusCode::INTERNAL_SERVER_ERROR);
+    /// 身份提供方请求失败(-11)
+    pub const OIDC_PROVIDER_ERROR: &'static Kind =
+        &Kind::new(-11, "身份提供方请求失败", StatusCode::INTERNAL_SERVER_ERROR);
+    /// 目录服务请求失败(-12)
+    pub const LDAP_ERROR: &'static Kind =
+        &Kind::new(-12, "目录服务请求失败", StatusCode::INTERNAL_SERVER_ERROR);
+    /// 文件存储失败(-13)
+    pub const STORAGE_ERROR: &'static Kind =
+        &Kind::new(-13, "文件存储失败", StatusCode::INTERNAL_SERVER_ERROR);
 }
 
 impl StdError for Error {}
@@ -288,6 +407,10 @@ impl actix_web::ResponseError for Error {
             header::CONTENT_TYPE,
             header::HeaderValue::from_static("application/json; charset=utf-8"),
         );
+        if let Some(seconds) = self.retry_after {
+            resp.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(seconds));
+        }
         resp.set_body(Body::from(body))
     }
 }
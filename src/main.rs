@@ -3,8 +3,9 @@
 //!
 use crate::controller::LoadAllControllers;
 use crate::error::Exception;
-use crate::service::LoadAllServices;
-use crate::util::user::UserFactory;
+use crate::service::ldap::LdapService;
+use crate::service::{LoadAllServices, ServiceOpts};
+use crate::util::http::TrustedProxies;
 use actix_web::{middleware, App, HttpServer};
 use futures::TryFutureExt;
 use opt::Opts;
@@ -41,6 +42,13 @@ async fn main() -> Result<(), Exception> {
         redis,
         http,
         log,
+        sender,
+        password,
+        hasher,
+        session,
+        oidc,
+        ldap,
+        avatar,
     } = Opts::open_toml("config.toml")
         .or_else(|_e| Opts::open_json("config.json"))
         .await?;
@@ -67,12 +75,40 @@ async fn main() -> Result<(), Exception> {
             .map_err(|e| format!("Redis 连接错误: {}", e))?,
     );
 
+    let jwt = session.create_jwt(&http.secure_key)?;
+    avatar.check()?;
+    sender.check();
+    let service_opts = ServiceOpts {
+        session_store: session.create_store(),
+        sender,
+        password_policy: password.create_policy()?,
+        hasher,
+        oidc,
+        ldap,
+        avatar,
+    };
+
+    // 定期同步目录用户的角色，只在主线程中运行一个任务
+    if let Some(directory) = &service_opts.ldap {
+        if let Some(interval) = directory.sync_interval() {
+            let ldap_svc = LdapService::new(
+                pg_pool.clone(),
+                redis_pool.clone(),
+                service_opts.session_store.clone(),
+                Some(directory.clone()),
+            );
+            actix_rt::spawn(ldap_svc.run_sync(interval));
+        }
+    }
+
     let http_config = http.clone();
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
-            .wrap(UserFactory::new(&http_config.secure_key, redis_pool.clone()).name("identity"))
-            .load_all_services(pg_pool.clone(), redis_pool.clone())
+            .wrap(session.create_factory(&http_config.secure_key, redis_pool.clone(), jwt.clone()))
+            .data(session.clone())
+            .app_data(TrustedProxies(http_config.trusted_proxies.clone()))
+            .load_all_services(pg_pool.clone(), redis_pool.clone(), service_opts.clone())
             .load_all_controllers()
             .service(actix_files::Files::new("/", &http_config.html).index_file("index.html"))
     })
@@ -9,17 +9,15 @@ pub struct Permission {
     pub permission_name: String,
 }
 
-/// 角色权限
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, PostgresMapper)]
-#[pg_mapper(table = "role_permission")]
-pub struct RolePermission {
-    pub role_id: Id,
-    pub permission_id: Id,
-}
-
 // ------------------------------------------------
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct PermissionContent {
     pub permission_name: String,
 }
+
+/// 批量设置角色权限时的权限列表
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PermissionIdsParams {
+    pub permission_ids: Vec<Id>,
+}
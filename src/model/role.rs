@@ -60,3 +60,39 @@ pub struct RoleContent {
     pub max_user: Option<i64>,
     pub max_permission: Option<i64>,
 }
+
+/// 创建或修改互斥约束时所需的字段
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct MutexConstraintContent {
+    pub constraint_name: String,
+    pub role_ids: Vec<Id>,
+}
+
+/// 互斥约束及其包含的所有角色
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct MutexConstraint {
+    pub id: Id,
+    pub constraint_name: String,
+    pub roles: Vec<Role>,
+}
+
+/// 创建或修改先决条件约束时所需的字段
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BaseRequiredConstraintContent {
+    pub constraint_name: String,
+    pub role_id: Id,
+}
+
+/// 先决条件约束及其限制的角色，用户必须先拥有该角色的所有父角色才能被授予该角色
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BaseRequiredConstraint {
+    pub id: Id,
+    pub constraint_name: String,
+    pub role: Role,
+}
+
+/// 批量授予/收回角色时的用户列表
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct UserIdsParams {
+    pub user_ids: Vec<Id>,
+}
@@ -10,6 +10,18 @@ pub enum Gender {
     Female,
 }
 
+/// 用户状态，只有 `Active` 的用户可以登录
+#[derive(Serialize, Deserialize, Debug, Display, PartialEq, Eq, Clone, Copy, ToSql, FromSql)]
+pub enum UserStatus {
+    Active,
+    /// 已被管理员禁用
+    Disabled,
+    /// 尚未启用，如等待审核
+    Pending,
+    /// 已被软删除，保留其登录方式、角色等数据，可由管理员恢复
+    Deleted,
+}
+
 /// 用户
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, PostgresMapper)]
 #[pg_mapper(table = "user_info")]
@@ -23,14 +35,21 @@ pub struct UserInfo {
     pub create_time: NaiveDateTime,
     pub update_time: NaiveDateTime,
     pub max_role: Option<i64>,
+    pub status: UserStatus,
+    /// 最近一次变更状态的原因
+    pub status_reason: Option<String>,
 }
 
 /// 授权类型
-#[derive(Serialize, Deserialize, Debug, Display, PartialEq, Eq, Clone, ToSql, FromSql)]
+#[derive(Serialize, Deserialize, Debug, Display, PartialEq, Eq, Clone, Copy, ToSql, FromSql)]
 pub enum AuthType {
     Username,
     Phone,
     Email,
+    /// OpenID Connect 外部身份，`identity` 为 `{身份提供方}:{sub}`，每个用户只能关联一个
+    Oidc,
+    /// LDAP 目录用户，`identity` 为小写的目录用户名，密码由目录服务校验
+    Ldap,
 }
 
 /// 用户授权
@@ -46,6 +65,19 @@ pub struct UserAuth {
     pub update_time: NaiveDateTime,
 }
 
+/// 用户登录方式的变更记录，`old_identity` 为空表示绑定，`new_identity` 为空表示解绑
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, PostgresMapper)]
+#[pg_mapper(table = "user_auth_history")]
+pub struct UserAuthHistory {
+    pub id: Id,
+    pub user_id: Id,
+    pub auth_type: AuthType,
+    pub old_identity: Option<String>,
+    pub new_identity: Option<String>,
+    pub client_ip: Option<String>,
+    pub create_time: NaiveDateTime,
+}
+
 /// 用户角色
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, PostgresMapper)]
 #[pg_mapper(table = "user_role")]
@@ -54,14 +86,95 @@ pub struct UserRole {
     pub role_id: Id,
 }
 
-/// -----------------------------------------------------------------
+/// 密码登录失败记录，保存在 Redis 中，登录成功或管理员解锁后清空
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct LoginFailure {
+    /// 连续失败次数
+    pub failure_count: u64,
+    pub last_failure_time: Option<NaiveDateTime>,
+    pub last_failure_ip: Option<String>,
+    /// 锁定截止时间，为空或早于当前时间表示未锁定
+    pub locked_until: Option<NaiveDateTime>,
+}
 
+/// 登录会话，保存在 Redis 中，`id` 用于注销指定会话
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Session {
+    pub id: String,
+    pub created_at: NaiveDateTime,
+    /// 最近一次访问时间，为减少 Redis 写入，精度约为一分钟
+    pub last_seen: NaiveDateTime,
+    /// 绝对过期时间，空闲超时可能使会话更早失效
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// 是否为发起本次请求的会话
+    pub current: bool,
+}
+
+/// 个人访问令牌，令牌本身只在创建时返回一次，数据库中只保存其 hash
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, PostgresMapper)]
+#[pg_mapper(table = "user_token")]
+pub struct AccessToken {
+    pub id: Id,
+    pub user_id: Id,
+    pub name: String,
+    /// 令牌可使用的权限名，须为创建者所拥有权限的子集
+    pub scopes: Vec<String>,
+    /// 过期时间，为空表示永不过期
+    pub expire_time: Option<NaiveDateTime>,
+    pub last_used_time: Option<NaiveDateTime>,
+    pub create_time: NaiveDateTime,
+}
+
+/// 新创建的个人访问令牌
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct CreatedAccessToken {
+    #[serde(flatten)]
+    pub info: AccessToken,
+    pub token: String,
+}
+
+/// 待确认的两步验证密钥，`otpauth_uri` 可生成二维码供验证器应用扫描
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TotpEnrollment {
+    /// Base32 编码的密钥，供无法扫码时手动输入
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// OpenID Connect 授权地址，前端须跳转到 `authorization_url`，回调时原样带回 `state`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct OidcAuthorization {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+/// 登录结果，启用了两步验证的用户须使用 `totp_token` 完成第二步验证
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum SignInResult {
+    SignedIn(UserInfo),
+    TotpRequired { totp_token: String },
+}
+
+// -----------------------------------------------------------------
+
+/// 登录参数
+///
+/// * `Username`: `credential1` 为密码；
+/// * `Phone`: `credential1` 为手机验证码；
+/// * `Email`: `credential1` 为密码，或者在 `credential2` 中填写邮箱验证码（此时忽略 `credential1`）；
+/// * `Ldap`: `identity` 为目录用户名，`credential1` 为目录密码。
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct SignInParams {
     pub auth_type: AuthType,
     pub identity: String,
     pub credential1: String,
     pub credential2: Option<String>,
+    /// 记住我，为 `true` 时会话使用更长的有效期，且关闭浏览器后不失效
+    #[serde(default)]
+    pub remember_me: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -72,6 +185,20 @@ pub struct RegisterParams {
     pub auth_code: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EmailRegisterParams {
+    pub username: String,
+    pub nickname: String,
+    pub email: String,
+    pub auth_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BindEmailParams {
+    pub email: String,
+    pub auth_code: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct GetAuthCodeParams {
     pub identity: String,
@@ -81,3 +208,189 @@ pub struct GetAuthCodeParams {
 pub struct AddPasswordParams {
     pub password: String,
 }
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ChangePasswordParams {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// 重置密码参数，`auth_type` 只能为 `Phone` 或 `Email`，`auth_code` 为发往 `identity` 的验证码
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ResetPasswordParams {
+    pub auth_type: AuthType,
+    pub identity: String,
+    pub auth_code: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RevokeRoleParams {
+    /// 是否同时收回以该角色为先决条件的角色
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RoleIdsParams {
+    pub role_ids: Vec<Id>,
+}
+
+/// 一次性密码或恢复码
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TotpCodeParams {
+    pub code: String,
+}
+
+/// 创建个人访问令牌的参数
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct CreateAccessTokenParams {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TotpSignInParams {
+    pub totp_token: String,
+    /// 一次性密码或恢复码
+    pub code: String,
+    /// 同 `SignInParams::remember_me`
+    #[serde(default)]
+    pub remember_me: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct OidcAuthorizeParams {
+    /// 是否为当前登录用户关联外部身份，为 `true` 时回调后须调用 `/user/oidc/link`
+    #[serde(default)]
+    pub link: bool,
+}
+
+/// OpenID Connect 登录参数，`code` 和 `state` 为身份提供方回调地址中的参数
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct OidcSignInParams {
+    pub provider: String,
+    pub code: String,
+    pub state: String,
+    /// 同 `SignInParams::remember_me`
+    #[serde(default)]
+    pub remember_me: bool,
+}
+
+/// 为已登录用户关联外部身份的参数
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct OidcLinkParams {
+    pub provider: String,
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// 管理员查询用户时的筛选条件，各条件同时满足，均为空时查询所有用户
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct UserFilter {
+    /// 用户名包含的字符串，不区分大小写
+    pub username: Option<String>,
+    /// 昵称包含的字符串，不区分大小写
+    pub nickname: Option<String>,
+    /// 绑定的手机号包含的字符串
+    pub phone: Option<String>,
+    /// 绑定的电子邮箱包含的字符串，不区分大小写
+    pub email: Option<String>,
+    /// 直接拥有的角色
+    pub role_id: Option<Id>,
+    /// 创建日期不早于该日期
+    pub created_from: Option<NaiveDate>,
+    /// 创建日期不晚于该日期
+    pub created_to: Option<NaiveDate>,
+    /// 用户状态，为空时查询除 `Deleted` 以外的所有用户
+    pub status: Option<UserStatus>,
+}
+
+/// 管理员创建用户的参数，`password`、`phone`、`email` 至少填写一项作为用户的登录方式
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct CreateUserParams {
+    pub username: String,
+    pub nickname: String,
+    /// 用户名登录的密码，须符合配置的密码策略
+    pub password: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub avatar: Option<String>,
+    pub gender: Option<Gender>,
+    pub birthday: Option<NaiveDate>,
+    pub max_role: Option<i64>,
+    /// 用户的初始状态，为空时为 `Active`
+    pub status: Option<UserStatus>,
+}
+
+/// 头像的一种尺寸
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct AvatarImage {
+    /// 边长（像素）
+    pub size: u32,
+    pub url: String,
+}
+
+/// 上传头像的结果，`avatar` 为最大尺寸的地址（即 `UserInfo::avatar`），`images` 按尺寸从大到小排列
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct UploadedAvatar {
+    pub avatar: String,
+    pub images: Vec<AvatarImage>,
+}
+
+/// 管理员修改用户状态的参数
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct UserStatusParams {
+    pub status: UserStatus,
+    /// 变更原因，如离职、违规等
+    pub reason: Option<String>,
+}
+
+/// 管理员修改用户信息时可修改的字段
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct UserInfoContent {
+    pub nickname: String,
+    pub avatar: Option<String>,
+    pub gender: Gender,
+    pub birthday: Option<NaiveDate>,
+    pub max_role: Option<i64>,
+}
+
+/// 修改个人信息的参数
+///
+/// `update_time` 须为读取个人信息时得到的值，期间个人信息被修改过时拒绝本次修改，避免覆盖其他修改；
+/// 头像只能通过上传修改（见 `AvatarService`）
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ProfileUpdate {
+    pub nickname: String,
+    pub gender: Gender,
+    pub birthday: Option<NaiveDate>,
+    pub update_time: NaiveDateTime,
+}
+
+/// 重新认证的参数，修改登录方式等敏感操作须先证明是用户本人
+///
+/// 填写当前的登录密码 `password`，或者填写 `reauth_type`（`Phone` 或 `Email`）
+/// 及发往用户已绑定的该手机号/电子邮箱的验证码 `reauth_code`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct ReauthParams {
+    pub password: Option<String>,
+    pub reauth_type: Option<AuthType>,
+    pub reauth_code: Option<String>,
+}
+
+/// 绑定或更换手机号/电子邮箱的参数，`auth_code` 为发往新手机号/电子邮箱 `identity` 的验证码
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BindIdentityParams {
+    pub identity: String,
+    pub auth_code: String,
+    #[serde(flatten)]
+    pub reauth: ReauthParams,
+}
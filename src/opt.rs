@@ -1,11 +1,23 @@
 //! 配置
 use crate::error::Exception;
+use crate::util::crypto::PasswordHasher;
+use crate::util::jwt::Jwt;
+use crate::util::ldap::LdapDirectory;
+use crate::util::oidc::OidcProvider;
+use crate::util::sender::{CodeSender, DevSender, HttpSmsSender, SmtpSender};
+use crate::util::storage::{LocalStorage, S3Storage, Storage};
+use crate::util::types::PasswordPolicy;
+use crate::util::user::{SessionStore, UserFactory};
+use actix_web::cookie::SameSite;
 use deadpool_postgres::Config as PgConfig;
 use deadpool_redis::Config as RedisConfig;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
 use log::Level;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use time::Duration;
 use tokio::fs::File;
 use tokio::prelude::*;
 use tokio_postgres::NoTls;
@@ -20,6 +32,21 @@ pub struct Opts {
     pub redis: RedisOpts,
     pub http: HttpOpts,
     pub log: LogOpts,
+    #[serde(default)]
+    pub sender: SenderOpts,
+    #[serde(default)]
+    pub password: PasswordOpts,
+    #[serde(default)]
+    pub hasher: PasswordHasher,
+    #[serde(default)]
+    pub session: SessionOpts,
+    /// OpenID Connect 身份提供方，Key 为身份提供方名称，用于登录接口及外部身份的标识
+    #[serde(default)]
+    pub oidc: HashMap<String, OidcProvider>,
+    /// LDAP 目录服务，不配置时不能使用 `Ldap` 方式登录
+    pub ldap: Option<LdapDirectory>,
+    #[serde(default)]
+    pub avatar: AvatarOpts,
 }
 
 impl Opts {
@@ -42,6 +69,20 @@ impl Opts {
     }
 }
 
+#[cfg(test)]
+impl Opts {
+    /// 打开 `ADMINO_TEST_CONFIG` 指定的配置文件（TOML），未设置时返回 `None`，需要数据库或 Redis 的测试应跳过
+    pub async fn open_test() -> Option<Self> {
+        match std::env::var("ADMINO_TEST_CONFIG") {
+            Ok(path) => Some(Self::open_toml(path).await.unwrap()),
+            Err(_) => {
+                eprintln!("未设置 ADMINO_TEST_CONFIG，跳过需要数据库的测试");
+                None
+            }
+        }
+    }
+}
+
 /// 数据库配置
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DbOpts {
@@ -97,6 +138,9 @@ pub struct HttpOpts {
     pub html: PathBuf,
     #[serde(rename = "secure-key", with = "hex_serde")]
     pub secure_key: [u8; 32],
+    /// 可信的反向代理地址，只有来自这些地址的请求才从 `Forwarded`/`X-Forwarded-For` 请求头中读取客户端 IP
+    #[serde(rename = "trusted-proxies", default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 /// 日志配置
@@ -104,3 +148,391 @@ pub struct HttpOpts {
 pub struct LogOpts {
     pub level: Level,
 }
+
+/// 会话配置，时长的单位均为秒
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SessionOpts {
+    pub cookie_name: String,
+    /// 是否只允许通过 HTTPS 发送 Cookie，部署在 HTTPS 之后时应设为 `true`
+    pub secure: bool,
+    /// Cookie 的 SameSite 属性，不配置时不设置该属性，为 `none` 时须同时开启 `secure`
+    pub same_site: Option<CookieSameSite>,
+    /// Cookie 的 Domain 属性，多个子域名共享登录状态时可配置为上级域名
+    pub domain: Option<String>,
+    /// Cookie 的 Path 属性
+    pub path: String,
+    /// 会话在 Redis 中的 Key 前缀，多个实例共用一个 Redis 时用于区分
+    pub key_prefix: String,
+    /// 空闲超时，超过该时长未访问则会话失效
+    pub idle_timeout: u64,
+    /// 绝对超时，自登录起超过该时长会话即失效，无论是否活跃
+    pub absolute_timeout: u64,
+    /// 登录时勾选“记住我”的会话有效期，不受空闲超时限制
+    pub remember_me_ttl: u64,
+    /// 会话模式
+    pub mode: SessionMode,
+    /// `mode` 为 `jwt` 时的 JWT 配置
+    pub jwt: JwtOpts,
+}
+
+/// 会话模式
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    /// 每个请求都从 Redis 中读取会话
+    Redis,
+    /// 签发短期有效的 JWT，过期后使用 Redis 中的会话（刷新令牌）重新签发
+    Jwt,
+}
+
+/// JWT 配置
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct JwtOpts {
+    /// 签名算法，`HS256` 使用 `http.secure-key` 作为密钥，`RS256`/`EdDSA` 使用 PEM 格式的密钥文件
+    pub algorithm: JwtAlgorithm,
+    /// 私钥文件，用于签发
+    pub private_key: Option<PathBuf>,
+    /// 公钥文件，用于校验
+    pub public_key: Option<PathBuf>,
+    pub issuer: String,
+    /// 访问令牌有效期（秒），应小于会话的空闲超时
+    pub access_ttl: u64,
+}
+
+impl Default for JwtOpts {
+    fn default() -> Self {
+        Self {
+            algorithm: JwtAlgorithm::HS256,
+            private_key: None,
+            public_key: None,
+            issuer: "admino".into(),
+            access_ttl: 15 * 60,
+        }
+    }
+}
+
+/// JWT 签名算法
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+impl JwtOpts {
+    /// 读取密钥文件创建 JWT 签发器，`secret` 为 HS256 使用的密钥
+    pub fn create_jwt(&self, secret: &[u8]) -> Result<Jwt, Exception> {
+        let read_key = |path: &Option<PathBuf>, name: &str| -> Result<Vec<u8>, Exception> {
+            let path = path
+                .as_ref()
+                .ok_or_else(|| format!("{:?} 算法须配置 {}", self.algorithm, name))?;
+            Ok(std::fs::read(path)
+                .map_err(|e| format!("读取 JWT 密钥 {} 失败: {}", path.display(), e))?)
+        };
+
+        let (algorithm, encoding_key, decoding_key) = match self.algorithm {
+            JwtAlgorithm::HS256 => (
+                Algorithm::HS256,
+                EncodingKey::from_secret(secret),
+                DecodingKey::from_secret(secret),
+            ),
+            JwtAlgorithm::RS256 => (
+                Algorithm::RS256,
+                EncodingKey::from_rsa_pem(&read_key(&self.private_key, "private_key")?)?,
+                DecodingKey::from_rsa_pem(&read_key(&self.public_key, "public_key")?)?,
+            ),
+            JwtAlgorithm::EdDSA => (
+                Algorithm::EdDSA,
+                EncodingKey::from_ed_pem(&read_key(&self.private_key, "private_key")?)?,
+                DecodingKey::from_ed_pem(&read_key(&self.public_key, "public_key")?)?,
+            ),
+        };
+
+        Ok(Jwt::new(
+            algorithm,
+            encoding_key,
+            decoding_key,
+            self.issuer.clone(),
+            self.access_ttl as i64,
+        ))
+    }
+}
+
+impl Default for SessionOpts {
+    fn default() -> Self {
+        Self {
+            cookie_name: "identity".into(),
+            secure: false,
+            same_site: None,
+            domain: None,
+            path: "/".into(),
+            key_prefix: "user:".into(),
+            idle_timeout: 2 * 3600,
+            absolute_timeout: 24 * 3600,
+            remember_me_ttl: 30 * 24 * 3600,
+            mode: SessionMode::Redis,
+            jwt: JwtOpts::default(),
+        }
+    }
+}
+
+impl SessionOpts {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::seconds(self.idle_timeout as i64)
+    }
+
+    pub fn absolute_timeout(&self) -> Duration {
+        Duration::seconds(self.absolute_timeout as i64)
+    }
+
+    pub fn remember_me_ttl(&self) -> Duration {
+        Duration::seconds(self.remember_me_ttl as i64)
+    }
+
+    /// 创建会话存储，服务与身份标识中间件须使用相同的 Key 前缀
+    pub fn create_store(&self) -> SessionStore {
+        SessionStore::new(self.key_prefix.clone())
+    }
+
+    /// 会话模式为 `jwt` 时创建 JWT 签发器，否则返回 `None`，`key` 为 `http.secure-key`
+    pub fn create_jwt(&self, key: &[u8]) -> Result<Option<Jwt>, Exception> {
+        match self.mode {
+            SessionMode::Redis => Ok(None),
+            SessionMode::Jwt => {
+                if self.jwt.access_ttl >= self.idle_timeout {
+                    warn!("JWT 有效期不小于会话的空闲超时，会话可能在刷新前过期");
+                }
+                self.jwt.create_jwt(key).map(Some)
+            }
+        }
+    }
+
+    /// 使用会话配置创建身份标识中间件工厂
+    pub fn create_factory(
+        &self,
+        key: &[u8],
+        redis_pool: RedisPool,
+        jwt: Option<Jwt>,
+    ) -> UserFactory {
+        if self.same_site == Some(CookieSameSite::None) && !self.secure {
+            warn!("SameSite=None 的 Cookie 须同时设置 Secure，否则会被浏览器拒绝");
+        }
+
+        UserFactory::new(key, redis_pool)
+            .name(self.cookie_name.clone())
+            .secure(self.secure)
+            .same_site(self.same_site.map(SameSite::from))
+            .domain(self.domain.clone())
+            .path(self.path.clone())
+            .idle_timeout(self.idle_timeout())
+            .absolute_timeout(self.absolute_timeout())
+            .store(self.create_store())
+            .jwt(jwt)
+    }
+}
+
+/// Cookie 的 SameSite 属性
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(same_site: CookieSameSite) -> Self {
+        match same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+/// 密码策略配置
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PasswordOpts {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// 至少须包含几类字符（大写字母、小写字母、数字、符号），取值 1 到 4
+    pub min_char_classes: usize,
+    /// 禁止使用的密码列表文件，每行一个，不区分大小写
+    pub blocklist: Option<PathBuf>,
+}
+
+impl Default for PasswordOpts {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 64,
+            min_char_classes: 2,
+            blocklist: None,
+        }
+    }
+}
+
+impl PasswordOpts {
+    /// 使用配置创建密码策略，会读取 `blocklist` 文件
+    pub fn create_policy(self) -> Result<PasswordPolicy, Exception> {
+        let mut blocklist = PasswordPolicy::COMMON_PASSWORDS
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<HashSet<_>>();
+
+        if let Some(path) = &self.blocklist {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("读取密码黑名单 {} 失败: {}", path.display(), e))?;
+            blocklist.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_lowercase),
+            );
+        }
+
+        Ok(PasswordPolicy {
+            min_length: self.min_length,
+            max_length: self.max_length,
+            min_char_classes: self.min_char_classes,
+            blocklist,
+        })
+    }
+}
+
+/// 验证码发送配置，未配置时默认使用 `DevSender` 输出到标准输出，启动时会输出警告日志
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SenderOpts {
+    #[serde(default)]
+    pub phone: CodeSenderOpts,
+    #[serde(default)]
+    pub email: CodeSenderOpts,
+}
+
+/// 单个验证码发送器的配置，由 `type` 字段决定使用哪种实现
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CodeSenderOpts {
+    Smtp(SmtpSender),
+    Http(HttpSmsSender),
+    Dev(DevSender),
+}
+
+impl Default for CodeSenderOpts {
+    fn default() -> Self {
+        CodeSenderOpts::Dev(DevSender::default())
+    }
+}
+
+impl SenderOpts {
+    /// 使用 `DevSender` 时输出警告日志，`DevSender` 会将验证码明文写入文件或标准输出，仅用于开发调试
+    pub fn check(&self) {
+        for (name, opts) in [("phone", &self.phone), ("email", &self.email)].iter() {
+            if let CodeSenderOpts::Dev(sender) = opts {
+                match &sender.path {
+                    Some(path) => warn!(
+                        "验证码发送器 [sender.{}] 为 dev，验证码将以明文写入文件 {}，请勿在生产环境中使用",
+                        name,
+                        path.display()
+                    ),
+                    None => warn!(
+                        "验证码发送器 [sender.{}] 为 dev，验证码将以明文输出到标准输出，请勿在生产环境中使用",
+                        name
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl CodeSenderOpts {
+    /// 使用配置直接创建验证码发送器
+    pub fn create_sender(self) -> Box<dyn CodeSender> {
+        match self {
+            CodeSenderOpts::Smtp(sender) => Box::new(sender),
+            CodeSenderOpts::Http(sender) => Box::new(sender),
+            CodeSenderOpts::Dev(sender) => Box::new(sender),
+        }
+    }
+}
+
+/// 头像配置
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AvatarOpts {
+    /// 上传的图片文件的最大字节数
+    pub max_size: usize,
+    /// 上传的图片的最大宽度及高度（像素），防止解码过大的图片耗尽内存
+    pub max_dimension: u32,
+    /// 头像裁剪为正方形后缩放到的边长（像素），`UserInfo::avatar` 为其中最大尺寸的地址
+    pub sizes: Vec<u32>,
+    /// 头像地址的前缀，不配置时为本服务的 `/avatars`；
+    /// 使用 CDN 或可公开读取的对象存储时可配置为其地址，如 `http://127.0.0.1:9000/admino`
+    pub base_url: Option<String>,
+    /// 读取头像时 `Cache-Control` 响应头中的 `max-age`（秒），头像地址包含内容的 hash，可以长期缓存
+    pub cache_max_age: u64,
+    pub storage: StorageOpts,
+}
+
+impl Default for AvatarOpts {
+    fn default() -> Self {
+        Self {
+            max_size: 2 * 1024 * 1024,
+            max_dimension: 4096,
+            sizes: vec![256, 128, 64],
+            base_url: None,
+            cache_max_age: 365 * 24 * 3600,
+            storage: StorageOpts::default(),
+        }
+    }
+}
+
+impl AvatarOpts {
+    /// 检查 `sizes` 不为空，且均不为 0、不超过 `max_dimension`
+    pub fn check(&self) -> Result<(), Exception> {
+        if self.sizes.is_empty()
+            || self
+                .sizes
+                .iter()
+                .any(|size| *size == 0 || *size > self.max_dimension)
+        {
+            return Err(format!(
+                "头像尺寸 {:?} 须不为空，且均在 1 到 {} 之间",
+                self.sizes, self.max_dimension
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// 文件存储的配置，由 `type` 字段决定使用哪种实现，未配置时保存在本地的 `./avatars` 目录中
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageOpts {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Default for StorageOpts {
+    fn default() -> Self {
+        StorageOpts::Local(LocalStorage {
+            dir: "./avatars".into(),
+        })
+    }
+}
+
+impl StorageOpts {
+    /// 使用配置直接创建文件存储
+    pub fn create_storage(self) -> Box<dyn Storage> {
+        match self {
+            StorageOpts::Local(storage) => Box::new(storage),
+            StorageOpts::S3(storage) => Box::new(storage),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! 头像上传及读取相关服务
+use crate::error::{Error, Kind};
+use crate::model::{AvatarImage, Id, UploadedAvatar};
+use crate::opt::{AvatarOpts, PgPool};
+use crate::service::user::lock_users;
+use crate::util::storage::Storage;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use image::imageops::FilterType;
+use image::io::Reader;
+use image::{ImageFormat, ImageOutputFormat};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+
+/// 头像上传及读取相关服务
+pub struct AvatarService {
+    pg_pool: PgPool,
+    storage: Box<dyn Storage>,
+    /// 按从大到小排列
+    sizes: Vec<u32>,
+    max_size: usize,
+    max_dimension: u32,
+    base_url: String,
+    cache_max_age: u64,
+}
+
+impl AvatarService {
+    pub fn new(pg_pool: PgPool, opts: AvatarOpts) -> Self {
+        let mut sizes = opts.sizes;
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        sizes.dedup();
+
+        Self {
+            pg_pool,
+            storage: opts.storage.create_storage(),
+            sizes,
+            max_size: opts.max_size,
+            max_dimension: opts.max_dimension,
+            base_url: opts
+                .base_url
+                .map(|url| url.trim_end_matches('/').to_owned())
+                .unwrap_or_else(|| AvatarService::PATH.to_owned()),
+            cache_max_age: opts.cache_max_age,
+        }
+    }
+
+    /// 本服务读取头像的路径，见 `GET /avatars/{user_id}/{name}`
+    pub const PATH: &'static str = "/avatars";
+    /// 头像统一重新编码为 PNG
+    pub const CONTENT_TYPE: &'static str = "image/png";
+    /// 头像文件名中内容 hash 的长度
+    const HASH_LEN: usize = 16;
+    /// 接受上传的图片格式
+    const ACCEPTED_FORMATS: &'static [ImageFormat] = &[
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Gif,
+        ImageFormat::WebP,
+    ];
+
+    /// 上传的图片文件的最大字节数
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// 读取头像时 `Cache-Control` 响应头中的 `max-age`（秒）
+    pub fn cache_max_age(&self) -> u64 {
+        self.cache_max_age
+    }
+
+    /// 头像文件的 Key，即 `{user_id}/{hash}_{size}.png`
+    fn object_key(user_id: Id, hash: &str, size: u32) -> String {
+        format!("{}/{}_{}.png", user_id, hash, size)
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+
+    /// 解析头像地址中的 hash，不是本服务为该用户保存的头像时返回 `None`
+    fn parse_url(&self, user_id: Id, url: &str) -> Option<String> {
+        let name = url
+            .strip_prefix(&self.base_url)?
+            .strip_prefix(&format!("/{}/", user_id))?;
+
+        parse_file_name(name).map(|(hash, _)| hash.to_owned())
+    }
+
+    /// 上传头像，返回各个尺寸的地址
+    ///
+    /// 1. 只接受 PNG、JPEG、GIF 及 WebP 格式（按文件内容判断），否则返回 `INVALID_IMAGE`；
+    /// 2. 宽度或高度超过 `max_dimension` 时返回 `IMAGE_TOO_LARGE`；
+    /// 3. 居中裁剪为正方形，缩放到配置的各个尺寸并重新编码为 PNG 后保存，文件名包含原图内容的 hash；
+    /// 4. 将用户的头像修改为最大尺寸的地址，原头像是本服务保存的头像时删除其文件。
+    pub async fn upload(&self, user_id: Id, data: Vec<u8>) -> Result<UploadedAvatar, Error> {
+        let hash = format!("{:x}", Sha256::digest(&data))[..AvatarService::HASH_LEN].to_owned();
+
+        let sizes = self.sizes.clone();
+        let max_dimension = self.max_dimension;
+
+        // 解码及缩放图片比较耗时，在线程池中执行
+        let images = web::block(move || resize(&data, &sizes, max_dimension))
+            .await
+            .map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => Kind::WORKER_THREAD_ERROR.into(),
+            })?;
+
+        let mut uploaded = Vec::with_capacity(images.len());
+
+        for (size, image) in images {
+            let key = AvatarService::object_key(user_id, &hash, size);
+            self.storage
+                .put(&key, AvatarService::CONTENT_TYPE, image)
+                .await?;
+            uploaded.push(AvatarImage {
+                size,
+                url: self.url(&key),
+            });
+        }
+
+        let avatar = uploaded
+            .first()
+            .map(|image| image.url.clone())
+            .ok_or_else(|| Error::from(Kind::UNKNOWN))?;
+
+        let old = self.replace_avatar(user_id, Some(&avatar)).await?;
+
+        self.delete_files(user_id, old.as_deref(), Some(&hash))
+            .await;
+
+        Ok(UploadedAvatar {
+            avatar,
+            images: uploaded,
+        })
+    }
+
+    /// 删除用户的头像，头像是本服务保存的头像时同时删除其文件
+    pub async fn delete(&self, user_id: Id) -> Result<(), Error> {
+        let old = self.replace_avatar(user_id, None).await?;
+
+        self.delete_files(user_id, old.as_deref(), None).await;
+
+        Ok(())
+    }
+
+    /// 读取头像文件，`name` 不是本服务生成的文件名或文件不存在时返回 `EMPTY_RESULT`
+    pub async fn load(&self, user_id: Id, name: &str) -> Result<Vec<u8>, Error> {
+        if parse_file_name(name).is_none() {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        self.storage
+            .get(&format!("{}/{}", user_id, name))
+            .await?
+            .ok_or_else(|| Kind::EMPTY_RESULT.into())
+    }
+
+    /// 修改用户的头像地址，返回原头像地址，用户不存在时返回错误
+    async fn replace_avatar(
+        &self,
+        user_id: Id,
+        avatar: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[user_id]).await?;
+
+        let statement = transaction
+            .prepare("select avatar from user_info where id = $1")
+            .await?;
+
+        let old: Option<String> = transaction.query_one(&statement, &[&user_id]).await?.get(0);
+
+        let statement = transaction
+            .prepare("update user_info set avatar = $1 where id = $2")
+            .await?;
+
+        transaction
+            .execute(&statement, &[&avatar, &user_id])
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(old)
+    }
+
+    /// 删除原头像 `old` 的所有尺寸的文件，与当前头像的 hash `current` 相同时跳过
+    ///
+    /// 头像地址已经修改，删除失败只记录错误
+    async fn delete_files(&self, user_id: Id, old: Option<&str>, current: Option<&str>) {
+        let hash = match old.and_then(|url| self.parse_url(user_id, url)) {
+            Some(hash) if Some(hash.as_str()) != current => hash,
+            _ => return,
+        };
+
+        for size in self.sizes.iter() {
+            let key = AvatarService::object_key(user_id, &hash, *size);
+            if let Err(e) = self.storage.delete(&key).await {
+                error!("删除头像文件 {} 时发生错误: {}", key, e);
+            }
+        }
+    }
+}
+
+/// 解析本服务生成的头像文件名 `{hash}_{size}.png`，返回 hash 及尺寸
+fn parse_file_name(name: &str) -> Option<(&str, u32)> {
+    let (hash, size) = name.strip_suffix(".png")?.split_once('_')?;
+
+    if hash.len() != AvatarService::HASH_LEN
+        || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        || size.is_empty()
+        || !size.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    size.parse().ok().map(|size| (hash, size))
+}
+
+/// 校验图片并生成各个尺寸的 PNG 图片，需在线程池中执行
+fn resize(data: &[u8], sizes: &[u32], max_dimension: u32) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let format = Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| Kind::INVALID_IMAGE.with_detail(e))?
+        .format()
+        .filter(|format| AvatarService::ACCEPTED_FORMATS.contains(format))
+        .ok_or(Kind::INVALID_IMAGE)?;
+
+    // 先只读取图片头中的尺寸，避免解码过大的图片
+    let (width, height) = Reader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|e| Kind::INVALID_IMAGE.with_detail(e))?;
+
+    if width > max_dimension || height > max_dimension {
+        return Err(Kind::IMAGE_TOO_LARGE.into());
+    }
+
+    let image = Reader::with_format(Cursor::new(data), format)
+        .decode()
+        .map_err(|e| Kind::INVALID_IMAGE.with_detail(e))?;
+
+    sizes
+        .iter()
+        .map(|size| {
+            let mut png = Vec::new();
+            image
+                .resize_to_fill(*size, *size, FilterType::Lanczos3)
+                .write_to(&mut png, ImageOutputFormat::Png)
+                .map_err(|e| Kind::DATA_FORMAT.with_detail(e))?;
+            Ok((*size, png))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+
+    fn encode(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let image = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        });
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut data, format)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn resize_to_png_squares() {
+        for data in &[
+            encode(300, 200, ImageOutputFormat::Png),
+            encode(200, 300, ImageOutputFormat::Jpeg(90)),
+        ] {
+            let images = resize(data, &[128, 32], 4096).unwrap();
+            assert_eq!(
+                images.iter().map(|(size, _)| *size).collect::<Vec<_>>(),
+                vec![128, 32]
+            );
+
+            for (size, png) in images {
+                assert_eq!(image::guess_format(&png).unwrap(), ImageFormat::Png);
+                let image = image::load_from_memory(&png).unwrap();
+                assert_eq!(image.dimensions(), (size, size));
+            }
+        }
+    }
+
+    #[test]
+    fn resize_rejects_large_images() {
+        let wide = encode(5000, 1, ImageOutputFormat::Png);
+        let e = resize(&wide, &[64], 4096).unwrap_err();
+        assert_eq!(e.kind().code(), Kind::IMAGE_TOO_LARGE.code());
+
+        let tall = encode(1, 101, ImageOutputFormat::Png);
+        let e = resize(&tall, &[64], 100).unwrap_err();
+        assert_eq!(e.kind().code(), Kind::IMAGE_TOO_LARGE.code());
+
+        // 尺寸只从图片头读取，图片数据不完整也不会被解码
+        let mut truncated = wide.clone();
+        truncated.truncate(64);
+        let e = resize(&truncated, &[64], 4096).unwrap_err();
+        assert_eq!(e.kind().code(), Kind::IMAGE_TOO_LARGE.code());
+
+        assert!(resize(&encode(100, 100, ImageOutputFormat::Png), &[64], 100).is_ok());
+    }
+
+    #[test]
+    fn resize_rejects_invalid_images() {
+        let mut truncated = encode(64, 64, ImageOutputFormat::Png);
+        truncated.truncate(truncated.len() / 2);
+
+        let mut bmp = b"BM".to_vec();
+        bmp.extend(&[0; 64]);
+
+        for data in &[
+            Vec::new(),
+            b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>".to_vec(),
+            bmp,
+            truncated,
+        ] {
+            let e = resize(data, &[64], 4096).unwrap_err();
+            assert_eq!(e.kind().code(), Kind::INVALID_IMAGE.code());
+        }
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            parse_file_name("3f2a9c1d7e8b4a60_64.png"),
+            Some(("3f2a9c1d7e8b4a60", 64))
+        );
+
+        for name in &[
+            "3f2a9c1d7e8b4a60_64.jpg",
+            "3f2a9c1d7e8b4a6_64.png",
+            "3F2A9C1D7E8B4A60_64.png",
+            "3f2a9c1d7e8b4a60_.png",
+            "3f2a9c1d7e8b4a60_+64.png",
+            "../3f2a9c1d7e8b4a6_64.png",
+        ] {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+    }
+}
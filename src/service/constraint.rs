@@ -0,0 +1,628 @@
+//! 角色约束相关服务
+use crate::error::{Error, Kind};
+use crate::model::{
+    BaseRequiredConstraint, BaseRequiredConstraintContent, ConstraintType, Count, Id,
+    MutexConstraint, MutexConstraintContent, Role, RoleConstraint,
+};
+use crate::opt::PgPool;
+use crate::util::db::Pager;
+use deadpool_postgres::Transaction;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+/// 在事务中以共享模式锁定互斥约束表和先决条件约束表，防止授予/收回角色的过程中约束发生变化
+///
+/// 写入 `user_role` 的事务须在第一次写入 `user_role` 之前调用，
+/// 与修改约束时先写约束表、再锁定 `user_role` 的顺序一致，避免死锁
+pub async fn lock_constraints(transaction: &Transaction<'_>) -> Result<(), Error> {
+    transaction
+        .batch_execute("lock table constraint_mutex, constraint_base_required in share mode")
+        .await?;
+
+    Ok(())
+}
+
+/// 检查为用户 `user_id` 授予角色 `role_id` 后是否违反互斥约束
+///
+/// 用户通过角色继承关系间接获得的角色同样受互斥约束限制，
+/// 需在授予角色的事务中、写入 `user_role` 之前调用，调用前须已锁定约束表（见 `lock_constraints`）
+pub async fn check_mutex(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    role_id: Id,
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare(
+            "with recursive held(id) as (\
+                 select role_id from user_role where user_id = $1 \
+                 union \
+                 select role_ext.base_id from role_ext join held on role_ext.derived_id = held.id\
+             ), granted(id) as (\
+                 select $2::bigint \
+                 union \
+                 select role_ext.base_id from role_ext join granted on role_ext.derived_id = granted.id\
+             ) \
+             select exists(\
+                 select 1 from constraint_mutex a join constraint_mutex b \
+                 on a.constraint_id = b.constraint_id and a.role_id <> b.role_id \
+                 where a.role_id in (select id from granted) \
+                 and b.role_id in (select id from held union select id from granted)\
+             )",
+        )
+        .await?;
+
+    let conflict: bool = transaction
+        .query_one(&statement, &[&user_id, &role_id])
+        .await?
+        .get(0);
+
+    if conflict {
+        Err(Kind::ROLE_MUTEX_CONFLICT.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// 检查为用户 `user_id` 授予角色 `role_id` 时是否满足先决条件约束
+///
+/// 如果角色 `role_id` 有先决条件约束，用户必须已经拥有其所有父角色，
+/// 需在授予角色的事务中、写入 `user_role` 之前调用，调用前须已锁定约束表（见 `lock_constraints`）
+pub async fn check_base_required(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    role_id: Id,
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare(
+            "select exists(\
+                 select 1 from constraint_base_required join role_ext \
+                 on role_ext.derived_id = constraint_base_required.role_id \
+                 where constraint_base_required.role_id = $2 \
+                 and role_ext.base_id not in (select role_id from user_role where user_id = $1)\
+             )",
+        )
+        .await?;
+
+    let missing: bool = transaction
+        .query_one(&statement, &[&user_id, &role_id])
+        .await?
+        .get(0);
+
+    if missing {
+        Err(Kind::BASE_ROLE_REQUIRED.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// 查询收回用户 `user_id` 的角色 `role_id` 时，因先决条件约束需要一并收回的其他角色
+///
+/// 需在收回角色的事务中、删除 `user_role` 之前调用，调用前须已锁定约束表（见 `lock_constraints`）
+pub async fn query_dependent_roles(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    role_id: Id,
+) -> Result<Vec<Id>, Error> {
+    let statement = transaction
+        .prepare(
+            "with recursive dependents(id) as (\
+                 select $2::bigint \
+                 union \
+                 select constraint_base_required.role_id from constraint_base_required \
+                 join role_ext on role_ext.derived_id = constraint_base_required.role_id \
+                 join dependents on role_ext.base_id = dependents.id\
+             ) \
+             select role_id from user_role where user_id = $1 and role_id <> $2 \
+             and role_id in (select id from dependents)",
+        )
+        .await?;
+
+    let rows = transaction.query(&statement, &[&user_id, &role_id]).await?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// 检查角色 `role_id` 的父角色变化后，拥有该角色或其派生角色的用户是否违反互斥约束
+///
+/// 用户通过角色继承关系间接获得的角色同样计入，检查所有互斥约束，
+/// 调用前须已锁定约束表、`role_ext` 及 `user_role`
+pub async fn check_inherited_mutex(
+    transaction: &Transaction<'_>,
+    role_id: Id,
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare(
+            "with recursive derived(id) as (\
+                 select $1::bigint \
+                 union \
+                 select role_ext.derived_id from role_ext join derived on role_ext.base_id = derived.id\
+             ), held(user_id, role_id) as (\
+                 select user_id, role_id from user_role where user_id in (\
+                     select user_id from user_role where role_id in (select id from derived)\
+                 ) \
+                 union \
+                 select held.user_id, role_ext.base_id from role_ext join held on role_ext.derived_id = held.role_id\
+             ) \
+             select exists(\
+                 select 1 from held join constraint_mutex on constraint_mutex.role_id = held.role_id \
+                 group by held.user_id, constraint_mutex.constraint_id having count(distinct held.role_id) > 1\
+             )",
+        )
+        .await?;
+
+    let conflict: bool = transaction.query_one(&statement, &[&role_id]).await?.get(0);
+
+    if conflict {
+        Err(Kind::ROLE_MUTEX_CONFLICT.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// 检查直接拥有角色 `role_id` 的用户是否满足其先决条件约束，即直接拥有该角色当前的所有父角色
+///
+/// 角色没有先决条件约束时直接通过，用于角色的父角色或先决条件约束变化后，
+/// 调用前须已锁定约束表、`role_ext` 及 `user_role`
+pub async fn check_base_required_holders(
+    transaction: &Transaction<'_>,
+    role_id: Id,
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare(
+            "select exists(\
+                 select 1 from constraint_base_required \
+                 join user_role on user_role.role_id = constraint_base_required.role_id \
+                 join role_ext on role_ext.derived_id = user_role.role_id \
+                 where constraint_base_required.role_id = $1 and not exists(\
+                     select 1 from user_role base \
+                     where base.user_id = user_role.user_id and base.role_id = role_ext.base_id\
+                 )\
+             )",
+        )
+        .await?;
+
+    let missing: bool = transaction.query_one(&statement, &[&role_id]).await?.get(0);
+
+    if missing {
+        Err(Kind::BASE_ROLE_REQUIRED.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// 角色约束相关服务
+pub struct ConstraintService {
+    pg_pool: PgPool,
+}
+
+impl ConstraintService {
+    pub fn new(pg_pool: PgPool) -> Self {
+        Self { pg_pool }
+    }
+
+    pub async fn query_mutex_count(&self) -> Result<Count, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select count(1) from role_constraint where constraint_type = $1")
+            .await?;
+
+        Ok(Count {
+            count: pg_client
+                .query_one(&statement, &[&ConstraintType::Mutex])
+                .await?
+                .get(0),
+        })
+    }
+
+    pub async fn list_mutex_constraints(
+        &self,
+        pager: &Pager,
+    ) -> Result<Vec<MutexConstraint>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select * from role_constraint where constraint_type = $1 order by id limit $2 offset $3")
+            .await?;
+
+        let rows = pg_client
+            .query(
+                &statement,
+                &[&ConstraintType::Mutex, &pager.limit(), &pager.offset()],
+            )
+            .await?;
+
+        let mut constraints = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            let RoleConstraint {
+                id,
+                constraint_name,
+                ..
+            } = RoleConstraint::from_row_ref(row)?;
+
+            constraints.push(MutexConstraint {
+                id,
+                constraint_name,
+                roles: Vec::new(),
+            });
+        }
+
+        let ids: Vec<Id> = constraints.iter().map(|c| c.id).collect();
+
+        let statement = pg_client
+            .prepare("select constraint_mutex.constraint_id, role.* from role join constraint_mutex on role.id = constraint_mutex.role_id where constraint_mutex.constraint_id = any($1)")
+            .await?;
+
+        for row in pg_client.query(&statement, &[&ids]).await?.iter() {
+            let constraint_id: Id = row.get(0);
+            if let Some(constraint) = constraints.iter_mut().find(|c| c.id == constraint_id) {
+                constraint.roles.push(Role::from_row_ref(row)?);
+            }
+        }
+
+        Ok(constraints)
+    }
+
+    pub async fn query_mutex_constraint(&self, id: Id) -> Result<MutexConstraint, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select * from role_constraint where id = $1 and constraint_type = $2")
+            .await?;
+
+        let constraint = match pg_client
+            .query_opt(&statement, &[&id, &ConstraintType::Mutex])
+            .await?
+        {
+            Some(row) => RoleConstraint::from_row(row)?,
+            None => return Err(Kind::EMPTY_RESULT.into()),
+        };
+
+        let statement = pg_client
+            .prepare("select * from role where id in (select role_id from constraint_mutex where constraint_id = $1)")
+            .await?;
+
+        let rows = pg_client.query(&statement, &[&id]).await?;
+
+        let mut roles = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            roles.push(Role::from_row_ref(row)?);
+        }
+
+        Ok(MutexConstraint {
+            id: constraint.id,
+            constraint_name: constraint.constraint_name,
+            roles,
+        })
+    }
+
+    pub async fn create_mutex_constraint(
+        &self,
+        params: &MutexConstraintContent,
+    ) -> Result<MutexConstraint, Error> {
+        let id = {
+            let mut pg_client = self.pg_pool.get().await?;
+
+            let transaction = pg_client.transaction().await?;
+
+            let statement = transaction
+                .prepare("insert into role_constraint(constraint_name, constraint_type) values($1, $2) returning id")
+                .await?;
+
+            let id: Id = transaction
+                .query_one(
+                    &statement,
+                    &[&params.constraint_name, &ConstraintType::Mutex],
+                )
+                .await?
+                .get(0);
+
+            Self::set_mutex_roles(&transaction, id, &params.role_ids).await?;
+
+            transaction.commit().await?;
+
+            id
+        };
+
+        self.query_mutex_constraint(id).await
+    }
+
+    pub async fn update_mutex_constraint(
+        &self,
+        id: Id,
+        params: &MutexConstraintContent,
+    ) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        let statement = transaction
+            .prepare("update role_constraint set constraint_name = $1 where id = $2 and constraint_type = $3")
+            .await?;
+
+        let count = transaction
+            .execute(
+                &statement,
+                &[&params.constraint_name, &id, &ConstraintType::Mutex],
+            )
+            .await?;
+
+        if count != 1 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        let statement = transaction
+            .prepare("delete from constraint_mutex where constraint_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&id]).await?;
+
+        Self::set_mutex_roles(&transaction, id, &params.role_ids).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_mutex_constraint(&self, id: Id) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        let statement = transaction
+            .prepare("delete from constraint_mutex where constraint_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&id]).await?;
+
+        let statement = transaction
+            .prepare("delete from role_constraint where id = $1 and constraint_type = $2")
+            .await?;
+
+        let count = transaction
+            .execute(&statement, &[&id, &ConstraintType::Mutex])
+            .await?;
+
+        if count != 1 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 写入互斥约束包含的角色，如果已有用户同时拥有其中多个角色则返回错误
+    async fn set_mutex_roles(
+        transaction: &Transaction<'_>,
+        id: Id,
+        role_ids: &[Id],
+    ) -> Result<(), Error> {
+        let statement = transaction
+            .prepare("insert into constraint_mutex(constraint_id, role_id) select $1, unnest($2::bigint[]) on conflict do nothing")
+            .await?;
+
+        transaction.execute(&statement, &[&id, &role_ids]).await?;
+
+        // 防止检查过程中有新的用户角色写入；授予角色的事务在写入 user_role 之前已锁定约束表（见 `lock_constraints`），
+        // 此时持有 user_role 写锁的事务不会再等待 constraint_mutex，不会死锁
+        transaction
+            .batch_execute("lock table user_role in share mode")
+            .await?;
+
+        let statement = transaction
+            .prepare(
+                "with recursive held(user_id, role_id) as (\
+                     select user_id, role_id from user_role \
+                     union \
+                     select held.user_id, role_ext.base_id from role_ext join held on role_ext.derived_id = held.role_id\
+                 ) \
+                 select exists(\
+                     select 1 from held where role_id in (select role_id from constraint_mutex where constraint_id = $1) \
+                     group by user_id having count(distinct role_id) > 1\
+                 )",
+            )
+            .await?;
+
+        let conflict: bool = transaction.query_one(&statement, &[&id]).await?.get(0);
+
+        if conflict {
+            Err(Kind::ROLE_MUTEX_CONFLICT.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn query_base_required_count(&self) -> Result<Count, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select count(1) from role_constraint where constraint_type = $1")
+            .await?;
+
+        Ok(Count {
+            count: pg_client
+                .query_one(&statement, &[&ConstraintType::BaseRequired])
+                .await?
+                .get(0),
+        })
+    }
+
+    pub async fn list_base_required_constraints(
+        &self,
+        pager: &Pager,
+    ) -> Result<Vec<BaseRequiredConstraint>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare(
+                "select constraint_base_required.constraint_id, role_constraint.constraint_name, role.* \
+                 from role_constraint \
+                 join constraint_base_required on constraint_base_required.constraint_id = role_constraint.id \
+                 join role on role.id = constraint_base_required.role_id \
+                 order by role_constraint.id limit $1 offset $2",
+            )
+            .await?;
+
+        let rows = pg_client
+            .query(&statement, &[&pager.limit(), &pager.offset()])
+            .await?;
+
+        let mut constraints = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            constraints.push(BaseRequiredConstraint {
+                id: row.get(0),
+                constraint_name: row.get(1),
+                role: Role::from_row_ref(row)?,
+            });
+        }
+
+        Ok(constraints)
+    }
+
+    pub async fn query_base_required_constraint(
+        &self,
+        id: Id,
+    ) -> Result<BaseRequiredConstraint, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare(
+                "select constraint_base_required.constraint_id, role_constraint.constraint_name, role.* \
+                 from role_constraint \
+                 join constraint_base_required on constraint_base_required.constraint_id = role_constraint.id \
+                 join role on role.id = constraint_base_required.role_id \
+                 where role_constraint.id = $1",
+            )
+            .await?;
+
+        if let Some(row) = pg_client.query_opt(&statement, &[&id]).await? {
+            Ok(BaseRequiredConstraint {
+                id: row.get(0),
+                constraint_name: row.get(1),
+                role: Role::from_row_ref(&row)?,
+            })
+        } else {
+            Err(Kind::EMPTY_RESULT.into())
+        }
+    }
+
+    pub async fn create_base_required_constraint(
+        &self,
+        params: &BaseRequiredConstraintContent,
+    ) -> Result<BaseRequiredConstraint, Error> {
+        let id = {
+            let mut pg_client = self.pg_pool.get().await?;
+
+            let transaction = pg_client.transaction().await?;
+
+            let statement = transaction
+                .prepare("insert into role_constraint(constraint_name, constraint_type) values($1, $2) returning id")
+                .await?;
+
+            let id: Id = transaction
+                .query_one(
+                    &statement,
+                    &[&params.constraint_name, &ConstraintType::BaseRequired],
+                )
+                .await?
+                .get(0);
+
+            Self::set_base_required_role(&transaction, id, params.role_id).await?;
+
+            transaction.commit().await?;
+
+            id
+        };
+
+        self.query_base_required_constraint(id).await
+    }
+
+    pub async fn update_base_required_constraint(
+        &self,
+        id: Id,
+        params: &BaseRequiredConstraintContent,
+    ) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        let statement = transaction
+            .prepare("update role_constraint set constraint_name = $1 where id = $2 and constraint_type = $3")
+            .await?;
+
+        let count = transaction
+            .execute(
+                &statement,
+                &[&params.constraint_name, &id, &ConstraintType::BaseRequired],
+            )
+            .await?;
+
+        if count != 1 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        let statement = transaction
+            .prepare("delete from constraint_base_required where constraint_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&id]).await?;
+
+        Self::set_base_required_role(&transaction, id, params.role_id).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_base_required_constraint(&self, id: Id) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        let statement = transaction
+            .prepare("delete from constraint_base_required where constraint_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&id]).await?;
+
+        let statement = transaction
+            .prepare("delete from role_constraint where id = $1 and constraint_type = $2")
+            .await?;
+
+        let count = transaction
+            .execute(&statement, &[&id, &ConstraintType::BaseRequired])
+            .await?;
+
+        if count != 1 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 写入先决条件约束限制的角色，如果已有用户拥有该角色却缺少其父角色则返回错误
+    async fn set_base_required_role(
+        transaction: &Transaction<'_>,
+        id: Id,
+        role_id: Id,
+    ) -> Result<(), Error> {
+        let statement = transaction
+            .prepare("insert into constraint_base_required(constraint_id, role_id) values($1, $2)")
+            .await?;
+
+        transaction.execute(&statement, &[&id, &role_id]).await?;
+
+        // 防止检查过程中有用户角色变化；授予/收回角色的事务在写入 user_role 之前已锁定约束表（见 `lock_constraints`），
+        // 此时持有 user_role 写锁的事务不会再等待 constraint_base_required，不会死锁
+        transaction
+            .batch_execute("lock table user_role in share mode")
+            .await?;
+
+        check_base_required_holders(transaction, role_id).await
+    }
+}
@@ -0,0 +1,298 @@
+//! LDAP 登录及分组同步相关服务
+use crate::error::{Error, Kind};
+use crate::model::{AuthType, Id, UserInfo};
+use crate::opt::{PgPool, RedisPool};
+use crate::service::user::{
+    check_login_lock, check_user_status, record_login_failure, sync_mapped_roles, UserService,
+};
+use crate::util::http::ClientIp;
+use crate::util::ldap::{LdapDirectory, LdapUser};
+use crate::util::types::Username;
+use crate::util::user::SessionStore;
+use deadpool_redis::cmd;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::iter;
+use std::time::Duration;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+/// LDAP 登录及分组同步相关服务
+pub struct LdapService {
+    pg_pool: PgPool,
+    redis_pool: RedisPool,
+    session_store: SessionStore,
+    directory: Option<LdapDirectory>,
+}
+
+impl LdapService {
+    pub fn new(
+        pg_pool: PgPool,
+        redis_pool: RedisPool,
+        session_store: SessionStore,
+        directory: Option<LdapDirectory>,
+    ) -> Self {
+        Self {
+            pg_pool,
+            redis_pool,
+            session_store,
+            directory,
+        }
+    }
+
+    /// 自动创建用户时，用户名不可用时使用的随机后缀长度及尝试次数
+    const RANDOM_SUFFIX_LEN: usize = 6;
+    const CREATE_USER_ATTEMPTS: usize = 3;
+
+    fn directory(&self) -> Result<&LdapDirectory, Error> {
+        self.directory
+            .as_ref()
+            .ok_or_else(|| Kind::INVALID_PARAMS.into())
+    }
+
+    /// 使用目录用户名及密码登录，未配置目录服务时返回 `INVALID_PARAMS`
+    ///
+    /// 与密码登录共用失败记录及锁定规则；目录用户首次登录时，如果配置了 `auto_create` 则创建新用户并关联，
+    /// 否则返回 `LOGIN_FAILED`；关联的用户不能登录时返回错误（见 `check_user_status`）。
+    /// 登录成功后按分组同步用户的角色。
+    pub async fn sign_in(
+        &self,
+        username: &str,
+        password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        let directory = self.directory()?;
+
+        let mut redis = self.redis_pool.get().await?;
+
+        let ip_key = UserService::gen_ip_login_failure_key(client_ip);
+
+        check_login_lock(&mut redis, &ip_key, Kind::TOO_MANY_REQUESTS).await?;
+
+        let identity = username.trim().to_lowercase();
+        let linked = self.query_linked_user(&identity).await?;
+
+        let user_key = linked
+            .as_ref()
+            .map(|user_info| UserService::gen_login_failure_key(user_info.id));
+
+        if let Some(user_key) = &user_key {
+            check_login_lock(&mut redis, user_key, Kind::ACCOUNT_LOCKED).await?;
+        }
+
+        let ldap_user = match directory.authenticate(&identity, password).await? {
+            Some(ldap_user) => ldap_user,
+            None => {
+                record_login_failure(
+                    &mut redis,
+                    &ip_key,
+                    UserService::IP_LOCK_THRESHOLD,
+                    client_ip,
+                )
+                .await?;
+                if let Some(user_key) = &user_key {
+                    record_login_failure(
+                        &mut redis,
+                        user_key,
+                        UserService::ACCOUNT_LOCK_THRESHOLD,
+                        client_ip,
+                    )
+                    .await?;
+                }
+                return Err(Kind::LOGIN_FAILED.into());
+            }
+        };
+
+        if let Some(user_key) = &user_key {
+            cmd("DEL").arg(user_key).execute_async(&mut redis).await?;
+        }
+
+        // 目录中的用户名可能与输入的写法不同
+        let linked = match linked {
+            Some(user_info) if ldap_user.username == identity => Some(user_info),
+            _ => self.query_linked_user(&ldap_user.username).await?,
+        };
+
+        let user_info = match linked {
+            Some(user_info) => user_info,
+            None if directory.auto_create => self.create_user(&ldap_user).await?,
+            None => return Err(Kind::LOGIN_FAILED.into()),
+        };
+
+        check_user_status(&user_info)?;
+
+        self.sync_roles(user_info.id, directory, Some(&ldap_user))
+            .await?;
+
+        Ok(user_info)
+    }
+
+    /// 查询目录用户关联的用户
+    async fn query_linked_user(&self, identity: &str) -> Result<Option<UserInfo>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_info where id in (select user_id from user_auth where auth_type = $1 and identity = $2)")
+            .await?;
+
+        match pg
+            .query_opt(&statement, &[&AuthType::Ldap, &identity])
+            .await?
+        {
+            Some(row) => Ok(Some(UserInfo::from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 创建用户并关联目录用户
+    ///
+    /// 优先使用目录用户名作为用户名，不符合用户名格式或已被占用时追加随机后缀
+    async fn create_user(&self, ldap_user: &LdapUser) -> Result<UserInfo, Error> {
+        let base = ldap_user.username.chars().take(24).collect::<String>();
+
+        let candidates = iter::once(base.clone())
+            .chain((0..LdapService::CREATE_USER_ATTEMPTS).map(|_| {
+                let suffix: String = iter::repeat(())
+                    .map(|()| OsRng.sample(Alphanumeric))
+                    .take(LdapService::RANDOM_SUFFIX_LEN)
+                    .collect();
+                format!("{}_{}", base, suffix.to_lowercase())
+            }))
+            .filter_map(|username| Username::new(&username).ok());
+
+        let nickname = ldap_user
+            .nickname
+            .clone()
+            .unwrap_or_else(|| ldap_user.username.clone());
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        let statement = transaction
+            .prepare("insert into user_info(username, nickname) values($1, $2) on conflict (username) do nothing returning *")
+            .await?;
+
+        let mut user_info = None;
+
+        for username in candidates {
+            if let Some(row) = transaction
+                .query_opt(&statement, &[&username, &nickname])
+                .await?
+            {
+                user_info = Some(UserInfo::from_row(row)?);
+                break;
+            }
+        }
+
+        let user_info = user_info.ok_or_else(|| Error::from(Kind::DUPLICATE_VALUE))?;
+
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4)")
+            .await?;
+
+        transaction
+            .execute(
+                &statement,
+                &[&user_info.id, &AuthType::Ldap, &ldap_user.username, &""],
+            )
+            .await?;
+
+        transaction.commit().await?;
+
+        info!(
+            "已为目录用户 {} 创建用户 {}",
+            ldap_user.dn, user_info.username
+        );
+
+        Ok(user_info)
+    }
+
+    /// 按分组同步用户的角色，`ldap_user` 为 `None` 表示用户已不在目录中，收回所有映射的角色
+    ///
+    /// 收回了角色时注销用户的所有会话
+    async fn sync_roles(
+        &self,
+        user_id: Id,
+        directory: &LdapDirectory,
+        ldap_user: Option<&LdapUser>,
+    ) -> Result<(), Error> {
+        let granted = ldap_user
+            .map(|ldap_user| directory.mapped_roles(ldap_user))
+            .unwrap_or_default();
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let mut transaction = pg.transaction().await?;
+
+        let revoked = sync_mapped_roles(
+            &mut transaction,
+            user_id,
+            &granted,
+            &directory.managed_roles(),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        if revoked {
+            let mut redis = self.redis_pool.get().await?;
+            self.session_store.revoke_all(&mut redis, &user_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// 同步所有已关联目录用户的角色
+    pub async fn sync_all(&self) -> Result<(), Error> {
+        let directory = self.directory()?;
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select user_id, identity from user_auth where auth_type = $1")
+            .await?;
+
+        let users = pg
+            .query(&statement, &[&AuthType::Ldap])
+            .await?
+            .iter()
+            .map(|row| (row.get::<_, Id>(0), row.get::<_, String>(1)))
+            .collect::<Vec<_>>();
+
+        drop(pg);
+
+        for (user_id, identity) in users.iter() {
+            let ldap_user = directory.find_user(identity).await?;
+
+            if ldap_user.is_none() {
+                warn!("目录用户 {} 已不存在，收回其映射的角色", identity);
+            }
+
+            // 单个用户同步失败（如角色约束冲突）不影响其他用户
+            if let Err(e) = self
+                .sync_roles(*user_id, directory, ldap_user.as_ref())
+                .await
+            {
+                error!("同步目录用户 {} 的角色时发生错误: {}", identity, e);
+            }
+        }
+
+        info!("已同步 {} 个目录用户的角色", users.len());
+
+        Ok(())
+    }
+
+    /// 每隔 `interval` 同步一次所有目录用户的角色，在 `main.rs` 中启动
+    pub async fn run_sync(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.sync_all().await {
+                error!("同步目录用户的角色时发生错误: {}", e);
+            }
+        }
+    }
+}
@@ -1,21 +1,51 @@
 //! 服务（Service）的实现，使用 deadpool 连接池访问 PostgreSQL / Redis
-use crate::opt::{PgPool, RedisPool};
+use crate::opt::{AvatarOpts, PgPool, RedisPool, SenderOpts};
+use crate::service::avatar::AvatarService;
+use crate::service::constraint::ConstraintService;
+use crate::service::ldap::LdapService;
+use crate::service::oidc::OidcService;
 use crate::service::permission::PermissionService;
 use crate::service::role::RoleService;
+use crate::service::token::TokenService;
+use crate::service::totp::TotpService;
 use crate::service::user::UserService;
+use crate::util::crypto::PasswordHasher;
+use crate::util::ldap::LdapDirectory;
+use crate::util::oidc::OidcProvider;
+use crate::util::types::PasswordPolicy;
+use crate::util::user::SessionStore;
 use actix_service::ServiceFactory;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::App;
+use std::collections::HashMap;
 
+pub(crate) mod avatar;
+pub(crate) mod constraint;
+pub(crate) mod ldap;
+pub(crate) mod oidc;
 pub(crate) mod permission;
 pub(crate) mod role;
+pub(crate) mod token;
+pub(crate) mod totp;
 pub(crate) mod user;
 
+/// 除连接池外，创建各个服务所需的配置，由 `main.rs` 从 `Opts` 中组装
+#[derive(Clone)]
+pub struct ServiceOpts {
+    pub session_store: SessionStore,
+    pub sender: SenderOpts,
+    pub password_policy: PasswordPolicy,
+    pub hasher: PasswordHasher,
+    pub oidc: HashMap<String, OidcProvider>,
+    pub ldap: Option<LdapDirectory>,
+    pub avatar: AvatarOpts,
+}
+
 /// 加载所有服务，已为 `actix_web::app:App` 实现这个 `trait`，
 /// 详见 `main.rs` 中对 `load_all_services` 函数的调用
 pub trait LoadAllServices {
-    fn load_all_services(self, pg_pool: PgPool, redis_pool: RedisPool) -> Self;
+    fn load_all_services(self, pg_pool: PgPool, redis_pool: RedisPool, opts: ServiceOpts) -> Self;
 }
 
 impl<T, B> LoadAllServices for App<T, B>
@@ -29,9 +59,37 @@ where
         InitError = (),
     >,
 {
-    fn load_all_services(self, pg_pool: PgPool, redis_pool: RedisPool) -> Self {
-        self.data(UserService::new(pg_pool.clone(), redis_pool))
-            .data(RoleService::new(pg_pool.clone()))
-            .data(PermissionService::new(pg_pool))
+    fn load_all_services(self, pg_pool: PgPool, redis_pool: RedisPool, opts: ServiceOpts) -> Self {
+        let ServiceOpts {
+            session_store,
+            sender,
+            password_policy,
+            hasher,
+            oidc,
+            ldap,
+            avatar,
+        } = opts;
+
+        self.data(UserService::new(
+            pg_pool.clone(),
+            redis_pool.clone(),
+            session_store.clone(),
+            sender,
+            password_policy,
+            hasher,
+        ))
+        .data(TotpService::new(pg_pool.clone(), redis_pool.clone()))
+        .data(TokenService::new(pg_pool.clone()))
+        .data(OidcService::new(pg_pool.clone(), redis_pool.clone(), oidc))
+        .data(LdapService::new(
+            pg_pool.clone(),
+            redis_pool.clone(),
+            session_store.clone(),
+            ldap,
+        ))
+        .data(RoleService::new(pg_pool.clone(), redis_pool, session_store))
+        .data(PermissionService::new(pg_pool.clone()))
+        .data(AvatarService::new(pg_pool.clone(), avatar))
+        .data(ConstraintService::new(pg_pool))
     }
 }
@@ -0,0 +1,338 @@
+//! OpenID Connect 登录相关服务
+use crate::error::{Error, Kind};
+use crate::model::{AuthType, Id, OidcAuthorization, UserInfo};
+use crate::opt::{PgPool, RedisPool};
+use crate::service::user::{check_user_status, insert_auth_history, lock_users, sync_mapped_roles};
+use crate::util::http::ClientIp;
+use crate::util::oidc::{random_string, IdTokenClaims, OidcProvider};
+use crate::util::types::Username;
+use deadpool_redis::cmd;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+/// 已发起、等待身份提供方回调的授权请求
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+    /// 发起关联外部身份的用户，登录时为空
+    user_id: Option<Id>,
+}
+
+/// OpenID Connect 登录相关服务
+pub struct OidcService {
+    pg_pool: PgPool,
+    redis_pool: RedisPool,
+    providers: HashMap<String, OidcProvider>,
+}
+
+impl OidcService {
+    pub fn new(
+        pg_pool: PgPool,
+        redis_pool: RedisPool,
+        providers: HashMap<String, OidcProvider>,
+    ) -> Self {
+        Self {
+            pg_pool,
+            redis_pool,
+            providers,
+        }
+    }
+
+    /// 等待回调的授权请求，以 `state` 为 Key
+    const STATE_KEY: &'static str = "user:oidcState";
+    const STATE_EXPIRE: u64 = 600;
+    const STATE_LEN: usize = 32;
+    const NONCE_LEN: usize = 32;
+    /// PKCE 的 `code_verifier` 长度，须在 43 到 128 之间
+    const CODE_VERIFIER_LEN: usize = 64;
+    /// 自动创建用户时，随机用户名的后缀长度及尝试次数
+    const RANDOM_USERNAME_LEN: usize = 8;
+    const CREATE_USER_ATTEMPTS: usize = 3;
+
+    fn provider(&self, name: &str) -> Result<&OidcProvider, Error> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| Kind::EMPTY_RESULT.into())
+    }
+
+    /// 所有已配置的身份提供方名称
+    pub fn list_providers(&self) -> Vec<String> {
+        self.providers.keys().cloned().sorted().collect()
+    }
+
+    /// 发起授权请求，`user_id` 不为空时表示为该用户关联外部身份
+    pub async fn authorize(
+        &self,
+        provider_name: &str,
+        user_id: Option<Id>,
+    ) -> Result<OidcAuthorization, Error> {
+        let provider = self.provider(provider_name)?;
+
+        let state = random_string(OidcService::STATE_LEN);
+        let pending = PendingAuthorization {
+            provider: provider_name.to_owned(),
+            code_verifier: random_string(OidcService::CODE_VERIFIER_LEN),
+            nonce: random_string(OidcService::NONCE_LEN),
+            user_id,
+        };
+
+        let authorization_url = provider
+            .authorization_url(&state, &pending.nonce, &pending.code_verifier)
+            .await?;
+
+        let mut redis = self.redis_pool.get().await?;
+
+        cmd("SETEX")
+            .arg(format!("{}:{}", OidcService::STATE_KEY, state))
+            .arg(OidcService::STATE_EXPIRE)
+            .arg(serde_json::to_string(&pending).map_err(|e| Kind::DATA_FORMAT.with_detail(e))?)
+            .execute_async(&mut redis)
+            .await?;
+
+        Ok(OidcAuthorization {
+            authorization_url,
+            state,
+        })
+    }
+
+    /// 处理身份提供方的回调，`state` 只能使用一次，返回发起授权时的请求及 ID Token 中的声明
+    async fn complete(
+        &self,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+    ) -> Result<(PendingAuthorization, IdTokenClaims), Error> {
+        let key = format!("{}:{}", OidcService::STATE_KEY, state);
+
+        let mut redis = self.redis_pool.get().await?;
+
+        let pending: Option<String> = cmd("GET").arg(&key).query_async(&mut redis).await?;
+        // 只有成功删除的请求才能继续，防止同一个 state 被并发使用
+        let deleted: u64 = cmd("DEL").arg(&key).query_async(&mut redis).await?;
+
+        let pending = match pending {
+            Some(pending) if deleted > 0 => serde_json::from_str::<PendingAuthorization>(&pending)
+                .map_err(|e| Kind::DATA_FORMAT.with_detail(e))?,
+            _ => return Err(Kind::OIDC_LOGIN_FAILED.into()),
+        };
+
+        if pending.provider != provider_name {
+            return Err(Kind::OIDC_LOGIN_FAILED.into());
+        }
+
+        let provider = self.provider(provider_name)?;
+        let id_token = provider.exchange_code(code, &pending.code_verifier).await?;
+        let claims = provider.verify_id_token(&id_token, &pending.nonce).await?;
+
+        Ok((pending, claims))
+    }
+
+    /// 使用外部身份登录
+    ///
+    /// 外部身份未关联用户时，如果身份提供方配置了 `auto_create` 则创建新用户并关联，否则返回 `OIDC_LOGIN_FAILED`；
+    /// 关联的用户不能登录时返回错误（见 `check_user_status`）；配置了 `roles_claim` 时，为用户授予映射到的角色
+    pub async fn sign_in(
+        &self,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+    ) -> Result<UserInfo, Error> {
+        let (_, claims) = self.complete(provider_name, code, state).await?;
+        let provider = self.provider(provider_name)?;
+        let identity = format!("{}:{}", provider_name, claims.subject());
+
+        let user_info = match self.query_linked_user(&identity).await? {
+            Some(user_info) => user_info,
+            None if provider.auto_create => {
+                self.create_user(provider_name, provider, &identity, &claims)
+                    .await?
+            }
+            None => return Err(Kind::OIDC_LOGIN_FAILED.into()),
+        };
+
+        check_user_status(&user_info)?;
+
+        self.grant_mapped_roles(user_info.id, provider, &claims)
+            .await?;
+
+        Ok(user_info)
+    }
+
+    /// 为已登录用户关联外部身份，发起授权的须是同一用户
+    ///
+    /// 外部身份已关联其他用户，或者该用户已关联过外部身份时返回 `DUPLICATE_VALUE`
+    pub async fn link(
+        &self,
+        user_id: Id,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let (pending, claims) = self.complete(provider_name, code, state).await?;
+
+        if pending.user_id != Some(user_id) {
+            return Err(Kind::OIDC_LOGIN_FAILED.into());
+        }
+
+        let identity = format!("{}:{}", provider_name, claims.subject());
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[user_id]).await?;
+
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4) on conflict do nothing")
+            .await?;
+
+        if transaction
+            .execute(&statement, &[&user_id, &AuthType::Oidc, &identity, &""])
+            .await?
+            == 0
+        {
+            return Err(Kind::DUPLICATE_VALUE.into());
+        }
+
+        insert_auth_history(
+            &transaction,
+            user_id,
+            AuthType::Oidc,
+            None,
+            Some(&identity),
+            Some(client_ip),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 查询外部身份关联的用户
+    async fn query_linked_user(&self, identity: &str) -> Result<Option<UserInfo>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_info where id in (select user_id from user_auth where auth_type = $1 and identity = $2)")
+            .await?;
+
+        match pg
+            .query_opt(&statement, &[&AuthType::Oidc, &identity])
+            .await?
+        {
+            Some(row) => Ok(Some(UserInfo::from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 创建用户并关联外部身份
+    ///
+    /// 优先使用 `username_claim` 声明作为用户名，不符合用户名格式或已被占用时使用 `{身份提供方}_{随机字符串}`
+    async fn create_user(
+        &self,
+        provider_name: &str,
+        provider: &OidcProvider,
+        identity: &str,
+        claims: &IdTokenClaims,
+    ) -> Result<UserInfo, Error> {
+        let preferred = claims
+            .string(&provider.username_claim)
+            .and_then(|username| Username::new(username).ok());
+
+        let candidates =
+            preferred
+                .into_iter()
+                .chain((0..OidcService::CREATE_USER_ATTEMPTS).filter_map(|_| {
+                    let suffix = random_string(OidcService::RANDOM_USERNAME_LEN).to_lowercase();
+                    let username = format!("{}_{}", provider_name, suffix);
+                    // 身份提供方名称过长时截断
+                    let start = username.chars().count().saturating_sub(32);
+                    Username::new(&username.chars().skip(start).collect::<String>()).ok()
+                }));
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        let statement = transaction
+            .prepare("insert into user_info(username, nickname) values($1, $2) on conflict (username) do nothing returning *")
+            .await?;
+
+        let mut user_info = None;
+
+        for username in candidates {
+            let nickname = claims
+                .string(&provider.nickname_claim)
+                .map(str::to_owned)
+                .unwrap_or_else(|| username.to_string());
+
+            if let Some(row) = transaction
+                .query_opt(&statement, &[&username, &nickname])
+                .await?
+            {
+                user_info = Some(UserInfo::from_row(row)?);
+                break;
+            }
+        }
+
+        let user_info = user_info.ok_or_else(|| Error::from(Kind::DUPLICATE_VALUE))?;
+
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4)")
+            .await?;
+
+        transaction
+            .execute(
+                &statement,
+                &[&user_info.id, &AuthType::Oidc, &identity, &""],
+            )
+            .await?;
+
+        transaction.commit().await?;
+
+        info!("已为外部身份 {} 创建用户 {}", identity, user_info.username);
+
+        Ok(user_info)
+    }
+
+    /// 为用户授予 `roles_claim` 映射到的角色，用户已拥有的角色会被忽略
+    ///
+    /// 每个角色单独授予，违反约束或不存在的角色只记录警告，不影响登录
+    async fn grant_mapped_roles(
+        &self,
+        user_id: Id,
+        provider: &OidcProvider,
+        claims: &IdTokenClaims,
+    ) -> Result<(), Error> {
+        let roles_claim = match &provider.roles_claim {
+            Some(roles_claim) => roles_claim,
+            None => return Ok(()),
+        };
+
+        let role_names = claims
+            .strings(roles_claim)
+            .into_iter()
+            .filter_map(|value| provider.role_mapping.get(value).cloned())
+            .collect::<Vec<_>>();
+
+        if role_names.is_empty() {
+            return Ok(());
+        }
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let mut transaction = pg.transaction().await?;
+
+        sync_mapped_roles(&mut transaction, user_id, &role_names, &[]).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
@@ -1,18 +1,31 @@
 //! 角色相关服务
 use crate::error::{Error, Kind};
-use crate::model::{Count, Id, Role, RoleContent};
-use crate::opt::PgPool;
+use crate::model::{Count, Id, Permission, Role, RoleContent, UserInfo};
+use crate::opt::{PgPool, RedisPool};
+use crate::service::constraint::{
+    check_base_required_holders, check_inherited_mutex, lock_constraints,
+};
+use crate::service::user::{delete_user_roles, insert_user_role, lock_roles, lock_users};
 use crate::util::db::Pager;
+use crate::util::user::SessionStore;
+use deadpool_postgres::Transaction;
+use itertools::Itertools;
 use tokio_pg_mapper::FromTokioPostgresRow;
 
 /// 角色相关服务
 pub struct RoleService {
     pg_pool: PgPool,
+    redis_pool: RedisPool,
+    session_store: SessionStore,
 }
 
 impl RoleService {
-    pub fn new(pg_pool: PgPool) -> Self {
-        Self { pg_pool }
+    pub fn new(pg_pool: PgPool, redis_pool: RedisPool, session_store: SessionStore) -> Self {
+        Self {
+            pg_pool,
+            redis_pool,
+            session_store,
+        }
     }
 
     pub async fn query_roles_count(&self) -> Result<Count, Error> {
@@ -116,4 +129,578 @@ impl RoleService {
             Err(Kind::EMPTY_RESULT.into())
         }
     }
+
+    /// 查询角色的所有直接父角色
+    pub async fn list_base_roles(&self, id: Id) -> Result<Vec<Role>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select * from role where id in (select base_id from role_ext where derived_id = $1)")
+            .await?;
+
+        let rows = pg_client.query(&statement, &[&id]).await?;
+
+        let mut roles = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            roles.push(Role::from_row_ref(row)?);
+        }
+
+        Ok(roles)
+    }
+
+    /// 为派生角色 `derived_id` 添加父角色 `base_id`，添加后不能使继承关系成环，
+    /// 也不能使拥有派生角色的用户违反互斥约束或先决条件约束
+    pub async fn add_base_role(&self, derived_id: Id, base_id: Id) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        insert_role_ext(&transaction, derived_id, base_id).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 删除派生角色 `derived_id` 与父角色 `base_id` 的继承关系，删除后拥有派生角色的用户仍须满足先决条件约束
+    pub async fn remove_base_role(&self, derived_id: Id, base_id: Id) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        delete_role_ext(&transaction, derived_id, base_id).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 查询角色的所有有效权限，即角色自身及其所有祖先角色权限的并集
+    pub async fn query_role_perm(&self, id: Id) -> Result<Vec<Permission>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare(
+                "with recursive roles(id) as (\
+                     select $1::bigint \
+                     union \
+                     select role_ext.base_id from role_ext join roles on role_ext.derived_id = roles.id\
+                 ) \
+                 select * from permission where id in (select permission_id from role_permission where role_id in (select id from roles))",
+            )
+            .await?;
+
+        let rows = pg_client.query(&statement, &[&id]).await?;
+
+        let mut perms = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            perms.push(Permission::from_row_ref(row)?);
+        }
+
+        Ok(perms)
+    }
+
+    /// 查询拥有该角色的所有用户
+    pub async fn list_role_users(&self, id: Id) -> Result<Vec<UserInfo>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select * from user_info where id in (select user_id from user_role where role_id = $1)")
+            .await?;
+
+        let rows = pg_client.query(&statement, &[&id]).await?;
+
+        let mut users = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            users.push(UserInfo::from_row_ref(row)?);
+        }
+
+        Ok(users)
+    }
+
+    /// 将角色批量授予多个用户，任一用户授予失败则全部不生效
+    pub async fn grant_role_to_users(&self, id: Id, user_ids: &[Id]) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        lock_constraints(&transaction).await?;
+        lock_users(&transaction, user_ids).await?;
+        lock_roles(&transaction, &[id]).await?;
+
+        for user_id in user_ids {
+            insert_user_role(&transaction, *user_id, id).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 批量收回多个用户的该角色，未拥有该角色的用户会被忽略，被收回角色的用户的所有会话将被注销
+    pub async fn revoke_role_from_users(
+        &self,
+        id: Id,
+        user_ids: &[Id],
+        cascade: bool,
+    ) -> Result<(), Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        lock_constraints(&transaction).await?;
+        lock_users(&transaction, user_ids).await?;
+
+        let mut revoked = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            if delete_user_roles(&transaction, *user_id, &[id], cascade).await? > 0 {
+                revoked.push(*user_id);
+            }
+        }
+
+        transaction.commit().await?;
+
+        let mut redis = self.redis_pool.get().await?;
+        for user_id in revoked.iter() {
+            self.session_store.revoke_all(&mut redis, user_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// 查询角色直接拥有的所有权限，不包括继承的权限
+    pub async fn list_role_permissions(&self, id: Id) -> Result<Vec<Permission>, Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("select * from permission where id in (select permission_id from role_permission where role_id = $1)")
+            .await?;
+
+        let rows = pg_client.query(&statement, &[&id]).await?;
+
+        let mut perms = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            perms.push(Permission::from_row_ref(row)?);
+        }
+
+        Ok(perms)
+    }
+
+    /// 为角色授予权限，不能超过角色的最大权限数
+    pub async fn grant_permission(&self, id: Id, permission_id: Id) -> Result<Permission, Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        lock_roles(&transaction, &[id]).await?;
+
+        let statement = transaction
+            .prepare("select * from permission where id = $1")
+            .await?;
+
+        let permission = match transaction.query_opt(&statement, &[&permission_id]).await? {
+            Some(row) => Permission::from_row(row)?,
+            None => return Err(Kind::EMPTY_RESULT.into()),
+        };
+
+        let statement = transaction
+            .prepare("insert into role_permission(role_id, permission_id) values($1, $2) on conflict do nothing")
+            .await?;
+
+        transaction
+            .execute(&statement, &[&id, &permission_id])
+            .await?;
+
+        Self::check_max_permission(&transaction, id).await?;
+
+        transaction.commit().await?;
+
+        Ok(permission)
+    }
+
+    /// 收回角色的权限
+    pub async fn revoke_permission(&self, id: Id, permission_id: Id) -> Result<(), Error> {
+        let pg_client = self.pg_pool.get().await?;
+
+        let statement = pg_client
+            .prepare("delete from role_permission where role_id = $1 and permission_id = $2")
+            .await?;
+
+        let count = pg_client
+            .execute(&statement, &[&id, &permission_id])
+            .await?;
+
+        if count == 1 {
+            Ok(())
+        } else {
+            Err(Kind::EMPTY_RESULT.into())
+        }
+    }
+
+    /// 将角色的权限替换为 `permission_ids`，不能超过角色的最大权限数
+    pub async fn replace_permissions(
+        &self,
+        id: Id,
+        permission_ids: &[Id],
+    ) -> Result<Vec<Permission>, Error> {
+        let mut pg_client = self.pg_pool.get().await?;
+
+        let transaction = pg_client.transaction().await?;
+
+        lock_roles(&transaction, &[id]).await?;
+
+        let statement = transaction
+            .prepare("select * from permission where id = any($1)")
+            .await?;
+
+        let rows = transaction.query(&statement, &[&permission_ids]).await?;
+
+        if rows.len() != permission_ids.iter().unique().count() {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        let mut perms = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            perms.push(Permission::from_row_ref(row)?);
+        }
+
+        let statement = transaction
+            .prepare("delete from role_permission where role_id = $1 and permission_id <> all($2)")
+            .await?;
+
+        transaction
+            .execute(&statement, &[&id, &permission_ids])
+            .await?;
+
+        let statement = transaction
+            .prepare("insert into role_permission(role_id, permission_id) select $1, unnest($2::bigint[]) on conflict do nothing")
+            .await?;
+
+        transaction
+            .execute(&statement, &[&id, &permission_ids])
+            .await?;
+
+        Self::check_max_permission(&transaction, id).await?;
+
+        transaction.commit().await?;
+
+        Ok(perms)
+    }
+
+    /// 检查角色的权限数是否超过最大权限数，调用前须已锁定角色
+    async fn check_max_permission(transaction: &Transaction<'_>, id: Id) -> Result<(), Error> {
+        let statement = transaction
+            .prepare("select (select max_permission from role where id = $1) < (select count(1) from role_permission where role_id = $1)")
+            .await?;
+
+        let exceeded: Option<bool> = transaction.query_one(&statement, &[&id]).await?.get(0);
+
+        if let Some(true) = exceeded {
+            Err(Kind::ROLE_PERMISSION_LIMIT.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// 在事务中为派生角色 `derived_id` 添加父角色 `base_id`
+///
+/// 依次锁定约束表、`role_ext` 及 `user_role`，与授予角色及修改约束时的加锁顺序一致
+async fn insert_role_ext(
+    transaction: &Transaction<'_>,
+    derived_id: Id,
+    base_id: Id,
+) -> Result<(), Error> {
+    if derived_id == base_id {
+        return Err(Kind::ROLE_INHERITANCE_CYCLE.into());
+    }
+
+    lock_constraints(transaction).await?;
+
+    // 防止并发添加继承关系时绕过成环检查
+    transaction
+        .batch_execute("lock table role_ext in share row exclusive mode")
+        .await?;
+
+    // 如果派生角色已经是父角色的祖先，添加后就会成环
+    let statement = transaction
+        .prepare(
+            "with recursive ancestors(id) as (\
+                 select $1::bigint \
+                 union \
+                 select role_ext.base_id from role_ext join ancestors on role_ext.derived_id = ancestors.id\
+             ) \
+             select exists(select 1 from ancestors where id = $2)",
+        )
+        .await?;
+
+    let cyclic: bool = transaction
+        .query_one(&statement, &[&base_id, &derived_id])
+        .await?
+        .get(0);
+
+    if cyclic {
+        return Err(Kind::ROLE_INHERITANCE_CYCLE.into());
+    }
+
+    let statement = transaction
+        .prepare("insert into role_ext(base_id, derived_id) values($1, $2)")
+        .await?;
+
+    transaction
+        .execute(&statement, &[&base_id, &derived_id])
+        .await?;
+
+    // 防止检查过程中有用户角色变化
+    transaction
+        .batch_execute("lock table user_role in share mode")
+        .await?;
+
+    // 拥有派生角色的用户会间接获得新的父角色
+    check_inherited_mutex(transaction, derived_id).await?;
+    check_base_required_holders(transaction, derived_id).await
+}
+
+/// 在事务中删除派生角色 `derived_id` 与父角色 `base_id` 的继承关系，加锁顺序与 `insert_role_ext` 一致
+async fn delete_role_ext(
+    transaction: &Transaction<'_>,
+    derived_id: Id,
+    base_id: Id,
+) -> Result<(), Error> {
+    lock_constraints(transaction).await?;
+
+    transaction
+        .batch_execute("lock table role_ext in share row exclusive mode")
+        .await?;
+
+    let statement = transaction
+        .prepare("delete from role_ext where base_id = $1 and derived_id = $2")
+        .await?;
+
+    if transaction
+        .execute(&statement, &[&base_id, &derived_id])
+        .await?
+        != 1
+    {
+        return Err(Kind::EMPTY_RESULT.into());
+    }
+
+    // 防止检查过程中有用户角色变化
+    transaction
+        .batch_execute("lock table user_role in share mode")
+        .await?;
+
+    check_base_required_holders(transaction, derived_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::Opts;
+
+    /// 测试在事务中执行，结束时回滚
+    async fn test_pool() -> Option<PgPool> {
+        Some(Opts::open_test().await?.db.create_pool().unwrap())
+    }
+
+    async fn create_role(transaction: &Transaction<'_>, name: &str) -> Id {
+        transaction
+            .query_one("insert into role(name) values($1) returning id", &[&name])
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    async fn create_user(transaction: &Transaction<'_>, name: &str, role_ids: &[Id]) -> Id {
+        let user_id = transaction
+            .query_one(
+                "insert into user_info(username, nickname) values($1, $1) returning id",
+                &[&name],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        for role_id in role_ids {
+            transaction
+                .execute(
+                    "insert into user_role(user_id, role_id) values($1, $2)",
+                    &[&user_id, role_id],
+                )
+                .await
+                .unwrap();
+        }
+
+        user_id
+    }
+
+    async fn create_constraint(
+        transaction: &Transaction<'_>,
+        name: &str,
+        constraint_type: &str,
+        role_ids: &[Id],
+    ) {
+        let constraint_id: Id = transaction
+            .query_one(
+                "insert into role_constraint(constraint_name, constraint_type) values($1, $2::text::\"ConstraintType\") returning id",
+                &[&name, &constraint_type],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        let table = match constraint_type {
+            "Mutex" => "constraint_mutex",
+            _ => "constraint_base_required",
+        };
+
+        for role_id in role_ids {
+            transaction
+                .execute(
+                    format!(
+                        "insert into {}(constraint_id, role_id) values($1, $2)",
+                        table
+                    )
+                    .as_str(),
+                    &[&constraint_id, role_id],
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn has_ext(transaction: &Transaction<'_>, derived_id: Id, base_id: Id) -> bool {
+        transaction
+            .query_one(
+                "select exists(select 1 from role_ext where base_id = $1 and derived_id = $2)",
+                &[&base_id, &derived_id],
+            )
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    /// 在保存点中修改继承关系，失败时回滚到保存点
+    macro_rules! try_ext {
+        ($transaction:expr, $f:ident($derived:expr, $base:expr)) => {{
+            let savepoint = $transaction.transaction().await.unwrap();
+            let result = $f(&savepoint, $derived, $base).await;
+            if result.is_ok() {
+                savepoint.commit().await.unwrap();
+            } else {
+                savepoint.rollback().await.unwrap();
+            }
+            result.map_err(|e| e.kind().code())
+        }};
+    }
+
+    #[actix_rt::test]
+    async fn add_base_role_rejects_mutex_conflict() {
+        let pool = match test_pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut pg = pool.get().await.unwrap();
+        let mut transaction = pg.transaction().await.unwrap();
+
+        // ext_mutex_a 与 ext_mutex_b 互斥，ext_leaf 继承 ext_derived
+        let mutex_a = create_role(&transaction, "ext_mutex_a").await;
+        let mutex_b = create_role(&transaction, "ext_mutex_b").await;
+        let derived = create_role(&transaction, "ext_derived").await;
+        let leaf = create_role(&transaction, "ext_leaf").await;
+        let other = create_role(&transaction, "ext_other").await;
+        create_constraint(&transaction, "ext_mutex", "Mutex", &[mutex_a, mutex_b]).await;
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(leaf, derived)),
+            Ok(())
+        );
+
+        // 用户通过 ext_leaf 间接拥有 ext_derived，同时直接拥有 ext_mutex_b
+        create_user(&transaction, "ext_test", &[leaf, mutex_b]).await;
+
+        // 添加后用户会间接拥有互斥的 ext_mutex_a
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(derived, mutex_a)),
+            Err(Kind::ROLE_MUTEX_CONFLICT.code())
+        );
+        assert!(!has_ext(&transaction, derived, mutex_a).await);
+
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(derived, other)),
+            Ok(())
+        );
+        assert!(has_ext(&transaction, derived, other).await);
+
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(other, leaf)),
+            Err(Kind::ROLE_INHERITANCE_CYCLE.code())
+        );
+
+        transaction.rollback().await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn base_role_changes_keep_base_required() {
+        let pool = match test_pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut pg = pool.get().await.unwrap();
+        let mut transaction = pg.transaction().await.unwrap();
+
+        // ext_required 有先决条件约束，用户直接拥有其父角色 ext_base_a
+        let base_a = create_role(&transaction, "ext_base_a").await;
+        let base_b = create_role(&transaction, "ext_base_b").await;
+        let required = create_role(&transaction, "ext_required").await;
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(required, base_a)),
+            Ok(())
+        );
+        create_constraint(
+            &transaction,
+            "ext_base_required",
+            "BaseRequired",
+            &[required],
+        )
+        .await;
+        let user_id = create_user(&transaction, "ext_test", &[base_a, required]).await;
+
+        // 用户未直接拥有新的父角色 ext_base_b
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(required, base_b)),
+            Err(Kind::BASE_ROLE_REQUIRED.code())
+        );
+        assert!(!has_ext(&transaction, required, base_b).await);
+
+        // 直接拥有新的父角色后可以添加
+        transaction
+            .execute(
+                "insert into user_role(user_id, role_id) values($1, $2)",
+                &[&user_id, &base_b],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            try_ext!(transaction, insert_role_ext(required, base_b)),
+            Ok(())
+        );
+
+        // 删除父角色后用户仍满足先决条件约束
+        assert_eq!(
+            try_ext!(transaction, delete_role_ext(required, base_a)),
+            Ok(())
+        );
+        assert!(!has_ext(&transaction, required, base_a).await);
+        assert_eq!(
+            try_ext!(transaction, delete_role_ext(required, base_a)),
+            Err(Kind::EMPTY_RESULT.code())
+        );
+
+        transaction.rollback().await.unwrap();
+    }
 }
@@ -0,0 +1,136 @@
+//! 个人访问令牌相关服务
+use crate::error::{Error, Kind};
+use crate::model::{AccessToken, CreateAccessTokenParams, CreatedAccessToken, Id, Permission};
+use crate::opt::PgPool;
+use crate::util::crypto::{generate_access_token, hash_access_token};
+use chrono::Utc;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+/// 个人访问令牌相关服务
+pub struct TokenService {
+    pg_pool: PgPool,
+}
+
+impl TokenService {
+    pub fn new(pg_pool: PgPool) -> Self {
+        Self { pg_pool }
+    }
+
+    const MAX_NAME_LEN: usize = 64;
+
+    /// 查询用户的所有个人访问令牌（包括已过期的）
+    pub async fn list_tokens(&self, user_id: Id) -> Result<Vec<AccessToken>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select id, user_id, name, scopes, expire_time, last_used_time, create_time from user_token where user_id = $1 order by id")
+            .await?;
+
+        let rows = pg.query(&statement, &[&user_id]).await?;
+
+        let mut tokens = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            tokens.push(AccessToken::from_row_ref(row)?);
+        }
+
+        Ok(tokens)
+    }
+
+    /// 创建个人访问令牌，`permissions` 为用户当前拥有的权限
+    ///
+    /// `scopes` 中包含用户未拥有的权限时返回 `NO_PERMISSION`，名称重复时返回 `DUPLICATE_VALUE`
+    pub async fn create_token(
+        &self,
+        user_id: Id,
+        params: &CreateAccessTokenParams,
+        permissions: &[Permission],
+    ) -> Result<CreatedAccessToken, Error> {
+        let name = params.name.trim();
+        if name.is_empty() || name.chars().count() > TokenService::MAX_NAME_LEN {
+            return Err(Kind::INVALID_PARAMS.into());
+        }
+
+        if params
+            .expire_time
+            .is_some_and(|t| t <= Utc::now().naive_utc())
+        {
+            return Err(Kind::INVALID_PARAMS.into());
+        }
+
+        if !params
+            .scopes
+            .iter()
+            .all(|scope| permissions.iter().any(|p| &p.permission_name == scope))
+        {
+            return Err(Kind::NO_PERMISSION.into());
+        }
+
+        let token = generate_access_token();
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("insert into user_token(user_id, name, token_hash, scopes, expire_time) values($1, $2, $3, $4, $5) returning id, user_id, name, scopes, expire_time, last_used_time, create_time")
+            .await?;
+
+        let row = pg
+            .query_one(
+                &statement,
+                &[
+                    &user_id,
+                    &name,
+                    &hash_access_token(&token),
+                    &params.scopes,
+                    &params.expire_time,
+                ],
+            )
+            .await?;
+
+        Ok(CreatedAccessToken {
+            info: AccessToken::from_row(row)?,
+            token,
+        })
+    }
+
+    /// 删除用户的个人访问令牌，令牌不存在时返回 `EMPTY_RESULT`
+    pub async fn delete_token(&self, user_id: Id, id: Id) -> Result<(), Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("delete from user_token where id = $1 and user_id = $2")
+            .await?;
+
+        if pg.execute(&statement, &[&id, &user_id]).await? == 0 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        Ok(())
+    }
+
+    /// 校验 `Authorization: Bearer` 中的令牌，令牌不存在、已过期或其用户不是 `Active` 状态时返回 `INVALID_ACCESS_TOKEN`
+    ///
+    /// 最近使用时间至多每分钟更新一次
+    pub async fn authenticate(&self, token: &str) -> Result<AccessToken, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select id, user_id, name, scopes, expire_time, last_used_time, create_time from user_token where token_hash = $1 and (expire_time is null or expire_time > now()) and user_id in (select id from user_info where status = 'Active')")
+            .await?;
+
+        let access_token = match pg
+            .query_opt(&statement, &[&hash_access_token(token)])
+            .await?
+        {
+            Some(row) => AccessToken::from_row(row)?,
+            None => return Err(Kind::INVALID_ACCESS_TOKEN.into()),
+        };
+
+        let statement = pg
+            .prepare("update user_token set last_used_time = now() where id = $1 and (last_used_time is null or last_used_time < now() - interval '1 minute')")
+            .await?;
+
+        pg.execute(&statement, &[&access_token.id]).await?;
+
+        Ok(access_token)
+    }
+}
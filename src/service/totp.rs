@@ -0,0 +1,381 @@
+//! 两步验证（TOTP）相关服务
+use crate::error::{Error, Kind};
+use crate::model::{Id, TotpEnrollment};
+use crate::opt::{PgPool, RedisPool};
+use crate::service::user::{check_login_lock, record_login_failure, UserService};
+use crate::util::http::ClientIp;
+use crate::util::limit::Limit;
+use crate::util::totp;
+use deadpool_redis::cmd;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::iter;
+
+/// 两步验证相关服务
+pub struct TotpService {
+    pg_pool: PgPool,
+    redis_pool: RedisPool,
+}
+
+impl TotpService {
+    pub fn new(pg_pool: PgPool, redis_pool: RedisPool) -> Self {
+        Self {
+            pg_pool,
+            redis_pool,
+        }
+    }
+
+    /// 待确认的 TOTP 密钥
+    const ENROLL_KEY: &'static str = "user:totpEnroll";
+    const ENROLL_EXPIRE: u64 = 600;
+    /// 最近一次使用的时间步，防止同一个一次性密码被重复使用
+    const LAST_STEP_KEY: &'static str = "user:totpLastStep";
+    /// 已通过第一步验证、等待输入一次性密码的登录
+    const PENDING_KEY: &'static str = "user:totpPending";
+    const PENDING_EXPIRE: u64 = 300;
+    /// 同一次登录的尝试次数，校验前计数，并发请求也无法超过上限
+    const PENDING_ATTEMPT_KEY: &'static str = "user:totpPendingAttempt";
+    /// 同一次登录最多允许尝试的次数，达到后须重新登录
+    const PENDING_MAX_ATTEMPTS: u64 = 5;
+    /// 同一客户端 IP 完成两步验证的频率限制
+    const SIGN_IN_LIMIT_KEY: &'static str = "user:totpSignInLimit";
+    const SIGN_IN_LIMIT_PER_IP: Limit = Limit::new(30, 300);
+    const RECOVERY_CODE_COUNT: usize = 10;
+
+    /// 查询用户的 TOTP 密钥，未启用两步验证时返回 `None`
+    async fn query_secret(&self, user_id: Id) -> Result<Option<String>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select secret from user_totp where user_id = $1")
+            .await?;
+
+        Ok(pg
+            .query_opt(&statement, &[&user_id])
+            .await?
+            .map(|row| row.get(0)))
+    }
+
+    /// 判断用户是否已启用两步验证
+    pub async fn is_enabled(&self, user_id: Id) -> Result<bool, Error> {
+        Ok(self.query_secret(user_id).await?.is_some())
+    }
+
+    /// 开始启用两步验证，生成待确认的密钥
+    ///
+    /// 任何登录方式的用户均可启用（包括只使用 LDAP、OpenID Connect 登录的用户），用户不存在时返回 `EMPTY_RESULT`，
+    /// 已启用时返回 `DUPLICATE_VALUE`
+    pub async fn begin_enrollment(&self, user_id: Id) -> Result<TotpEnrollment, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select username, exists(select 1 from user_totp where user_id = $1) from user_info where id = $1")
+            .await?;
+
+        let (username, enabled): (String, bool) =
+            match pg.query_opt(&statement, &[&user_id]).await? {
+                Some(row) => (row.get(0), row.get(1)),
+                None => return Err(Kind::EMPTY_RESULT.into()),
+            };
+
+        if enabled {
+            return Err(Kind::DUPLICATE_VALUE.into());
+        }
+
+        let secret = totp::generate_secret();
+
+        let mut redis = self.redis_pool.get().await?;
+
+        cmd("SETEX")
+            .arg(format!("{}:{}", TotpService::ENROLL_KEY, user_id))
+            .arg(TotpService::ENROLL_EXPIRE)
+            .arg(&secret)
+            .execute_async(&mut redis)
+            .await?;
+
+        Ok(TotpEnrollment {
+            otpauth_uri: totp::otpauth_uri(&username, &secret),
+            secret,
+        })
+    }
+
+    /// 使用验证器应用生成的一次性密码确认启用两步验证，返回一次性恢复码（只返回这一次）
+    pub async fn confirm_enrollment(&self, user_id: Id, code: &str) -> Result<Vec<String>, Error> {
+        let enroll_key = format!("{}:{}", TotpService::ENROLL_KEY, user_id);
+
+        let mut redis = self.redis_pool.get().await?;
+
+        let secret: String = match cmd("GET")
+            .arg(&enroll_key)
+            .query_async::<Option<String>>(&mut redis)
+            .await?
+        {
+            Some(secret) => secret,
+            None => return Err(Kind::EMPTY_RESULT.into()),
+        };
+
+        let step = totp::verify(&secret, code, None).ok_or(Kind::INVALID_TOTP_CODE)?;
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        let statement = transaction
+            .prepare("insert into user_totp(user_id, secret) values($1, $2) on conflict (user_id) do nothing")
+            .await?;
+
+        if transaction
+            .execute(&statement, &[&user_id, &secret])
+            .await?
+            == 0
+        {
+            return Err(Kind::DUPLICATE_VALUE.into());
+        }
+
+        let recovery_codes = totp::generate_recovery_codes(TotpService::RECOVERY_CODE_COUNT);
+
+        let statement = transaction
+            .prepare("delete from user_recovery_code where user_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&user_id]).await?;
+
+        let statement = transaction
+            .prepare("insert into user_recovery_code(user_id, code_hash) values($1, $2)")
+            .await?;
+
+        for code in recovery_codes.iter() {
+            transaction
+                .execute(&statement, &[&user_id, &totp::hash_recovery_code(code)])
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        cmd("DEL")
+            .arg(&enroll_key)
+            .execute_async(&mut redis)
+            .await?;
+
+        self.save_last_step(user_id, step).await?;
+
+        Ok(recovery_codes)
+    }
+
+    /// 校验用户的一次性密码或恢复码，恢复码使用后即失效
+    ///
+    /// 未启用两步验证时返回 `EMPTY_RESULT`，校验失败时返回 `INVALID_TOTP_CODE`
+    pub async fn verify(&self, user_id: Id, code: &str) -> Result<(), Error> {
+        let secret = self
+            .query_secret(user_id)
+            .await?
+            .ok_or(Kind::EMPTY_RESULT)?;
+
+        let code = code.trim();
+
+        if code.len() == 6 && code.bytes().all(|c| c.is_ascii_digit()) {
+            let mut redis = self.redis_pool.get().await?;
+
+            let last_step: Option<u64> = cmd("GET")
+                .arg(format!("{}:{}", TotpService::LAST_STEP_KEY, user_id))
+                .query_async(&mut redis)
+                .await?;
+
+            let step = totp::verify(&secret, code, last_step).ok_or(Kind::INVALID_TOTP_CODE)?;
+
+            return self.save_last_step(user_id, step).await;
+        }
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("delete from user_recovery_code where user_id = $1 and code_hash = $2")
+            .await?;
+
+        if pg
+            .execute(&statement, &[&user_id, &totp::hash_recovery_code(code)])
+            .await?
+            == 0
+        {
+            return Err(Kind::INVALID_TOTP_CODE.into());
+        }
+
+        info!("用户 {} 使用了两步验证恢复码", user_id);
+
+        Ok(())
+    }
+
+    async fn save_last_step(&self, user_id: Id, step: u64) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(cmd("SETEX")
+            .arg(format!("{}:{}", TotpService::LAST_STEP_KEY, user_id))
+            .arg(totp::STEP * 4)
+            .arg(step)
+            .execute_async(&mut redis)
+            .await?)
+    }
+
+    /// 使用一次性密码或恢复码关闭两步验证
+    pub async fn disable(&self, user_id: Id, code: &str) -> Result<(), Error> {
+        self.verify(user_id, code).await?;
+        self.reset(user_id).await
+    }
+
+    /// 清除用户的 TOTP 密钥及恢复码，管理员可直接调用以重置用户的两步验证
+    pub async fn reset(&self, user_id: Id) -> Result<(), Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        let statement = transaction
+            .prepare("delete from user_totp where user_id = $1")
+            .await?;
+
+        if transaction.execute(&statement, &[&user_id]).await? == 0 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        let statement = transaction
+            .prepare("delete from user_recovery_code where user_id = $1")
+            .await?;
+
+        transaction.execute(&statement, &[&user_id]).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 用户已通过第一步验证，生成等待两步验证的临时令牌
+    pub async fn create_pending_sign_in(&self, user_id: Id) -> Result<String, Error> {
+        let token: String = iter::repeat(())
+            .map(|()| OsRng.sample(Alphanumeric))
+            .take(32)
+            .collect();
+
+        let mut redis = self.redis_pool.get().await?;
+
+        cmd("SETEX")
+            .arg(format!("{}:{}", TotpService::PENDING_KEY, token))
+            .arg(TotpService::PENDING_EXPIRE)
+            .arg(user_id)
+            .execute_async(&mut redis)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// 使用临时令牌及一次性密码（或恢复码）完成登录，返回用户 ID
+    ///
+    /// 1. 同一客户端 IP 的请求过于频繁，或 IP 处于锁定期时返回 `TOO_MANY_REQUESTS`，账号处于锁定期时返回 `ACCOUNT_LOCKED`；
+    /// 2. 令牌不存在、已过期或尝试次数达到上限时返回 `TOTP_SIGN_IN_EXPIRED`；
+    /// 3. 一次性密码错误时与密码错误一样记录账号和 IP 的失败次数（见 `UserService::sign_in_with_password`），
+    ///    重新登录获取新的令牌也无法绕过账号的锁定；
+    /// 4. 登录成功后清空账号的失败记录。
+    pub async fn complete_pending_sign_in(
+        &self,
+        token: &str,
+        code: &str,
+        client_ip: &ClientIp,
+    ) -> Result<Id, Error> {
+        let key = format!("{}:{}", TotpService::PENDING_KEY, token);
+        let attempt_key = format!("{}:{}", TotpService::PENDING_ATTEMPT_KEY, token);
+
+        let mut redis = self.redis_pool.get().await?;
+
+        TotpService::SIGN_IN_LIMIT_PER_IP
+            .hit(
+                &mut redis,
+                &format!("{}:{}", TotpService::SIGN_IN_LIMIT_KEY, client_ip),
+            )
+            .await?;
+
+        let ip_key = UserService::gen_ip_login_failure_key(client_ip);
+
+        check_login_lock(&mut redis, &ip_key, Kind::TOO_MANY_REQUESTS).await?;
+
+        let user_id: Id = match cmd("GET")
+            .arg(&key)
+            .query_async::<Option<Id>>(&mut redis)
+            .await?
+        {
+            Some(user_id) => user_id,
+            None => return Err(Kind::TOTP_SIGN_IN_EXPIRED.into()),
+        };
+
+        let user_key = UserService::gen_login_failure_key(user_id);
+
+        check_login_lock(&mut redis, &user_key, Kind::ACCOUNT_LOCKED).await?;
+
+        let attempts: u64 = cmd("INCR")
+            .arg(&attempt_key)
+            .query_async(&mut redis)
+            .await?;
+
+        if attempts == 1 {
+            cmd("EXPIRE")
+                .arg(&attempt_key)
+                .arg(TotpService::PENDING_EXPIRE)
+                .execute_async(&mut redis)
+                .await?;
+        }
+
+        if attempts > TotpService::PENDING_MAX_ATTEMPTS {
+            cmd("DEL")
+                .arg(&key)
+                .arg(&attempt_key)
+                .execute_async(&mut redis)
+                .await?;
+            return Err(Kind::TOTP_SIGN_IN_EXPIRED.into());
+        }
+
+        if let Err(e) = self.verify(user_id, code).await {
+            if e.kind().code() != Kind::INVALID_TOTP_CODE.code() {
+                return Err(e);
+            }
+
+            record_login_failure(
+                &mut redis,
+                &ip_key,
+                UserService::IP_LOCK_THRESHOLD,
+                client_ip,
+            )
+            .await?;
+            record_login_failure(
+                &mut redis,
+                &user_key,
+                UserService::ACCOUNT_LOCK_THRESHOLD,
+                client_ip,
+            )
+            .await?;
+
+            if attempts == TotpService::PENDING_MAX_ATTEMPTS {
+                cmd("DEL")
+                    .arg(&key)
+                    .arg(&attempt_key)
+                    .execute_async(&mut redis)
+                    .await?;
+                return Err(Kind::TOTP_SIGN_IN_EXPIRED.into());
+            }
+
+            return Err(e);
+        }
+
+        // 令牌只能使用一次，并发请求中只有删除成功的一个可以完成登录
+        let deleted: u64 = cmd("DEL")
+            .arg(&key)
+            .arg(&attempt_key)
+            .query_async(&mut redis)
+            .await?;
+
+        if deleted == 0 {
+            return Err(Kind::TOTP_SIGN_IN_EXPIRED.into());
+        }
+
+        cmd("DEL").arg(&user_key).execute_async(&mut redis).await?;
+
+        Ok(user_id)
+    }
+}
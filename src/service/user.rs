@@ -1,10 +1,25 @@
 //! 用户及登录相关服务
 use crate::error::{Error, Kind};
 use crate::model::*;
-use crate::opt::{PgPool, RedisPool};
-use crate::util::crypto::{check_pwd, hash_pwd};
-use crate::util::types::{AuthCode, Phone, Username};
-use deadpool_redis::cmd;
+use crate::opt::{PgPool, RedisPool, SenderOpts};
+use crate::service::constraint::{
+    check_base_required, check_mutex, lock_constraints, query_dependent_roles,
+};
+use crate::util::crypto::{check_pwd, PasswordHasher};
+use crate::util::db::{contains_pattern, Pager};
+use crate::util::http::ClientIp;
+use crate::util::limit::{cooldown, reset_cooldown, Limit};
+use crate::util::sender::CodeSender;
+use crate::util::types::{
+    check_birthday, AuthCode, Email, Nickname, Password, PasswordPolicy, Phone, Username,
+};
+use crate::util::user::SessionStore;
+use chrono::{NaiveDateTime, Utc};
+use deadpool_postgres::Transaction;
+use deadpool_redis::{cmd, Connection};
+use itertools::Itertools;
+use postgres_types::ToSql;
+use std::collections::HashMap;
 use std::fmt::Display;
 use tokio_pg_mapper::FromTokioPostgresRow;
 
@@ -12,30 +27,100 @@ use tokio_pg_mapper::FromTokioPostgresRow;
 pub struct UserService {
     pg_pool: PgPool,
     redis_pool: RedisPool,
+    session_store: SessionStore,
+    phone_sender: Box<dyn CodeSender>,
+    email_sender: Box<dyn CodeSender>,
+    password_policy: PasswordPolicy,
+    hasher: PasswordHasher,
 }
 
 impl UserService {
-    pub fn new(pg_pool: PgPool, redis_pool: RedisPool) -> Self {
+    pub fn new(
+        pg_pool: PgPool,
+        redis_pool: RedisPool,
+        session_store: SessionStore,
+        sender: SenderOpts,
+        password_policy: PasswordPolicy,
+        hasher: PasswordHasher,
+    ) -> Self {
         Self {
             pg_pool,
             redis_pool,
+            session_store,
+            phone_sender: sender.phone.create_sender(),
+            email_sender: sender.email.create_sender(),
+            password_policy,
+            hasher,
         }
     }
 
     const AUTH_CODE_KEY: &'static str = "user:authCode";
-    const AUTH_CODE_EXPIRE: &'static str = "300";
+    const AUTH_CODE_EXPIRE: u64 = 300;
+    /// 验证码错误次数计数
+    const AUTH_CODE_FAILURE_KEY: &'static str = "user:authCodeFailure";
+    /// 同一个验证码最多允许输错的次数，达到后验证码失效
+    const AUTH_CODE_MAX_FAILURES: u64 = 5;
+    /// 同一手机号/电子邮箱两次发送验证码的最小间隔
+    const AUTH_CODE_COOLDOWN_KEY: &'static str = "user:authCodeCooldown";
+    const AUTH_CODE_COOLDOWN: u64 = 60;
+    /// 发送验证码/校验验证码的频率限制
+    const AUTH_CODE_LIMIT_KEY: &'static str = "user:authCodeLimit";
+    const SEND_LIMIT_PER_IDENTITY: Limit = Limit::new(10, 24 * 3600);
+    const SEND_LIMIT_PER_IP: Limit = Limit::new(20, 3600);
+    const CHECK_LIMIT_PER_IP: Limit = Limit::new(30, 300);
+
+    /// 密码登录失败记录，按用户 ID 及客户端 IP 分别记录
+    const LOGIN_FAILURE_KEY: &'static str = "user:loginFailure";
+    /// 同一账号连续失败达到此次数后开始锁定
+    pub(crate) const ACCOUNT_LOCK_THRESHOLD: u64 = 5;
+    /// 同一 IP 失败达到此次数后开始锁定，不论尝试的是哪个账号
+    pub(crate) const IP_LOCK_THRESHOLD: u64 = 20;
+    /// 首次锁定的时长，此后每多失败一次时长翻倍，直到 `LOCK_MAX_SECONDS`
+    const LOCK_BASE_SECONDS: i64 = 60;
+    const LOCK_MAX_SECONDS: i64 = 24 * 3600;
+    /// 最后一次失败后，失败记录保留的时长
+    const LOGIN_FAILURE_EXPIRE: i64 = 24 * 3600;
+    /// 变更用户状态的原因的最大长度
+    const MAX_STATUS_REASON_LEN: usize = 255;
+
+    /// 管理员查询用户时的筛选条件，占位符 `$1` 至 `$7` 依次为 `filter_patterns` 返回的匹配模式、
+    /// 角色 id 及创建日期的范围，参数为空时忽略对应的条件
+    const USER_FILTER_CONDITION: &'static str = "($1::text is null or username ilike $1) \
+         and ($2::text is null or nickname ilike $2) \
+         and ($3::text is null or id in (select user_id from user_auth where auth_type = 'Phone' and identity like $3)) \
+         and ($4::text is null or id in (select user_id from user_auth where auth_type = 'Email' and identity ilike $4)) \
+         and ($5::bigint is null or id in (select user_id from user_role where role_id = $5)) \
+         and ($6::date is null or create_time >= $6) \
+         and ($7::date is null or create_time < $7::date + 1) \
+         and (status = $8 or ($8::\"UserStatus\" is null and status <> 'Deleted'))";
 
     fn gen_auth_code_key<T: Display>(auth_type: AuthType, identity: &T) -> String {
         format!("{}:{}:{}", UserService::AUTH_CODE_KEY, auth_type, identity)
     }
 
-    pub async fn cache_auth_code<T: Display>(
+    fn gen_auth_code_failure_key<T: Display>(auth_type: AuthType, identity: &T) -> String {
+        format!(
+            "{}:{}:{}",
+            UserService::AUTH_CODE_FAILURE_KEY,
+            auth_type,
+            identity
+        )
+    }
+
+    /// 缓存验证码，并清空之前的验证码错误次数
+    async fn cache_auth_code<T: Display>(
         &self,
         auth_type: AuthType,
         identity: &T,
         auth_code: &AuthCode,
     ) -> Result<(), Error> {
         let mut redis = self.redis_pool.get().await?;
+
+        cmd("DEL")
+            .arg(Self::gen_auth_code_failure_key(auth_type, identity))
+            .execute_async(&mut redis)
+            .await?;
+
         Ok(cmd("SETEX")
             .arg(Self::gen_auth_code_key(auth_type, identity))
             .arg(UserService::AUTH_CODE_EXPIRE)
@@ -44,16 +129,129 @@ impl UserService {
             .await?)
     }
 
+    /// 生成验证码并发送到手机号
+    pub async fn send_auth_code_to_phone(
+        &self,
+        phone: &Phone,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        self.send_auth_code(
+            AuthType::Phone,
+            phone,
+            &phone.to_e164(),
+            self.phone_sender.as_ref(),
+            client_ip,
+        )
+        .await?;
+
+        info!("已给手机号 {} 发送数字验证码", phone);
+
+        Ok(())
+    }
+
+    /// 生成验证码并发送到电子邮箱
+    pub async fn send_auth_code_to_email(
+        &self,
+        email: &Email,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        self.send_auth_code(
+            AuthType::Email,
+            email,
+            &email.to_string(),
+            self.email_sender.as_ref(),
+            client_ip,
+        )
+        .await?;
+
+        info!("已给电子邮箱 {} 发送数字验证码", email);
+
+        Ok(())
+    }
+
+    /// 检查冷却时间及发送频率限制后，生成、缓存并发送验证码
+    async fn send_auth_code<T: Display>(
+        &self,
+        auth_type: AuthType,
+        identity: &T,
+        to: &str,
+        sender: &dyn CodeSender,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        let cooldown_key = format!(
+            "{}:{}:{}",
+            UserService::AUTH_CODE_COOLDOWN_KEY,
+            auth_type,
+            identity
+        );
+
+        cooldown(&mut redis, &cooldown_key, UserService::AUTH_CODE_COOLDOWN).await?;
+
+        UserService::SEND_LIMIT_PER_IDENTITY
+            .hit(
+                &mut redis,
+                &format!(
+                    "{}:send:{}:{}",
+                    UserService::AUTH_CODE_LIMIT_KEY,
+                    auth_type,
+                    identity
+                ),
+            )
+            .await?;
+
+        UserService::SEND_LIMIT_PER_IP
+            .hit(
+                &mut redis,
+                &format!("{}:send:ip:{}", UserService::AUTH_CODE_LIMIT_KEY, client_ip),
+            )
+            .await?;
+
+        let auth_code = AuthCode::default();
+
+        self.cache_auth_code(auth_type, identity, &auth_code)
+            .await?;
+
+        if let Err(e) = sender.send(to, &auth_code).await {
+            // 发送失败不应让用户等待冷却时间
+            if let Err(e) = reset_cooldown(&mut redis, &cooldown_key).await {
+                error!("从 Redis 中删除 {} 时发生错误: {}", cooldown_key, e);
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// 校验验证码
+    ///
+    /// 1. 同一 IP 校验验证码的频率受限，超出时返回 `TOO_MANY_REQUESTS`；
+    /// 2. 验证码正确时返回 `true`，验证码随即失效；
+    /// 3. 验证码错误时返回 `false`，同一验证码错误次数达到上限后验证码失效，并返回 `AUTH_CODE_ATTEMPTS_EXCEEDED`。
     pub async fn check_auth_code<T: Display>(
         &self,
         auth_type: AuthType,
         identity: &T,
         auth_code: &AuthCode,
+        client_ip: &ClientIp,
     ) -> Result<bool, Error> {
         let key = Self::gen_auth_code_key(auth_type, identity);
+        let failure_key = Self::gen_auth_code_failure_key(auth_type, identity);
 
         let mut redis = self.redis_pool.get().await?;
 
+        UserService::CHECK_LIMIT_PER_IP
+            .hit(
+                &mut redis,
+                &format!(
+                    "{}:check:ip:{}",
+                    UserService::AUTH_CODE_LIMIT_KEY,
+                    client_ip
+                ),
+            )
+            .await?;
+
         let get_auth_code: Option<String> = cmd("GET").arg(&key).query_async(&mut redis).await?;
 
         let cached_auth_code = match get_auth_code {
@@ -61,18 +259,70 @@ impl UserService {
             None => return Err(Kind::INVALID_AUTH_CODE.into()),
         };
 
-        if let Err(e) = cmd("DEL").arg(&key).execute_async(&mut redis).await {
-            error!("从 Redis 中删除 {} 时发生错误: {}", key, e);
+        if auth_code == &cached_auth_code {
+            if let Err(e) = cmd("DEL")
+                .arg(&key)
+                .arg(&failure_key)
+                .execute_async(&mut redis)
+                .await
+            {
+                error!("从 Redis 中删除 {} 时发生错误: {}", key, e);
+            }
+
+            return Ok(true);
+        }
+
+        let failures: u64 = cmd("INCR")
+            .arg(&failure_key)
+            .query_async(&mut redis)
+            .await?;
+
+        if failures >= UserService::AUTH_CODE_MAX_FAILURES {
+            cmd("DEL")
+                .arg(&key)
+                .arg(&failure_key)
+                .execute_async(&mut redis)
+                .await?;
+
+            return Err(Kind::AUTH_CODE_ATTEMPTS_EXCEEDED.into());
         }
 
-        Ok(auth_code == &cached_auth_code)
+        cmd("EXPIRE")
+            .arg(&failure_key)
+            .arg(UserService::AUTH_CODE_EXPIRE)
+            .execute_async(&mut redis)
+            .await?;
+
+        Ok(false)
     }
 
     pub async fn create_user_with_phone(
         &self,
         username: &Username,
-        nickname: &str,
+        nickname: &Nickname,
         phone: &Phone,
+    ) -> Result<UserInfo, Error> {
+        self.create_user(username, nickname, AuthType::Phone, phone)
+            .await
+    }
+
+    pub async fn create_user_with_email(
+        &self,
+        username: &Username,
+        nickname: &Nickname,
+        email: &Email,
+    ) -> Result<UserInfo, Error> {
+        self.create_user(username, nickname, AuthType::Email, email)
+            .await
+    }
+
+    /// 创建用户，并使用手机号/电子邮箱 `identity` 作为其第一种登录方式
+    async fn create_user(
+        &self,
+        username: &Username,
+        nickname: &Nickname,
+        auth_type: AuthType,
+        identity: &(dyn ToSql + Sync),
     ) -> Result<UserInfo, Error> {
         let mut pg = self.pg_pool.get().await?;
 
@@ -93,7 +343,7 @@ impl UserService {
             .await?;
 
         transaction
-            .execute(&statement, &[&user_info.id, &AuthType::Phone, phone, &""])
+            .execute(&statement, &[&user_info.id, &auth_type, identity, &""])
             .await?;
 
         transaction.commit().await?;
@@ -101,7 +351,60 @@ impl UserService {
         Ok(user_info)
     }
 
+    /// 为用户绑定电子邮箱，绑定后可使用电子邮箱登录，调用前须已校验发往该邮箱的验证码
+    ///
+    /// 已绑定电子邮箱时返回 `DUPLICATE_VALUE`，更换电子邮箱请使用 `bind_identity`
+    pub async fn bind_email(
+        &self,
+        user_id: Id,
+        email: &Email,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[user_id]).await?;
+
+        let statement = transaction
+            .prepare("select 1 from user_auth where user_id = $1 and auth_type = $2")
+            .await?;
+
+        if transaction
+            .query_opt(&statement, &[&user_id, &AuthType::Email])
+            .await?
+            .is_some()
+        {
+            return Err(Kind::DUPLICATE_VALUE.into());
+        }
+
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4)")
+            .await?;
+
+        transaction
+            .execute(&statement, &[&user_id, &AuthType::Email, email, &""])
+            .await?;
+
+        insert_auth_history(
+            &transaction,
+            user_id,
+            AuthType::Email,
+            None,
+            Some(&email.to_string()),
+            Some(client_ip),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 为用户新增登录密码，用户已有密码时返回 `DUPLICATE_VALUE`，修改密码请使用 `change_password`
     pub async fn add_password(&self, user_id: Id, password: &str) -> Result<(), Error> {
+        let password = Password::new(password, &self.password_policy)?;
+
         let pg = self.pg_pool.get().await?;
 
         let select = pg
@@ -110,7 +413,7 @@ impl UserService {
 
         if let Some(row) = pg.query_opt(&select, &[&user_id]).await? {
             let username: String = row.get(0);
-            let hashed_pwd = hash_pwd(password)?;
+            let hashed_pwd = self.hasher.hash(password.as_str()).await?;
 
             let insert = pg
                 .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4)")
@@ -128,126 +431,1602 @@ impl UserService {
         }
     }
 
-    pub async fn sign_in_with_username(
+    /// 使用原密码修改登录密码，修改后注销用户的所有会话
+    ///
+    /// 原密码错误计入账号的登录失败次数（见 `sign_in_with_password`），用户尚未设置密码时返回 `EMPTY_RESULT`
+    pub async fn change_password(
         &self,
-        username: &Username,
-        password: &str,
-    ) -> Result<UserInfo, Error> {
+        user_id: Id,
+        old_password: &str,
+        new_password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let new_password = Password::new(new_password, &self.password_policy)?;
+
         let pg = self.pg_pool.get().await?;
 
         let statement = pg
-            .prepare("select * from user_auth where auth_type = $1 and identity = $2")
+            .prepare("select credential1 from user_auth where user_id = $1 and auth_type = $2")
             .await?;
 
-        if let Some(row) = pg
-            .query_opt(&statement, &[&AuthType::Username, &username])
+        let hashed_pwd: String = match pg
+            .query_opt(&statement, &[&user_id, &AuthType::Username])
             .await?
         {
-            let user_auth = UserAuth::from_row(row)?;
+            Some(row) => row.get(0),
+            None => return Err(Kind::EMPTY_RESULT.into()),
+        };
 
-            check_pwd(password, &user_auth.credential1)?;
+        self.check_current_password(user_id, old_password, &hashed_pwd, client_ip)
+            .await?;
 
-            let statement = pg.prepare("select * from user_info where id = $1").await?;
+        let statement = pg
+            .prepare("update user_auth set credential1 = $3 where user_id = $1 and auth_type = $2")
+            .await?;
 
-            let row = pg.query_one(&statement, &[&user_auth.user_id]).await?;
+        pg.execute(
+            &statement,
+            &[
+                &user_id,
+                &AuthType::Username,
+                &self.hasher.hash(new_password.as_str()).await?,
+            ],
+        )
+        .await?;
 
-            Ok(UserInfo::from_row(row)?)
+        self.revoke_all_sessions(user_id).await
+    }
+
+    /// 校验用户当前的登录密码，密码错误计入账号的登录失败次数并返回 `WRONG_PASSWORD`
+    async fn check_current_password(
+        &self,
+        user_id: Id,
+        password: &str,
+        hashed_pwd: &str,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        let user_key = Self::gen_login_failure_key(user_id);
+
+        check_login_lock(&mut redis, &user_key, Kind::ACCOUNT_LOCKED).await?;
+
+        if let Err(e) = check_pwd(password, hashed_pwd).await {
+            if e.kind().code() == Kind::LOGIN_FAILED.code() {
+                record_login_failure(
+                    &mut redis,
+                    &user_key,
+                    UserService::ACCOUNT_LOCK_THRESHOLD,
+                    client_ip,
+                )
+                .await?;
+                return Err(Kind::WRONG_PASSWORD.into());
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// 重新认证用户，参数见 `ReauthParams`
+    ///
+    /// 密码错误返回 `WRONG_PASSWORD`，用户未绑定 `reauth_type` 或验证码错误时返回 `INVALID_AUTH_CODE`，
+    /// 未填写任何凭证时返回 `INVALID_PARAMS`
+    async fn reauthenticate(
+        &self,
+        user_id: Id,
+        reauth: &ReauthParams,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare(
+                "select identity, credential1 from user_auth where user_id = $1 and auth_type = $2",
+            )
+            .await?;
+
+        if let Some(password) = &reauth.password {
+            return match pg
+                .query_opt(&statement, &[&user_id, &AuthType::Username])
+                .await?
+            {
+                Some(row) => {
+                    let hashed_pwd: String = row.get(1);
+                    self.check_current_password(user_id, password, &hashed_pwd, client_ip)
+                        .await
+                }
+                None => Err(Kind::WRONG_PASSWORD.into()),
+            };
+        }
+
+        let (auth_type, auth_code) = match (reauth.reauth_type, &reauth.reauth_code) {
+            (Some(auth_type @ AuthType::Phone), Some(auth_code))
+            | (Some(auth_type @ AuthType::Email), Some(auth_code)) => {
+                (auth_type, AuthCode::new(auth_code)?)
+            }
+            _ => return Err(Kind::INVALID_PARAMS.into()),
+        };
+
+        let identity: String = match pg.query_opt(&statement, &[&user_id, &auth_type]).await? {
+            Some(row) => row.get(0),
+            None => return Err(Kind::INVALID_AUTH_CODE.into()),
+        };
+
+        if self
+            .check_auth_code(auth_type, &identity, &auth_code, client_ip)
+            .await?
+        {
+            Ok(())
         } else {
-            Err(Kind::LOGIN_FAILED.into())
+            Err(Kind::INVALID_AUTH_CODE.into())
         }
     }
 
-    pub async fn sign_in_with_phone(
+    /// 为用户绑定或更换手机号/电子邮箱，须先重新认证，`auth_code` 为发往新手机号/电子邮箱的验证码
+    ///
+    /// 新手机号/电子邮箱已被其他用户使用时返回 `DUPLICATE_VALUE`
+    pub async fn bind_identity(
         &self,
-        phone: &Phone,
-        auth_code: &AuthCode,
-    ) -> Result<UserInfo, Error> {
+        user_id: Id,
+        auth_type: AuthType,
+        params: &BindIdentityParams,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        // 与发送验证码时的格式保持一致
+        let identity = match auth_type {
+            AuthType::Phone => Phone::new(&params.identity)?.to_string(),
+            AuthType::Email => Email::new(&params.identity)?.to_string(),
+            AuthType::Username | AuthType::Oidc | AuthType::Ldap => {
+                return Err(Kind::INVALID_PARAMS.into())
+            }
+        };
+        let auth_code = AuthCode::new(&params.auth_code)?;
+
+        self.reauthenticate(user_id, &params.reauth, client_ip)
+            .await?;
+
         if !self
-            .check_auth_code(AuthType::Phone, phone, auth_code)
+            .check_auth_code(auth_type, &identity, &auth_code, client_ip)
             .await?
         {
             return Err(Kind::INVALID_AUTH_CODE.into());
         }
 
-        let pg = self.pg_pool.get().await?;
+        let mut pg = self.pg_pool.get().await?;
 
-        let statement = pg
-            .prepare("select * from user_info where id in (select user_id from user_auth where auth_type = $1 and identity = $2)")
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[user_id]).await?;
+
+        let statement = transaction
+            .prepare("select identity from user_auth where user_id = $1 and auth_type = $2")
             .await?;
 
-        if let Some(row) = pg.query_opt(&statement, &[&AuthType::Phone, phone]).await? {
-            Ok(UserInfo::from_row(row)?)
-        } else {
-            Err(Kind::LOGIN_FAILED.into())
+        let old_identity: Option<String> = transaction
+            .query_opt(&statement, &[&user_id, &auth_type])
+            .await?
+            .map(|row| row.get(0));
+
+        if old_identity.as_deref() == Some(identity.as_str()) {
+            return Ok(());
         }
-    }
 
-    pub async fn query_user_by_id(&self, id: Id) -> Result<UserInfo, Error> {
-        let pg = self.pg_pool.get().await?;
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4) on conflict (user_id, auth_type) do update set identity = excluded.identity")
+            .await?;
 
-        let statement = pg.prepare("select * from user_info where id = $1").await?;
+        transaction
+            .execute(&statement, &[&user_id, &auth_type, &identity, &""])
+            .await?;
 
-        if let Some(row) = pg.query_opt(&statement, &[&id]).await? {
-            Ok(UserInfo::from_row(row)?)
-        } else {
-            Err(Kind::EMPTY_RESULT.into())
-        }
+        insert_auth_history(
+            &transaction,
+            user_id,
+            auth_type,
+            old_identity.as_deref(),
+            Some(&identity),
+            Some(client_ip),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
     }
 
-    pub async fn query_user_roles(&self, user_id: Id) -> Result<Vec<Role>, Error> {
-        let pg = self.pg_pool.get().await?;
+    /// 解绑用户的手机号、电子邮箱或外部身份，须先重新认证
+    ///
+    /// 用户未绑定该登录方式时返回 `EMPTY_RESULT`，解绑后没有其他登录方式时返回 `LAST_AUTH_METHOD`
+    pub async fn unbind_identity(
+        &self,
+        user_id: Id,
+        auth_type: AuthType,
+        reauth: &ReauthParams,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        match auth_type {
+            AuthType::Phone | AuthType::Email | AuthType::Oidc => {}
+            AuthType::Username | AuthType::Ldap => return Err(Kind::INVALID_PARAMS.into()),
+        }
 
-        let statement = pg
+        self.reauthenticate(user_id, reauth, client_ip).await?;
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[user_id]).await?;
+
+        let statement = transaction
             .prepare(
-                "select * from role where id in (select role_id from user_role where user_id = $1)",
+                "delete from user_auth where user_id = $1 and auth_type = $2 returning identity",
             )
             .await?;
 
-        let rows = pg.query(&statement, &[&user_id]).await?;
+        let old_identity: String = match transaction
+            .query_opt(&statement, &[&user_id, &auth_type])
+            .await?
+        {
+            Some(row) => row.get(0),
+            None => return Err(Kind::EMPTY_RESULT.into()),
+        };
 
-        let mut roles = Vec::with_capacity(rows.len());
+        let statement = transaction
+            .prepare("select count(1) from user_auth where user_id = $1")
+            .await?;
 
-        for row in rows.iter() {
-            roles.push(Role::from_row_ref(row)?);
+        let remaining: i64 = transaction.query_one(&statement, &[&user_id]).await?.get(0);
+
+        if remaining == 0 {
+            return Err(Kind::LAST_AUTH_METHOD.into());
         }
 
-        Ok(roles)
+        insert_auth_history(
+            &transaction,
+            user_id,
+            auth_type,
+            Some(&old_identity),
+            None,
+            Some(client_ip),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
     }
 
-    pub async fn query_user_auth(&self, user_id: Id) -> Result<Vec<UserAuth>, Error> {
+    /// 查询用户登录方式的变更记录，按时间倒序排列
+    pub async fn query_auth_history(&self, user_id: Id) -> Result<Vec<UserAuthHistory>, Error> {
         let pg = self.pg_pool.get().await?;
 
         let statement = pg
-            .prepare("select * from user_auth where user_id = $1")
+            .prepare("select * from user_auth_history where user_id = $1 order by id desc")
             .await?;
 
         let rows = pg.query(&statement, &[&user_id]).await?;
 
-        let mut auth = Vec::with_capacity(rows.len());
+        let mut history = Vec::with_capacity(rows.len());
 
         for row in rows.iter() {
-            auth.push(UserAuth::from_row_ref(row)?);
+            history.push(UserAuthHistory::from_row_ref(row)?);
         }
 
-        Ok(auth)
+        Ok(history)
     }
 
-    // TODO: 应该返回 `Vec<Permission>`，待修改
-    pub async fn query_user_perm(&self, user_id: Id) -> Result<Vec<RolePermission>, Error> {
+    /// 忘记密码时，使用发往已绑定手机号/电子邮箱的验证码重置登录密码
+    ///
+    /// 用户尚未设置密码时直接设置新密码，重置后清空账号的登录失败记录并注销用户的所有会话
+    pub async fn reset_password(
+        &self,
+        auth_type: AuthType,
+        identity: &str,
+        auth_code: &AuthCode,
+        password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<(), Error> {
+        let password = Password::new(password, &self.password_policy)?;
+
+        // 与发送验证码时的格式保持一致
+        let identity = match auth_type {
+            AuthType::Phone => Phone::new(identity)?.to_string(),
+            AuthType::Email => Email::new(identity)?.to_string(),
+            AuthType::Username | AuthType::Oidc | AuthType::Ldap => {
+                return Err(Kind::INVALID_AUTH_CODE.into())
+            }
+        };
+
+        if !self
+            .check_auth_code(auth_type, &identity, auth_code, client_ip)
+            .await?
+        {
+            return Err(Kind::INVALID_AUTH_CODE.into());
+        }
+
         let pg = self.pg_pool.get().await?;
 
         let statement = pg
-            .prepare("select * from role_permission where role_id in (select role_id from user_role where user_id = $1)")
+            .prepare("select u.id, u.username from user_info u join user_auth a on a.user_id = u.id where a.auth_type = $1 and a.identity = $2")
             .await?;
 
-        let rows = pg.query(&statement, &[&user_id]).await?;
+        let (user_id, username): (Id, String) =
+            match pg.query_opt(&statement, &[&auth_type, &identity]).await? {
+                Some(row) => (row.get(0), row.get(1)),
+                None => return Err(Kind::EMPTY_RESULT.into()),
+            };
 
-        let mut perms = Vec::with_capacity(rows.len());
+        let statement = pg
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4) on conflict (user_id, auth_type) do update set credential1 = excluded.credential1")
+            .await?;
 
-        for row in rows.iter() {
-            perms.push(RolePermission::from_row_ref(row)?);
-        }
+        pg.execute(
+            &statement,
+            &[
+                &user_id,
+                &AuthType::Username,
+                &username,
+                &self.hasher.hash(password.as_str()).await?,
+            ],
+        )
+        .await?;
 
-        Ok(perms)
+        self.unlock_user(user_id).await?;
+        self.revoke_all_sessions(user_id).await
+    }
+
+    pub async fn sign_in_with_username(
+        &self,
+        username: &Username,
+        password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_auth where auth_type = $1 and identity = $2")
+            .await?;
+
+        let user_auth = match pg
+            .query_opt(&statement, &[&AuthType::Username, &username])
+            .await?
+        {
+            Some(row) => Some(UserAuth::from_row(row)?),
+            None => None,
+        };
+
+        self.sign_in_with_password(user_auth, password, client_ip)
+            .await
+    }
+
+    pub async fn sign_in_with_phone(
+        &self,
+        phone: &Phone,
+        auth_code: &AuthCode,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        if !self
+            .check_auth_code(AuthType::Phone, phone, auth_code, client_ip)
+            .await?
+        {
+            return Err(Kind::INVALID_AUTH_CODE.into());
+        }
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_info where id in (select user_id from user_auth where auth_type = $1 and identity = $2)")
+            .await?;
+
+        if let Some(row) = pg.query_opt(&statement, &[&AuthType::Phone, phone]).await? {
+            let user_info = UserInfo::from_row(row)?;
+            check_user_status(&user_info)?;
+            Ok(user_info)
+        } else {
+            Err(Kind::LOGIN_FAILED.into())
+        }
+    }
+
+    /// 使用电子邮箱及用户的登录密码（见 `add_password`）登录
+    pub async fn sign_in_with_email(
+        &self,
+        email: &Email,
+        password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_auth where auth_type = $1 and user_id in (select user_id from user_auth where auth_type = $2 and identity = $3)")
+            .await?;
+
+        let user_auth = match pg
+            .query_opt(&statement, &[&AuthType::Username, &AuthType::Email, email])
+            .await?
+        {
+            Some(row) => Some(UserAuth::from_row(row)?),
+            None => None,
+        };
+
+        self.sign_in_with_password(user_auth, password, client_ip)
+            .await
+    }
+
+    /// 校验密码登录，`user_auth` 为用户的 `Username` 授权记录，不存在时为 `None`
+    ///
+    /// 1. 客户端 IP 或账号处于锁定期时，分别返回 `TOO_MANY_REQUESTS` 和 `ACCOUNT_LOCKED`；
+    /// 2. 密码错误时同时记录账号和 IP 的失败次数，账号不存在时使用 `PasswordHasher::dummy_hash` 校验密码，只记录 IP 的失败次数；
+    /// 3. 登录成功后清空账号的失败记录，启用了两步验证时在完成两步验证后清空。
+    async fn sign_in_with_password(
+        &self,
+        user_auth: Option<UserAuth>,
+        password: &str,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        let ip_key = Self::gen_ip_login_failure_key(client_ip);
+
+        check_login_lock(&mut redis, &ip_key, Kind::TOO_MANY_REQUESTS).await?;
+
+        let user_auth = match user_auth {
+            Some(user_auth) => user_auth,
+            None => {
+                // 账号不存在时同样校验一次密码，避免通过响应时间探测用户名
+                let _ = check_pwd(password, &self.hasher.dummy_hash()).await;
+                record_login_failure(
+                    &mut redis,
+                    &ip_key,
+                    UserService::IP_LOCK_THRESHOLD,
+                    client_ip,
+                )
+                .await?;
+                return Err(Kind::LOGIN_FAILED.into());
+            }
+        };
+
+        let user_key = Self::gen_login_failure_key(user_auth.user_id);
+
+        check_login_lock(&mut redis, &user_key, Kind::ACCOUNT_LOCKED).await?;
+
+        if let Err(e) = check_pwd(password, &user_auth.credential1).await {
+            if e.kind().code() == Kind::LOGIN_FAILED.code() {
+                record_login_failure(
+                    &mut redis,
+                    &ip_key,
+                    UserService::IP_LOCK_THRESHOLD,
+                    client_ip,
+                )
+                .await?;
+                record_login_failure(
+                    &mut redis,
+                    &user_key,
+                    UserService::ACCOUNT_LOCK_THRESHOLD,
+                    client_ip,
+                )
+                .await?;
+            }
+            return Err(e);
+        }
+
+        if self.hasher.needs_rehash(&user_auth.credential1) {
+            // 重新生成 hash 失败不影响本次登录
+            if let Err(e) = self.rehash_password(&user_auth, password).await {
+                error!(
+                    "更新用户 {} 的密码 hash 时发生错误: {}",
+                    user_auth.user_id, e
+                );
+            }
+        }
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select exists(select 1 from user_totp where user_id = $1)")
+            .await?;
+
+        // 启用了两步验证时在完成两步验证后才清空，
+        // 否则可以反复使用密码登录清空两步验证的失败次数，见 `TotpService::complete_pending_sign_in`
+        let totp_enabled: bool = pg
+            .query_one(&statement, &[&user_auth.user_id])
+            .await?
+            .get(0);
+
+        if !totp_enabled {
+            cmd("DEL").arg(&user_key).execute_async(&mut redis).await?;
+        }
+
+        let statement = pg.prepare("select * from user_info where id = $1").await?;
+
+        let row = pg.query_one(&statement, &[&user_auth.user_id]).await?;
+
+        let user_info = UserInfo::from_row(row)?;
+
+        check_user_status(&user_info)?;
+
+        Ok(user_info)
+    }
+
+    /// 使用当前配置的算法及参数重新生成密码 hash，只在 hash 未被并发修改时更新
+    async fn rehash_password(&self, user_auth: &UserAuth, password: &str) -> Result<(), Error> {
+        let hashed_pwd = self.hasher.hash(password).await?;
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("update user_auth set credential1 = $4 where user_id = $1 and auth_type = $2 and credential1 = $3")
+            .await?;
+
+        pg.execute(
+            &statement,
+            &[
+                &user_auth.user_id,
+                &AuthType::Username,
+                &user_auth.credential1,
+                &hashed_pwd,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) fn gen_login_failure_key(user_id: Id) -> String {
+        format!("{}:{}", UserService::LOGIN_FAILURE_KEY, user_id)
+    }
+
+    pub(crate) fn gen_ip_login_failure_key(client_ip: &ClientIp) -> String {
+        format!("{}:ip:{}", UserService::LOGIN_FAILURE_KEY, client_ip)
+    }
+
+    /// 查询用户的密码登录失败记录
+    pub async fn query_login_failure(&self, user_id: Id) -> Result<LoginFailure, Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        let fields: HashMap<String, String> = cmd("HGETALL")
+            .arg(Self::gen_login_failure_key(user_id))
+            .query_async(&mut redis)
+            .await?;
+
+        let timestamp = |field: &str| {
+            fields
+                .get(field)
+                .and_then(|t| t.parse().ok())
+                .map(|t| NaiveDateTime::from_timestamp(t, 0))
+        };
+
+        Ok(LoginFailure {
+            failure_count: fields
+                .get("failure_count")
+                .and_then(|c| c.parse().ok())
+                .unwrap_or_default(),
+            last_failure_time: timestamp("last_failure_time"),
+            last_failure_ip: fields.get("last_failure_ip").cloned(),
+            locked_until: timestamp("locked_until"),
+        })
+    }
+
+    /// 解除用户的登录锁定，并清空失败记录
+    pub async fn unlock_user(&self, user_id: Id) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(cmd("DEL")
+            .arg(Self::gen_login_failure_key(user_id))
+            .execute_async(&mut redis)
+            .await?)
+    }
+
+    /// 列出用户的所有会话，`current_token` 为发起请求的会话令牌
+    pub async fn list_sessions(
+        &self,
+        user_id: Id,
+        current_token: Option<&str>,
+    ) -> Result<Vec<Session>, Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        self.session_store
+            .list(&mut redis, &user_id, current_token)
+            .await
+    }
+
+    /// 注销用户的指定会话，会话不存在时返回 `EMPTY_RESULT`
+    pub async fn revoke_session(&self, user_id: Id, session_id: &str) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        self.session_store
+            .revoke(&mut redis, &user_id, session_id)
+            .await
+    }
+
+    /// 查询用户的权限版本，见 `SessionStore`
+    pub async fn query_perm_version(&self, user_id: Id) -> Result<u64, Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        self.session_store
+            .query_perm_version(&mut redis, &user_id)
+            .await
+    }
+
+    /// 注销用户的所有会话
+    pub async fn revoke_all_sessions(&self, user_id: Id) -> Result<(), Error> {
+        let mut redis = self.redis_pool.get().await?;
+
+        self.session_store.revoke_all(&mut redis, &user_id).await
+    }
+
+    /// 使用电子邮箱及发往该邮箱的验证码登录
+    pub async fn sign_in_with_email_code(
+        &self,
+        email: &Email,
+        auth_code: &AuthCode,
+        client_ip: &ClientIp,
+    ) -> Result<UserInfo, Error> {
+        if !self
+            .check_auth_code(AuthType::Email, email, auth_code, client_ip)
+            .await?
+        {
+            return Err(Kind::INVALID_AUTH_CODE.into());
+        }
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_info where id in (select user_id from user_auth where auth_type = $1 and identity = $2)")
+            .await?;
+
+        if let Some(row) = pg.query_opt(&statement, &[&AuthType::Email, email]).await? {
+            let user_info = UserInfo::from_row(row)?;
+            check_user_status(&user_info)?;
+            Ok(user_info)
+        } else {
+            Err(Kind::LOGIN_FAILED.into())
+        }
+    }
+
+    pub async fn query_user_by_id(&self, id: Id) -> Result<UserInfo, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg.prepare("select * from user_info where id = $1").await?;
+
+        if let Some(row) = pg.query_opt(&statement, &[&id]).await? {
+            Ok(UserInfo::from_row(row)?)
+        } else {
+            Err(Kind::EMPTY_RESULT.into())
+        }
+    }
+
+    /// 查询用户，用户不是 `Active` 状态时返回错误，见 `check_user_status`
+    pub async fn query_active_user(&self, id: Id) -> Result<UserInfo, Error> {
+        let user_info = self.query_user_by_id(id).await?;
+
+        check_user_status(&user_info)?;
+
+        Ok(user_info)
+    }
+
+    /// 查询符合筛选条件的用户数
+    pub async fn query_users_count(&self, filter: &UserFilter) -> Result<Count, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare(&format!(
+                "select count(1) from user_info where {}",
+                UserService::USER_FILTER_CONDITION
+            ))
+            .await?;
+
+        let [username, nickname, phone, email] = filter_patterns(filter);
+
+        Ok(Count {
+            count: pg
+                .query_one(
+                    &statement,
+                    &[
+                        &username,
+                        &nickname,
+                        &phone,
+                        &email,
+                        &filter.role_id,
+                        &filter.created_from,
+                        &filter.created_to,
+                        &filter.status,
+                    ],
+                )
+                .await?
+                .get(0),
+        })
+    }
+
+    /// 分页查询符合筛选条件的用户，按 id 排序
+    pub async fn list_users(
+        &self,
+        filter: &UserFilter,
+        pager: &Pager,
+    ) -> Result<Vec<UserInfo>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare(&format!(
+                "select * from user_info where {} order by id limit $9 offset $10",
+                UserService::USER_FILTER_CONDITION
+            ))
+            .await?;
+
+        let [username, nickname, phone, email] = filter_patterns(filter);
+
+        let rows = pg
+            .query(
+                &statement,
+                &[
+                    &username,
+                    &nickname,
+                    &phone,
+                    &email,
+                    &filter.role_id,
+                    &filter.created_from,
+                    &filter.created_to,
+                    &filter.status,
+                    &pager.limit(),
+                    &pager.offset(),
+                ],
+            )
+            .await?;
+
+        let mut users = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            users.push(UserInfo::from_row_ref(row)?);
+        }
+
+        Ok(users)
+    }
+
+    /// 由管理员创建用户，并添加参数中的所有登录方式
+    ///
+    /// 参数格式错误或未填写任何登录方式时返回错误，用户名、手机号或电子邮箱已被使用时返回 `DUPLICATE_VALUE`
+    pub async fn add_user(&self, params: &CreateUserParams) -> Result<UserInfo, Error> {
+        let username = Username::new(&params.username)?;
+        let nickname = Nickname::new(&params.nickname)?;
+        if let Some(birthday) = &params.birthday {
+            check_birthday(birthday)?;
+        }
+        let password = params
+            .password
+            .as_deref()
+            .map(|password| Password::new(password, &self.password_policy))
+            .transpose()?;
+        let phone = params.phone.as_deref().map(Phone::new).transpose()?;
+        let email = params.email.as_deref().map(Email::new).transpose()?;
+
+        if password.is_none() && phone.is_none() && email.is_none() {
+            return Err(Kind::INVALID_PARAMS.into());
+        }
+
+        let hashed_pwd = match &password {
+            Some(password) => Some(self.hasher.hash(password.as_str()).await?),
+            None => None,
+        };
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        let statement = transaction
+            .prepare("insert into user_info(username, nickname, avatar, gender, birthday, max_role, status) values($1, $2, $3, $4, $5, $6, $7) returning *")
+            .await?;
+
+        let user_info = UserInfo::from_row(
+            transaction
+                .query_one(
+                    &statement,
+                    &[
+                        &username,
+                        &nickname,
+                        &params.avatar,
+                        &params.gender.clone().unwrap_or(Gender::Unknown),
+                        &params.birthday,
+                        &params.max_role,
+                        &params.status.unwrap_or(UserStatus::Active),
+                    ],
+                )
+                .await?,
+        )?;
+
+        let statement = transaction
+            .prepare("insert into user_auth(user_id, auth_type, identity, credential1) values($1, $2, $3, $4)")
+            .await?;
+
+        if let Some(hashed_pwd) = &hashed_pwd {
+            transaction
+                .execute(
+                    &statement,
+                    &[&user_info.id, &AuthType::Username, &username, hashed_pwd],
+                )
+                .await?;
+        }
+
+        if let Some(phone) = &phone {
+            transaction
+                .execute(&statement, &[&user_info.id, &AuthType::Phone, phone, &""])
+                .await?;
+        }
+
+        if let Some(email) = &email {
+            transaction
+                .execute(&statement, &[&user_info.id, &AuthType::Email, email, &""])
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(user_info)
+    }
+
+    /// 由管理员修改用户信息，返回修改后的用户
+    ///
+    /// 最大角色数小于用户当前拥有的角色数时返回 `USER_ROLE_LIMIT`
+    pub async fn update_user(&self, id: Id, content: &UserInfoContent) -> Result<UserInfo, Error> {
+        let nickname = Nickname::new(&content.nickname)?;
+        if let Some(birthday) = &content.birthday {
+            check_birthday(birthday)?;
+        }
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[id]).await?;
+
+        let statement = transaction
+            .prepare("update user_info set nickname = $1, avatar = $2, gender = $3, birthday = $4, max_role = $5 where id = $6 returning *")
+            .await?;
+
+        let user_info = UserInfo::from_row(
+            transaction
+                .query_one(
+                    &statement,
+                    &[
+                        &nickname,
+                        &content.avatar,
+                        &content.gender,
+                        &content.birthday,
+                        &content.max_role,
+                        &id,
+                    ],
+                )
+                .await?,
+        )?;
+
+        if let Some(max_role) = user_info.max_role {
+            let statement = transaction
+                .prepare("select count(1) from user_role where user_id = $1")
+                .await?;
+
+            let count: i64 = transaction.query_one(&statement, &[&id]).await?.get(0);
+
+            if count > max_role {
+                return Err(Kind::USER_ROLE_LIMIT.into());
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(user_info)
+    }
+
+    /// 修改个人信息，返回修改后的用户
+    ///
+    /// 使用 `update_time` 实现乐观并发控制：个人信息在读取后被修改过时返回 `UPDATE_CONFLICT`
+    pub async fn update_profile(
+        &self,
+        user_id: Id,
+        profile: &ProfileUpdate,
+    ) -> Result<UserInfo, Error> {
+        let nickname = Nickname::new(&profile.nickname)?;
+        if let Some(birthday) = &profile.birthday {
+            check_birthday(birthday)?;
+        }
+
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("update user_info set nickname = $1, gender = $2, birthday = $3 where id = $4 and update_time = $5 returning *")
+            .await?;
+
+        if let Some(row) = pg
+            .query_opt(
+                &statement,
+                &[
+                    &nickname,
+                    &profile.gender,
+                    &profile.birthday,
+                    &user_id,
+                    &profile.update_time,
+                ],
+            )
+            .await?
+        {
+            return Ok(UserInfo::from_row(row)?);
+        }
+
+        // 区分用户不存在与个人信息已被修改两种情况
+        self.query_user_by_id(user_id).await?;
+
+        Err(Kind::UPDATE_CONFLICT.into())
+    }
+
+    /// 修改用户状态，返回修改后的用户
+    ///
+    /// 用户不再是 `Active` 状态时注销其所有会话，其个人访问令牌也随之失效；
+    /// `reason` 去掉首尾空白后为空时不记录原因，超过 `MAX_STATUS_REASON_LEN` 个字符时返回 `INVALID_PARAMS`
+    pub async fn set_user_status(
+        &self,
+        id: Id,
+        status: UserStatus,
+        reason: Option<&str>,
+    ) -> Result<UserInfo, Error> {
+        let reason = reason.map(str::trim).filter(|reason| !reason.is_empty());
+        if reason.is_some_and(|reason| reason.chars().count() > UserService::MAX_STATUS_REASON_LEN)
+        {
+            return Err(Kind::INVALID_PARAMS.into());
+        }
+
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_users(&transaction, &[id]).await?;
+
+        let statement = transaction
+            .prepare(
+                "update user_info set status = $1, status_reason = $2 where id = $3 returning *",
+            )
+            .await?;
+
+        let user_info = UserInfo::from_row(
+            transaction
+                .query_one(&statement, &[&status, &reason, &id])
+                .await?,
+        )?;
+
+        transaction.commit().await?;
+
+        info!(
+            "用户 {} 的状态已变更为 {}，原因: {}",
+            user_info.username,
+            status,
+            reason.unwrap_or("-")
+        );
+
+        if status != UserStatus::Active {
+            self.revoke_all_sessions(id).await?;
+        }
+
+        Ok(user_info)
+    }
+
+    /// 软删除用户，即将用户状态修改为 `Deleted`
+    ///
+    /// 用户的登录方式、角色等数据均保留（仍占用其用户名、手机号及电子邮箱），可通过 `set_user_status` 恢复
+    pub async fn delete_user(&self, id: Id) -> Result<(), Error> {
+        self.set_user_status(id, UserStatus::Deleted, None)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn query_user_roles(&self, user_id: Id) -> Result<Vec<Role>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare(
+                "select * from role where id in (select role_id from user_role where user_id = $1)",
+            )
+            .await?;
+
+        let rows = pg.query(&statement, &[&user_id]).await?;
+
+        let mut roles = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            roles.push(Role::from_row_ref(row)?);
+        }
+
+        Ok(roles)
+    }
+
+    pub async fn query_user_auth(&self, user_id: Id) -> Result<Vec<UserAuth>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare("select * from user_auth where user_id = $1")
+            .await?;
+
+        let rows = pg.query(&statement, &[&user_id]).await?;
+
+        let mut auth = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            auth.push(UserAuth::from_row_ref(row)?);
+        }
+
+        Ok(auth)
+    }
+
+    /// 查询用户的所有有效权限，包括通过角色继承关系从父角色继承的权限
+    pub async fn query_user_perm(&self, user_id: Id) -> Result<Vec<Permission>, Error> {
+        let pg = self.pg_pool.get().await?;
+
+        let statement = pg
+            .prepare(
+                "with recursive roles(id) as (\
+                     select role_id from user_role where user_id = $1 \
+                     union \
+                     select role_ext.base_id from role_ext join roles on role_ext.derived_id = roles.id\
+                 ) \
+                 select * from permission where id in (select permission_id from role_permission where role_id in (select id from roles))",
+            )
+            .await?;
+
+        let rows = pg.query(&statement, &[&user_id]).await?;
+
+        let mut perms = Vec::with_capacity(rows.len());
+
+        for row in rows.iter() {
+            perms.push(Permission::from_row_ref(row)?);
+        }
+
+        Ok(perms)
+    }
+
+    /// 为用户授予角色
+    pub async fn grant_role(&self, user_id: Id, role_id: Id) -> Result<(), Error> {
+        self.grant_roles(user_id, &[role_id]).await
+    }
+
+    /// 为用户批量授予角色，任一角色授予失败则全部不生效
+    ///
+    /// 不能违反角色互斥约束、先决条件约束，以及角色的最大用户数和用户的最大角色数限制，
+    /// 先决条件角色须排在 `role_ids` 中依赖它的角色之前
+    pub async fn grant_roles(&self, user_id: Id, role_ids: &[Id]) -> Result<(), Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_constraints(&transaction).await?;
+        lock_users(&transaction, &[user_id]).await?;
+        lock_roles(&transaction, role_ids).await?;
+
+        for role_id in role_ids {
+            insert_user_role(&transaction, user_id, *role_id).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// 收回用户的角色，并注销用户的所有会话
+    ///
+    /// 如果该角色是用户其他角色的先决条件，`cascade` 为 `true` 时一并收回这些角色，否则返回错误
+    pub async fn revoke_role(&self, user_id: Id, role_id: Id, cascade: bool) -> Result<(), Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_constraints(&transaction).await?;
+        lock_users(&transaction, &[user_id]).await?;
+
+        if delete_user_roles(&transaction, user_id, &[role_id], cascade).await? == 0 {
+            return Err(Kind::EMPTY_RESULT.into());
+        }
+
+        transaction.commit().await?;
+
+        self.revoke_all_sessions(user_id).await
+    }
+
+    /// 批量收回用户的角色，用户未拥有的角色会被忽略，实际收回了角色时注销用户的所有会话
+    pub async fn revoke_roles(
+        &self,
+        user_id: Id,
+        role_ids: &[Id],
+        cascade: bool,
+    ) -> Result<(), Error> {
+        let mut pg = self.pg_pool.get().await?;
+
+        let transaction = pg.transaction().await?;
+
+        lock_constraints(&transaction).await?;
+        lock_users(&transaction, &[user_id]).await?;
+
+        let deleted = delete_user_roles(&transaction, user_id, role_ids, cascade).await?;
+
+        transaction.commit().await?;
+
+        if deleted > 0 {
+            self.revoke_all_sessions(user_id).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 用户名、昵称、手机号及电子邮箱的筛选条件对应的匹配模式
+fn filter_patterns(filter: &UserFilter) -> [Option<String>; 4] {
+    let pattern = |s: &Option<String>| s.as_deref().and_then(contains_pattern);
+
+    [
+        pattern(&filter.username),
+        pattern(&filter.nickname),
+        pattern(&filter.phone),
+        pattern(&filter.email),
+    ]
+}
+
+/// 检查用户能否登录：`Disabled`、`Pending` 状态分别返回 `USER_DISABLED`、`USER_PENDING`；
+/// 已删除的用户视为不存在，返回 `LOGIN_FAILED`
+pub(crate) fn check_user_status(user_info: &UserInfo) -> Result<(), Error> {
+    match user_info.status {
+        UserStatus::Active => Ok(()),
+        UserStatus::Disabled => Err(Kind::USER_DISABLED.into()),
+        UserStatus::Pending => Err(Kind::USER_PENDING.into()),
+        UserStatus::Deleted => Err(Kind::LOGIN_FAILED.into()),
+    }
+}
+
+/// 检查 `key` 对应的失败记录是否处于锁定期，是则返回 `kind` 错误及剩余锁定秒数
+pub(crate) async fn check_login_lock(
+    redis: &mut Connection,
+    key: &str,
+    kind: &'static Kind,
+) -> Result<(), Error> {
+    let locked_until: Option<i64> = cmd("HGET")
+        .arg(key)
+        .arg("locked_until")
+        .query_async(redis)
+        .await?;
+
+    let now = Utc::now().timestamp();
+
+    match locked_until {
+        Some(locked_until) if locked_until > now => {
+            Err(Error::from(kind).with_retry_after((locked_until - now) as u64))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 记录一次密码登录失败，失败次数达到 `threshold` 后按指数退避锁定
+pub(crate) async fn record_login_failure(
+    redis: &mut Connection,
+    key: &str,
+    threshold: u64,
+    client_ip: &ClientIp,
+) -> Result<(), Error> {
+    let now = Utc::now().timestamp();
+
+    let failure_count: u64 = cmd("HINCRBY")
+        .arg(key)
+        .arg("failure_count")
+        .arg(1)
+        .query_async(redis)
+        .await?;
+
+    cmd("HSET")
+        .arg(key)
+        .arg("last_failure_time")
+        .arg(now)
+        .arg("last_failure_ip")
+        .arg(client_ip.to_string())
+        .execute_async(redis)
+        .await?;
+
+    let mut expire = UserService::LOGIN_FAILURE_EXPIRE;
+
+    if failure_count >= threshold {
+        // 限制指数避免溢出，结果反正会被截断为 LOCK_MAX_SECONDS
+        let exp = (failure_count - threshold).min(32) as u32;
+        let lock_seconds =
+            (UserService::LOCK_BASE_SECONDS << exp).min(UserService::LOCK_MAX_SECONDS);
+
+        cmd("HSET")
+            .arg(key)
+            .arg("locked_until")
+            .arg(now + lock_seconds)
+            .execute_async(redis)
+            .await?;
+
+        expire = expire.max(lock_seconds);
+    }
+
+    Ok(cmd("EXPIRE")
+        .arg(key)
+        .arg(expire)
+        .execute_async(redis)
+        .await?)
+}
+
+/// 在事务中记录用户登录方式的变更，`old_identity` 为空表示绑定，`new_identity` 为空表示解绑
+pub(crate) async fn insert_auth_history(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    auth_type: AuthType,
+    old_identity: Option<&str>,
+    new_identity: Option<&str>,
+    client_ip: Option<&ClientIp>,
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare("insert into user_auth_history(user_id, auth_type, old_identity, new_identity, client_ip) values($1, $2, $3, $4, $5)")
+        .await?;
+
+    transaction
+        .execute(
+            &statement,
+            &[
+                &user_id,
+                &auth_type,
+                &old_identity,
+                &new_identity,
+                &client_ip.map(ClientIp::to_string),
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// 在事务中锁定用户所在行，用户不存在时返回错误
+///
+/// 授予/收回角色时先按 id 顺序锁定用户，再按 id 顺序锁定角色，避免并发事务死锁，
+/// 也保证了最大角色数/最大用户数检查不会被并发的授予操作绕过
+pub(crate) async fn lock_users(
+    transaction: &Transaction<'_>,
+    user_ids: &[Id],
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare("select id from user_info where id = any($1) order by id for update")
+        .await?;
+
+    let rows = transaction.query(&statement, &[&user_ids]).await?;
+
+    if rows.len() == user_ids.iter().unique().count() {
+        Ok(())
+    } else {
+        Err(Kind::EMPTY_RESULT.into())
+    }
+}
+
+/// 在事务中锁定角色所在行，角色不存在时返回错误
+pub(crate) async fn lock_roles(
+    transaction: &Transaction<'_>,
+    role_ids: &[Id],
+) -> Result<(), Error> {
+    let statement = transaction
+        .prepare("select id from role where id = any($1) order by id for update")
+        .await?;
+
+    let rows = transaction.query(&statement, &[&role_ids]).await?;
+
+    if rows.len() == role_ids.iter().unique().count() {
+        Ok(())
+    } else {
+        Err(Kind::EMPTY_RESULT.into())
+    }
+}
+
+/// 在事务中为用户授予角色，调用前须已锁定约束表、用户及角色
+pub(crate) async fn insert_user_role(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    role_id: Id,
+) -> Result<(), Error> {
+    check_mutex(transaction, user_id, role_id).await?;
+    check_base_required(transaction, user_id, role_id).await?;
+
+    let statement = transaction
+        .prepare("insert into user_role(user_id, role_id) values($1, $2) on conflict do nothing")
+        .await?;
+
+    if transaction
+        .execute(&statement, &[&user_id, &role_id])
+        .await?
+        == 0
+    {
+        // 用户已拥有该角色
+        return Ok(());
+    }
+
+    let statement = transaction
+        .prepare(
+            "select \
+             (select max_role from user_info where id = $1) < (select count(1) from user_role where user_id = $1), \
+             (select max_user from role where id = $2) < (select count(1) from user_role where role_id = $2)",
+        )
+        .await?;
+
+    let row = transaction
+        .query_one(&statement, &[&user_id, &role_id])
+        .await?;
+
+    if let Some(true) = row.get::<_, Option<bool>>(0) {
+        return Err(Kind::USER_ROLE_LIMIT.into());
+    }
+
+    if let Some(true) = row.get::<_, Option<bool>>(1) {
+        return Err(Kind::ROLE_USER_LIMIT.into());
+    }
+
+    Ok(())
+}
+
+/// 在事务中收回用户的角色，返回实际收回的角色数，调用前须已锁定约束表及用户
+///
+/// 如果这些角色是用户其他角色的先决条件，`cascade` 为 `true` 时一并收回，否则返回错误
+pub(crate) async fn delete_user_roles(
+    transaction: &Transaction<'_>,
+    user_id: Id,
+    role_ids: &[Id],
+    cascade: bool,
+) -> Result<u64, Error> {
+    let mut all_role_ids = role_ids.to_vec();
+
+    for role_id in role_ids {
+        for dependent in query_dependent_roles(transaction, user_id, *role_id).await? {
+            if !all_role_ids.contains(&dependent) {
+                if !cascade {
+                    return Err(Kind::BASE_ROLE_IN_USE.into());
+                }
+                all_role_ids.push(dependent);
+            }
+        }
+    }
+
+    let statement = transaction
+        .prepare("delete from user_role where user_id = $1 and role_id = any($2)")
+        .await?;
+
+    Ok(transaction
+        .execute(&statement, &[&user_id, &all_role_ids])
+        .await?)
+}
+
+/// 在事务中按角色名同步由外部身份（OIDC 声明、LDAP 分组）映射的角色，返回是否收回了角色
+///
+/// `managed` 为所有可能由映射授予的角色，其中不在 `granted` 中的会被收回，以其为先决条件的角色一并收回；
+/// `granted` 中的角色逐个授予，不存在或违反互斥等约束的角色只记录警告，不影响其他角色
+pub(crate) async fn sync_mapped_roles(
+    transaction: &mut Transaction<'_>,
+    user_id: Id,
+    granted: &[String],
+    managed: &[String],
+) -> Result<bool, Error> {
+    let role_names = granted.iter().chain(managed).unique().collect::<Vec<_>>();
+
+    if role_names.is_empty() {
+        return Ok(false);
+    }
+
+    let statement = transaction
+        .prepare("select id, name from role where name = any($1) order by id")
+        .await?;
+
+    let roles = transaction
+        .query(&statement, &[&role_names])
+        .await?
+        .iter()
+        .map(|row| (row.get::<_, Id>(0), row.get::<_, String>(1)))
+        .collect::<Vec<_>>();
+
+    if roles.len() < role_names.len() {
+        warn!("角色映射中的部分角色不存在: {:?}", role_names);
+    }
+
+    let role_ids = roles.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+    lock_constraints(transaction).await?;
+    lock_users(transaction, &[user_id]).await?;
+    lock_roles(transaction, &role_ids).await?;
+
+    let revoke_ids = roles
+        .iter()
+        .filter(|(_, name)| managed.contains(name) && !granted.contains(name))
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+
+    let revoked = !revoke_ids.is_empty()
+        && delete_user_roles(transaction, user_id, &revoke_ids, true).await? > 0;
+
+    for (role_id, name) in roles.iter().filter(|(_, name)| granted.contains(name)) {
+        let savepoint = transaction.transaction().await?;
+
+        match insert_user_role(&savepoint, user_id, *role_id).await {
+            Ok(()) => savepoint.commit().await?,
+            Err(e) => {
+                warn!("无法为用户 {} 授予映射的角色 {}: {}", user_id, name, e);
+                savepoint.rollback().await?;
+            }
+        }
+    }
+
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::Opts;
+
+    /// 测试在事务中执行，结束时回滚
+    async fn test_pool() -> Option<PgPool> {
+        Some(Opts::open_test().await?.db.create_pool().unwrap())
+    }
+
+    async fn create_role(transaction: &Transaction<'_>, name: &str) -> Id {
+        transaction
+            .query_one("insert into role(name) values($1) returning id", &[&name])
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    async fn create_constraint(
+        transaction: &Transaction<'_>,
+        name: &str,
+        constraint_type: &str,
+    ) -> Id {
+        transaction
+            .query_one(
+                "insert into role_constraint(constraint_name, constraint_type) values($1, $2::text::\"ConstraintType\") returning id",
+                &[&name, &constraint_type],
+            )
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    async fn grant(transaction: &Transaction<'_>, user_id: Id, role_ids: &[Id]) {
+        for role_id in role_ids {
+            transaction
+                .execute(
+                    "insert into user_role(user_id, role_id) values($1, $2)",
+                    &[&user_id, role_id],
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn held_roles(transaction: &Transaction<'_>, user_id: Id) -> Vec<String> {
+        transaction
+            .query(
+                "select role.name from user_role join role on role.id = user_role.role_id where user_id = $1 order by role.name",
+                &[&user_id],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[actix_rt::test]
+    async fn sync_mapped_roles_revokes_and_grants() {
+        let pool = match test_pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut pg = pool.get().await.unwrap();
+        let mut transaction = pg.transaction().await.unwrap();
+
+        let user_id: Id = transaction
+            .query_one(
+                "insert into user_info(username, nickname) values('sync_test', 'sync_test') returning id",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        // sync_base 为 sync_derived 的先决条件，sync_mutex 与 sync_blocked 互斥
+        let base = create_role(&transaction, "sync_base").await;
+        let derived = create_role(&transaction, "sync_derived").await;
+        let manual = create_role(&transaction, "sync_manual").await;
+        create_role(&transaction, "sync_mapped").await;
+        let mutex = create_role(&transaction, "sync_mutex").await;
+        let blocked = create_role(&transaction, "sync_blocked").await;
+
+        transaction
+            .execute(
+                "insert into role_ext(base_id, derived_id) values($1, $2)",
+                &[&base, &derived],
+            )
+            .await
+            .unwrap();
+        let constraint_id =
+            create_constraint(&transaction, "sync_base_required", "BaseRequired").await;
+        transaction
+            .execute(
+                "insert into constraint_base_required(constraint_id, role_id) values($1, $2)",
+                &[&constraint_id, &derived],
+            )
+            .await
+            .unwrap();
+        let constraint_id = create_constraint(&transaction, "sync_mutex", "Mutex").await;
+        for role_id in &[mutex, blocked] {
+            transaction
+                .execute(
+                    "insert into constraint_mutex(constraint_id, role_id) values($1, $2)",
+                    &[&constraint_id, role_id],
+                )
+                .await
+                .unwrap();
+        }
+
+        grant(&transaction, user_id, &[base, derived, manual]).await;
+
+        let managed = names(&["sync_base", "sync_mapped", "sync_blocked"]);
+
+        // 收回不再映射到的角色及以其为先决条件的角色，不影响非映射授予的角色
+        assert!(sync_mapped_roles(
+            &mut transaction,
+            user_id,
+            &names(&["sync_mapped"]),
+            &managed
+        )
+        .await
+        .unwrap());
+        assert_eq!(
+            held_roles(&transaction, user_id).await,
+            names(&["sync_manual", "sync_mapped"])
+        );
+
+        // 重复同步不会收回角色
+        assert!(!sync_mapped_roles(
+            &mut transaction,
+            user_id,
+            &names(&["sync_mapped"]),
+            &managed
+        )
+        .await
+        .unwrap());
+        assert_eq!(
+            held_roles(&transaction, user_id).await,
+            names(&["sync_manual", "sync_mapped"])
+        );
+
+        // 违反互斥约束及不存在的角色被跳过，其他角色照常授予
+        grant(&transaction, user_id, &[mutex]).await;
+        assert!(!sync_mapped_roles(
+            &mut transaction,
+            user_id,
+            &names(&["sync_blocked", "sync_missing", "sync_base", "sync_mapped"]),
+            &managed
+        )
+        .await
+        .unwrap());
+        assert_eq!(
+            held_roles(&transaction, user_id).await,
+            names(&["sync_base", "sync_manual", "sync_mapped", "sync_mutex"])
+        );
+
+        // 没有映射到任何角色时收回所有映射的角色
+        assert!(sync_mapped_roles(&mut transaction, user_id, &[], &managed)
+            .await
+            .unwrap());
+        assert_eq!(
+            held_roles(&transaction, user_id).await,
+            names(&["sync_manual", "sync_mutex"])
+        );
+
+        assert!(!sync_mapped_roles(&mut transaction, user_id, &[], &[])
+            .await
+            .unwrap());
+
+        transaction.rollback().await.unwrap();
     }
 }
@@ -1,24 +1,356 @@
 //! 加密相关工具
+//!
+//! 密码 hash 统一保存为 PHC 风格的字符串，由前缀区分算法：
+//!
+//! 1. `$argon2id$`: Argon2id，新密码默认使用的算法；
+//! 2. `$2a$`/`$2b$`/`$2y$`: bcrypt，通常来自导入的用户；
+//! 3. `$rpbkdf2$`: 早期版本使用的 PBKDF2（Rust PBKDF2 format），只用于校验。
+//!
+//! 登录成功后若 `PasswordHasher::needs_rehash` 返回 `true`，应使用当前配置重新生成 hash。
+//!
+//! 生成及校验密码 hash 比较耗时（默认配置约数十毫秒），均在线程池中执行，避免阻塞工作线程。
+//!
+//! 个人访问令牌是高熵的随机字符串，使用 SHA-256 保存即可。
 use crate::error::{Error, Kind};
+use actix_web::error::BlockingError;
+use actix_web::web;
+use argon2::{ThreadMode, Variant, Version};
 use pbkdf2::CheckError;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::iter;
 
-/// 使用随机盐值和随机迭代次数，
-/// 将密码使用 pbkdf2 算法生成 hash 并输出 Rust PBKF2 format 格式
-///
-/// 参考 `pbkdf2::pbkdf2_simple` 的注释
-pub fn hash_pwd(pwd: &str) -> Result<String, Error> {
-    pbkdf2::pbkdf2_simple(pwd, 10000).map_err(|e| Kind::CRYPTO_ERROR.with_detail(e))
+/// 个人访问令牌的前缀，便于在日志、代码仓库中识别泄露的令牌
+pub const ACCESS_TOKEN_PREFIX: &str = "admino_";
+const ACCESS_TOKEN_RAND_LEN: usize = 40;
+
+/// 生成新密码 hash 使用的算法及参数，由配置文件中的 `[hasher]` 决定
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum PasswordHasher {
+    Argon2 {
+        /// 内存开销，单位 KiB
+        memory_cost: u32,
+        /// 迭代次数
+        time_cost: u32,
+        /// 并行度
+        parallelism: u32,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+}
+
+impl Default for PasswordHasher {
+    /// 参考 OWASP 密码存储建议的 Argon2id 参数
+    fn default() -> Self {
+        PasswordHasher::Argon2 {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHasher {
+    /// 使用随机盐值生成密码 hash
+    pub async fn hash(&self, pwd: &str) -> Result<String, Error> {
+        let hasher = self.clone();
+        let pwd = pwd.to_owned();
+
+        web::block(move || hasher.hash_blocking(&pwd))
+            .await
+            .map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => Kind::WORKER_THREAD_ERROR.into(),
+            })
+    }
+
+    /// 同步生成密码 hash，需在线程池中执行
+    fn hash_blocking(&self, pwd: &str) -> Result<String, Error> {
+        match *self {
+            PasswordHasher::Argon2 {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+
+                let config = argon2::Config {
+                    variant: Variant::Argon2id,
+                    version: Version::Version13,
+                    mem_cost: memory_cost,
+                    time_cost,
+                    lanes: parallelism,
+                    thread_mode: ThreadMode::Sequential,
+                    secret: &[],
+                    ad: &[],
+                    hash_length: 32,
+                };
+
+                argon2::hash_encoded(pwd.as_bytes(), &salt, &config)
+                    .map_err(|e| Kind::CRYPTO_ERROR.with_detail(e))
+            }
+            PasswordHasher::Bcrypt { cost } => {
+                bcrypt::hash(pwd, cost).map_err(|e| Kind::CRYPTO_ERROR.with_detail(e))
+            }
+        }
+    }
+
+    /// 与当前配置的算法及参数相同、但不对应任何密码的 hash
+    ///
+    /// 用户不存在时使用它校验密码，使响应时间与用户存在时一致，避免通过响应时间探测用户名
+    pub fn dummy_hash(&self) -> String {
+        match *self {
+            PasswordHasher::Argon2 {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => format!(
+                "$argon2id$v=19$m={},t={},p={}${}${}",
+                memory_cost,
+                time_cost,
+                parallelism,
+                "A".repeat(22),
+                "A".repeat(43)
+            ),
+            PasswordHasher::Bcrypt { cost } => format!("$2b${:02}${}", cost, ".".repeat(53)),
+        }
+    }
+
+    /// 判断 `hashed_pwd` 是否使用了与当前配置不同的算法，或比当前配置更弱的参数
+    pub fn needs_rehash(&self, hashed_pwd: &str) -> bool {
+        match *self {
+            PasswordHasher::Argon2 {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => match parse_argon2_params(hashed_pwd) {
+                Some((m, t, p)) => m < memory_cost || t < time_cost || p < parallelism,
+                None => true,
+            },
+            PasswordHasher::Bcrypt { cost } => match parse_bcrypt_cost(hashed_pwd) {
+                Some(c) => c < cost,
+                None => true,
+            },
+        }
+    }
+}
+
+/// 从 `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>` 中解析出 (m, t, p)，不是 Argon2id 时返回 `None`
+fn parse_argon2_params(hashed_pwd: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = hashed_pwd.split('$').skip(1);
+    if parts.next()? != "argon2id" {
+        return None;
+    }
+
+    let mut params = parts.find(|part| part.starts_with("m="))?.split(',');
+    let mut param = |name: &str| -> Option<u32> {
+        params
+            .next()?
+            .strip_prefix(name)?
+            .strip_prefix('=')?
+            .parse()
+            .ok()
+    };
+
+    Some((param("m")?, param("t")?, param("p")?))
+}
+
+/// 从 `$2b$12$<salt+hash>` 中解析出 cost，不是 bcrypt 时返回 `None`
+fn parse_bcrypt_cost(hashed_pwd: &str) -> Option<u32> {
+    let mut parts = hashed_pwd.split('$').skip(1);
+    match parts.next()? {
+        "2a" | "2b" | "2y" => parts.next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// 生成个人访问令牌
+pub fn generate_access_token() -> String {
+    iter::repeat(())
+        .map(|()| OsRng.sample(Alphanumeric))
+        .take(ACCESS_TOKEN_RAND_LEN)
+        .fold(ACCESS_TOKEN_PREFIX.to_owned(), |mut token, c| {
+            token.push(c);
+            token
+        })
 }
 
-/// 校验明文密码与 Rust PBKDF2 format 格式的 hash 密码是否一致
+/// 个人访问令牌的 hash，十六进制的 SHA-256
+pub fn hash_access_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// 校验明文密码与 hash 密码是否一致，根据 hash 的前缀选择算法
 ///
-/// 参考 `pbkdf2::pbkdf2_check` 的注释
-pub fn check_pwd(pwd: &str, hashed_pwd: &str) -> Result<(), Error> {
-    pbkdf2::pbkdf2_check(pwd, hashed_pwd).map_err(|e| {
-        match e {
-            CheckError::HashMismatch => Kind::LOGIN_FAILED,
-            CheckError::InvalidFormat => Kind::CRYPTO_ERROR,
+/// 不一致时返回 `LOGIN_FAILED`，hash 格式无法识别时返回 `CRYPTO_ERROR`
+pub async fn check_pwd(pwd: &str, hashed_pwd: &str) -> Result<(), Error> {
+    let pwd = pwd.to_owned();
+    let hashed_pwd = hashed_pwd.to_owned();
+
+    web::block(move || check_pwd_blocking(&pwd, &hashed_pwd))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => Kind::WORKER_THREAD_ERROR.into(),
+        })
+}
+
+/// 同步校验密码，需在线程池中执行
+fn check_pwd_blocking(pwd: &str, hashed_pwd: &str) -> Result<(), Error> {
+    let matched = if hashed_pwd.starts_with("$argon2") {
+        argon2::verify_encoded(hashed_pwd, pwd.as_bytes())
+            .map_err(|e| Kind::CRYPTO_ERROR.with_detail(e))?
+    } else if parse_bcrypt_cost(hashed_pwd).is_some() {
+        bcrypt::verify(pwd, hashed_pwd).map_err(|e| Kind::CRYPTO_ERROR.with_detail(e))?
+    } else {
+        // 参考 `pbkdf2::pbkdf2_check` 的注释
+        match pbkdf2::pbkdf2_check(pwd, hashed_pwd) {
+            Ok(()) => true,
+            Err(CheckError::HashMismatch) => false,
+            Err(CheckError::InvalidFormat) => return Err(Kind::CRYPTO_ERROR.into()),
+        }
+    };
+
+    if matched {
+        Ok(())
+    } else {
+        Err(Kind::LOGIN_FAILED.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 早期版本 `pbkdf2::pbkdf2_simple("!23QweAsd", 10000)` 生成的 hash
+    const PBKDF2_HASH: &str = "$rpbkdf2$0$AAAnEA==$NQgVgFsNv/Dx9NYGuFcO2w==$gw1HDEjTIAJ6pVquV5xRpK0onnjMNTv4NB9cDKQYJ94=$";
+    /// `bcrypt::hash("!23QweAsd", 4)` 生成的 hash
+    const BCRYPT_HASH: &str = "$2b$04$U6r5.zrWrhIh5XYR1IC9sOCxBKLUxrF9dRG.W/BV54N.rt86.lr/6";
+
+    /// 参数较小的 Argon2id，加快测试
+    const ARGON2: PasswordHasher = PasswordHasher::Argon2 {
+        memory_cost: 256,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    fn error_code(result: Result<(), Error>) -> i64 {
+        result.unwrap_err().kind().code()
+    }
+
+    #[test]
+    fn check_legacy_pbkdf2_hash() {
+        assert!(check_pwd_blocking("!23QweAsd", PBKDF2_HASH).is_ok());
+        assert_eq!(
+            error_code(check_pwd_blocking("!23QweAsD", PBKDF2_HASH)),
+            Kind::LOGIN_FAILED.code()
+        );
+    }
+
+    #[test]
+    fn check_bcrypt_hash() {
+        assert!(check_pwd_blocking("!23QweAsd", BCRYPT_HASH).is_ok());
+        assert_eq!(
+            error_code(check_pwd_blocking("!23QweAsD", BCRYPT_HASH)),
+            Kind::LOGIN_FAILED.code()
+        );
+    }
+
+    #[test]
+    fn check_unknown_hash_format() {
+        assert_eq!(
+            error_code(check_pwd_blocking("!23QweAsd", "plain")),
+            Kind::CRYPTO_ERROR.code()
+        );
+    }
+
+    #[test]
+    fn argon2_hash_round_trip() {
+        let hashed = ARGON2.hash_blocking("!23QweAsd").unwrap();
+
+        assert!(hashed.starts_with("$argon2id$v=19$m=256,t=1,p=1$"));
+        assert!(check_pwd_blocking("!23QweAsd", &hashed).is_ok());
+        assert_eq!(
+            error_code(check_pwd_blocking("!23QweAsD", &hashed)),
+            Kind::LOGIN_FAILED.code()
+        );
+        assert!(!ARGON2.needs_rehash(&hashed));
+    }
+
+    #[test]
+    fn parse_argon2() {
+        assert_eq!(
+            parse_argon2_params("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"),
+            Some((19456, 2, 1))
+        );
+        assert_eq!(
+            parse_argon2_params("$argon2i$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"),
+            None
+        );
+        assert_eq!(
+            parse_argon2_params("$argon2id$v=19$m=19456,t=x,p=1$c2FsdA$aGFzaA"),
+            None
+        );
+        assert_eq!(
+            parse_argon2_params("$argon2id$v=19$m=19456,p=1$c2FsdA$aGFzaA"),
+            None
+        );
+        assert_eq!(parse_argon2_params(BCRYPT_HASH), None);
+        assert_eq!(parse_argon2_params(PBKDF2_HASH), None);
+    }
+
+    #[test]
+    fn argon2_needs_rehash() {
+        let hasher = PasswordHasher::default();
+
+        assert!(!hasher.needs_rehash("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"));
+        assert!(!hasher.needs_rehash("$argon2id$v=19$m=65536,t=3,p=4$c2FsdA$aGFzaA"));
+        assert!(hasher.needs_rehash("$argon2id$v=19$m=19455,t=2,p=1$c2FsdA$aGFzaA"));
+        assert!(hasher.needs_rehash("$argon2id$v=19$m=19456,t=1,p=1$c2FsdA$aGFzaA"));
+        assert!(hasher.needs_rehash("$argon2i$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"));
+        assert!(hasher.needs_rehash(BCRYPT_HASH));
+        assert!(hasher.needs_rehash(PBKDF2_HASH));
+    }
+
+    #[test]
+    fn bcrypt_needs_rehash() {
+        assert!(!PasswordHasher::Bcrypt { cost: 4 }.needs_rehash(BCRYPT_HASH));
+        assert!(PasswordHasher::Bcrypt { cost: 5 }.needs_rehash(BCRYPT_HASH));
+        assert!(PasswordHasher::Bcrypt { cost: 4 }.needs_rehash(PBKDF2_HASH));
+        assert!(PasswordHasher::Bcrypt { cost: 4 }
+            .needs_rehash("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"));
+    }
+
+    #[test]
+    fn dummy_hash_never_matches() {
+        for hasher in [ARGON2, PasswordHasher::Bcrypt { cost: 4 }].iter() {
+            let dummy = hasher.dummy_hash();
+            assert!(!hasher.needs_rehash(&dummy));
+            assert_eq!(
+                error_code(check_pwd_blocking("", &dummy)),
+                Kind::LOGIN_FAILED.code()
+            );
+            assert_eq!(
+                error_code(check_pwd_blocking("!23QweAsd", &dummy)),
+                Kind::LOGIN_FAILED.code()
+            );
         }
-        .into()
-    })
+    }
+
+    #[actix_rt::test]
+    async fn hash_and_check_in_thread_pool() {
+        let hashed = ARGON2.hash("!23QweAsd").await.unwrap();
+
+        assert!(check_pwd("!23QweAsd", &hashed).await.is_ok());
+        assert_eq!(
+            error_code(check_pwd("!23QweAsD", &hashed).await),
+            Kind::LOGIN_FAILED.code()
+        );
+    }
 }
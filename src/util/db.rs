@@ -24,3 +24,19 @@ pub struct QueryCondition {
     pub pager: Pager,
     pub order_by: Option<Vec<String>>,
 }
+
+/// 将用户输入转换为 `like`/`ilike` 的“包含”匹配模式，转义其中的通配符，输入为空白时返回 `None`
+pub fn contains_pattern(s: &str) -> Option<String> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return None;
+    }
+
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    Some(format!("%{}%", escaped))
+}
@@ -1,6 +1,13 @@
 //! HTTP 相关工具
 #![allow(dead_code)]
+use crate::error::{Error, Kind};
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 
 /// 从 Url 中的查询字符串构造的 HashMap Wrapper
 #[derive(Debug)]
@@ -37,3 +44,207 @@ impl<'a> From<&'a str> for QueryString<'a> {
         Self(map)
     }
 }
+
+/// 客户端 IP 地址，用于按 IP 限流
+///
+/// 默认取自 TCP 连接的对端地址；对端为可信的反向代理（见 `TrustedProxies`）时，
+/// 取 `Forwarded` 或 `X-Forwarded-For` 请求头中从右往左第一个不可信的地址，
+/// 不可信的对端发来的这些请求头会被忽略，防止伪造
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    /// 获取请求的客户端 IP 地址，没有对端地址时返回 `None`
+    pub fn of(req: &HttpRequest) -> Option<ClientIp> {
+        let peer = req.peer_addr()?.ip();
+
+        let trusted = match req.app_data::<TrustedProxies>() {
+            Some(trusted) if trusted.contains(&peer) => trusted,
+            _ => return Some(ClientIp(peer)),
+        };
+
+        // 转发链中离本服务最近的地址在最右边，从右往左跳过可信代理
+        let mut client = peer;
+        for node in forwarded_nodes(req).iter().rev() {
+            match parse_node(node) {
+                Some(ip) => {
+                    client = ip;
+                    if !trusted.contains(&ip) {
+                        break;
+                    }
+                }
+                // 无法识别的地址（如 `unknown` 或混淆标识）之前的内容不可信
+                None => break,
+            }
+        }
+
+        Some(ClientIp(client))
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = Error;
+    type Future = future::Ready<Result<ClientIp, Error>>;
+    type Config = TrustedProxies;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        future::ready(ClientIp::of(req).ok_or_else(|| Kind::UNKNOWN.into()))
+    }
+}
+
+/// 可信的反向代理地址，通过 `App::app_data` 注册，未注册时不信任任何代理
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+/// 按顺序返回转发链中的各个地址，优先使用 `Forwarded` 请求头
+fn forwarded_nodes(req: &HttpRequest) -> Vec<&str> {
+    let headers = req.headers();
+
+    let forwarded = headers
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| {
+                    let index = pair.find('=')?;
+                    if pair[..index].trim().eq_ignore_ascii_case("for") {
+                        Some(pair[index + 1..].trim())
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect()
+}
+
+/// 解析转发链中的地址，可能带引号、端口，IPv6 地址带端口时用方括号括起
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+
+    if node.starts_with('[') {
+        return node[1..node.find(']')?].parse().ok();
+    }
+
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn client_ip(peer: &str, headers: &[(&str, &str)]) -> String {
+        let mut req = TestRequest::default()
+            .peer_addr(format!("{}:40000", peer).parse().unwrap())
+            .app_data(TrustedProxies(vec![
+                PROXY.parse().unwrap(),
+                "fd00::1".parse().unwrap(),
+            ]));
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        ClientIp::of(&req.to_http_request()).unwrap().to_string()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_headers() {
+        assert_eq!(
+            client_ip("203.0.113.7", &[("x-forwarded-for", "198.51.100.1")]),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            client_ip("203.0.113.7", &[("forwarded", "for=198.51.100.1")]),
+            "203.0.113.7"
+        );
+
+        // 未注册可信代理时不读取请求头
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .header("x-forwarded-for", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(ClientIp::of(&req).unwrap().to_string(), PROXY);
+    }
+
+    #[test]
+    fn trusted_peer_reads_x_forwarded_for() {
+        assert_eq!(
+            client_ip(PROXY, &[("x-forwarded-for", "198.51.100.1")]),
+            "198.51.100.1"
+        );
+        // 客户端伪造的地址在左边，取最后一个不可信的地址
+        assert_eq!(
+            client_ip(
+                PROXY,
+                &[("x-forwarded-for", "192.0.2.9, 198.51.100.1, 10.0.0.1")]
+            ),
+            "198.51.100.1"
+        );
+        // 全部为可信代理时取最左边的地址
+        assert_eq!(
+            client_ip(PROXY, &[("x-forwarded-for", "fd00::1, 10.0.0.1")]),
+            "fd00::1"
+        );
+        // 没有请求头或地址无法识别时取对端地址
+        assert_eq!(client_ip(PROXY, &[]), PROXY);
+        assert_eq!(client_ip(PROXY, &[("x-forwarded-for", "garbage")]), PROXY);
+    }
+
+    #[test]
+    fn trusted_peer_reads_forwarded() {
+        assert_eq!(
+            client_ip(
+                PROXY,
+                &[
+                    (
+                        "forwarded",
+                        "for=192.0.2.9;proto=https, for=\"198.51.100.1:4711\""
+                    ),
+                    ("x-forwarded-for", "192.0.2.10")
+                ]
+            ),
+            "198.51.100.1"
+        );
+        assert_eq!(
+            client_ip(
+                PROXY,
+                &[("forwarded", "proto=https;For=\"[2001:db8::17]:4711\"")]
+            ),
+            "2001:db8::17"
+        );
+        assert_eq!(
+            client_ip(PROXY, &[("forwarded", "for=192.0.2.9, for=unknown")]),
+            PROXY
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! LDAP / Active Directory 目录服务
+//!
+//! 登录时先使用服务账号（未配置时匿名）绑定，按 `user_filter` 查找用户条目，
+//! 再使用该条目的 DN 及用户输入的密码绑定以校验密码。
+//!
+//! 用户所属的分组有两种来源，可同时使用：
+//!
+//! 1. 用户条目中的 `member_of_attr` 属性（如 Active Directory 的 `memberOf`）；
+//! 2. 在 `group_base_dn` 下按 `group_filter` 查找到的分组条目（如 OpenLDAP 的 `groupOfNames`）。
+//!
+//! 分组均以 DN 表示，`role_mapping` 中的 DN 不区分大小写。
+//!
+use crate::error::{Error, Kind};
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// 密码错误（invalidCredentials）
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// 目录服务配置
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LdapDirectory {
+    /// 目录服务地址，如 `ldap://127.0.0.1:389` 或 `ldaps://ad.example.com:636`
+    pub url: String,
+    /// 是否在 `ldap://` 连接上使用 StartTLS
+    #[serde(default)]
+    pub starttls: bool,
+    /// 连接及操作的超时时间（秒）
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// 用于查找用户及分组的服务账号，不配置时匿名绑定
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub user_base_dn: String,
+    /// 查找用户的过滤器，`{username}` 会被替换为转义后的用户名
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    /// 用户名属性，其值（小写）作为 `Ldap` 授权的身份标识
+    #[serde(default = "default_username_attr")]
+    pub username_attr: String,
+    /// 自动创建用户时作为昵称的属性
+    #[serde(default = "default_nickname_attr")]
+    pub nickname_attr: String,
+    /// 用户条目中记录所属分组的属性，如 `memberOf`
+    pub member_of_attr: Option<String>,
+    /// 分组条目的查找范围，不配置时不查找分组条目
+    pub group_base_dn: Option<String>,
+    /// 查找用户所属分组的过滤器，`{dn}` 会被替换为转义后的用户 DN
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    /// 目录用户首次登录时是否自动创建用户，为 `false` 时只能登录已关联的用户
+    #[serde(default = "default_auto_create")]
+    pub auto_create: bool,
+    /// 分组 DN 到角色名的映射，登录及定期同步时授予用户映射到的角色，并收回不再映射到的角色
+    #[serde(default)]
+    pub role_mapping: HashMap<String, String>,
+    /// 定期同步所有目录用户角色的间隔（秒），为 0 时只在登录时同步
+    #[serde(default)]
+    pub sync_interval: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_user_filter() -> String {
+    "(uid={username})".into()
+}
+
+fn default_username_attr() -> String {
+    "uid".into()
+}
+
+fn default_nickname_attr() -> String {
+    "cn".into()
+}
+
+fn default_group_filter() -> String {
+    "(|(member={dn})(uniqueMember={dn}))".into()
+}
+
+fn default_auto_create() -> bool {
+    true
+}
+
+/// 目录中的用户
+#[derive(Debug, Clone)]
+pub struct LdapUser {
+    pub dn: String,
+    /// 小写的用户名
+    pub username: String,
+    pub nickname: Option<String>,
+    /// 所属分组的 DN
+    pub groups: Vec<String>,
+}
+
+/// 访问目录服务失败的原因
+#[derive(Debug)]
+struct LdapError(String);
+
+impl fmt::Display for LdapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for LdapError {}
+
+fn ldap_error<E: fmt::Display>(e: E) -> Error {
+    Kind::LDAP_ERROR.with_detail(LdapError(e.to_string()))
+}
+
+impl LdapDirectory {
+    /// 定期同步的间隔，未开启时返回 `None`
+    pub fn sync_interval(&self) -> Option<Duration> {
+        if self.sync_interval > 0 {
+            Some(Duration::from_secs(self.sync_interval))
+        } else {
+            None
+        }
+    }
+
+    /// `user` 所属分组映射到的角色名
+    pub fn mapped_roles(&self, user: &LdapUser) -> Vec<String> {
+        self.role_mapping
+            .iter()
+            .filter(|(group, _)| user.groups.iter().any(|g| g.eq_ignore_ascii_case(group)))
+            .map(|(_, role)| role.clone())
+            .collect()
+    }
+
+    /// 所有可能由映射授予的角色名
+    pub fn managed_roles(&self) -> Vec<String> {
+        self.role_mapping.values().cloned().collect()
+    }
+
+    /// 查找用户的过滤器，用户名中的特殊字符会被转义
+    fn format_user_filter(&self, username: &str) -> String {
+        self.user_filter
+            .replace("{username}", &ldap_escape(username))
+    }
+
+    /// 查找用户所属分组的过滤器，DN 中的特殊字符会被转义
+    fn format_group_filter(&self, dn: &str) -> String {
+        self.group_filter.replace("{dn}", &ldap_escape(dn))
+    }
+
+    /// 连接目录服务，并使用服务账号绑定
+    async fn connect(&self) -> Result<Ldap, Error> {
+        let timeout = Duration::from_secs(self.timeout);
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(self.starttls);
+
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(ldap_error)?;
+
+        actix_rt::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                warn!("LDAP 连接错误: {}", e);
+            }
+        });
+
+        ldap.with_timeout(timeout);
+
+        let (bind_dn, bind_password) = match (&self.bind_dn, &self.bind_password) {
+            (Some(bind_dn), Some(bind_password)) => (bind_dn.as_str(), bind_password.as_str()),
+            _ => ("", ""),
+        };
+
+        ldap.simple_bind(bind_dn, bind_password)
+            .await
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+
+        Ok(ldap)
+    }
+
+    /// 按用户名查找用户条目，不存在或匹配到多个条目时返回 `None`
+    async fn search_user(
+        &self,
+        ldap: &mut Ldap,
+        username: &str,
+    ) -> Result<Option<LdapUser>, Error> {
+        let filter = self.format_user_filter(username);
+
+        let mut attrs = vec![self.username_attr.as_str(), self.nickname_attr.as_str()];
+        if let Some(member_of_attr) = &self.member_of_attr {
+            attrs.push(member_of_attr);
+        }
+
+        ldap.with_timeout(Duration::from_secs(self.timeout));
+        let (entries, _) = ldap
+            .search(&self.user_base_dn, Scope::Subtree, &filter, attrs)
+            .await
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+
+        if entries.len() > 1 {
+            warn!(
+                "用户名 {} 在目录中匹配到了 {} 个条目",
+                username,
+                entries.len()
+            );
+            return Ok(None);
+        }
+
+        let entry = match entries.into_iter().next() {
+            Some(entry) => SearchEntry::construct(entry),
+            None => return Ok(None),
+        };
+
+        let first = |attr: &str| {
+            entry
+                .attrs
+                .get(attr)
+                .and_then(|values| values.first())
+                .cloned()
+        };
+
+        let username = match first(&self.username_attr) {
+            Some(username) => username.to_lowercase(),
+            None => return Ok(None),
+        };
+        let nickname = first(&self.nickname_attr);
+
+        let mut groups = self
+            .member_of_attr
+            .as_ref()
+            .and_then(|attr| entry.attrs.get(attr))
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(group_base_dn) = &self.group_base_dn {
+            let filter = self.format_group_filter(&entry.dn);
+
+            ldap.with_timeout(Duration::from_secs(self.timeout));
+            let (entries, _) = ldap
+                .search(group_base_dn, Scope::Subtree, &filter, vec!["1.1"])
+                .await
+                .and_then(|result| result.success())
+                .map_err(ldap_error)?;
+
+            groups.extend(
+                entries
+                    .into_iter()
+                    .map(|entry| SearchEntry::construct(entry).dn),
+            );
+        }
+
+        Ok(Some(LdapUser {
+            dn: entry.dn,
+            username,
+            nickname,
+            groups,
+        }))
+    }
+
+    /// 校验用户名及密码，用户不存在或密码错误时返回 `None`
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<LdapUser>, Error> {
+        // 空密码会被目录服务视为匿名绑定而成功
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ldap = self.connect().await?;
+
+        let user = match self.search_user(&mut ldap, username).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let result = ldap
+            .simple_bind(&user.dn, password)
+            .await
+            .map_err(ldap_error)?;
+
+        let _ = ldap.unbind().await;
+
+        match result.rc {
+            0 => Ok(Some(user)),
+            INVALID_CREDENTIALS => Ok(None),
+            _ => Err(ldap_error(result)),
+        }
+    }
+
+    /// 按用户名查找用户，不校验密码，用于定期同步
+    pub async fn find_user(&self, username: &str) -> Result<Option<LdapUser>, Error> {
+        let mut ldap = self.connect().await?;
+        let user = self.search_user(&mut ldap, username).await;
+        let _ = ldap.unbind().await;
+        user
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const PEOPLE: &str = "ou=people,dc=example,dc=org";
+    const GROUPS: &str = "ou=groups,dc=example,dc=org";
+    const ALICE: &str = "cn=Alice (Ops),ou=people,dc=example,dc=org";
+    const SERVICE: &str = "cn=admino,dc=example,dc=org";
+
+    /// 模拟的目录服务收到的过滤器
+    #[derive(Debug, Clone, PartialEq)]
+    enum Filter {
+        And(Vec<Filter>),
+        Or(Vec<Filter>),
+        Eq(String, String),
+        Present(String),
+        Other(u8),
+    }
+
+    struct Entry {
+        dn: String,
+        password: Option<String>,
+        attrs: Vec<(String, Vec<String>)>,
+    }
+
+    impl Entry {
+        fn new(dn: &str, password: Option<&str>, attrs: &[(&str, &[&str])]) -> Self {
+            Entry {
+                dn: dn.into(),
+                password: password.map(String::from),
+                attrs: attrs
+                    .iter()
+                    .map(|(attr, values)| {
+                        (
+                            attr.to_string(),
+                            values.iter().map(|v| v.to_string()).collect(),
+                        )
+                    })
+                    .collect(),
+            }
+        }
+
+        fn matches(&self, filter: &Filter) -> bool {
+            match filter {
+                Filter::And(filters) => filters.iter().all(|f| self.matches(f)),
+                Filter::Or(filters) => filters.iter().any(|f| self.matches(f)),
+                Filter::Eq(attr, value) => self.attrs.iter().any(|(a, values)| {
+                    a.eq_ignore_ascii_case(attr)
+                        && values.iter().any(|v| v.eq_ignore_ascii_case(value))
+                }),
+                Filter::Present(attr) => {
+                    self.attrs.iter().any(|(a, _)| a.eq_ignore_ascii_case(attr))
+                }
+                Filter::Other(_) => false,
+            }
+        }
+    }
+
+    /// 在本地线程中运行的目录服务，只支持简单绑定、查找及解绑
+    struct Stub {
+        url: String,
+        connections: Arc<AtomicUsize>,
+        filters: Arc<Mutex<Vec<Filter>>>,
+    }
+
+    /// 读取一个 BER 编码的值，返回 (tag, 内容, 剩余的数据)
+    fn parse_tlv(data: &[u8]) -> (u8, &[u8], &[u8]) {
+        let tag = data[0];
+        let (len, offset) = if data[1] < 0x80 {
+            (data[1] as usize, 2)
+        } else {
+            let n = (data[1] & 0x7f) as usize;
+            let len = data[2..2 + n]
+                .iter()
+                .fold(0, |len, b| (len << 8) | *b as usize);
+            (len, 2 + n)
+        };
+        (tag, &data[offset..offset + len], &data[offset + len..])
+    }
+
+    fn parse_seq(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut values = Vec::new();
+        while !data.is_empty() {
+            let (tag, content, rest) = parse_tlv(data);
+            values.push((tag, content));
+            data = rest;
+        }
+        values
+    }
+
+    fn parse_int(data: &[u8]) -> i64 {
+        data.iter()
+            .fold(if data[0] & 0x80 != 0 { -1 } else { 0 }, |n, b| {
+                (n << 8) | *b as i64
+            })
+    }
+
+    fn parse_str(data: &[u8]) -> String {
+        String::from_utf8(data.to_vec()).unwrap()
+    }
+
+    fn parse_filter(tag: u8, content: &[u8]) -> Filter {
+        match tag {
+            0xa0 => Filter::And(
+                parse_seq(content)
+                    .into_iter()
+                    .map(|(t, c)| parse_filter(t, c))
+                    .collect(),
+            ),
+            0xa1 => Filter::Or(
+                parse_seq(content)
+                    .into_iter()
+                    .map(|(t, c)| parse_filter(t, c))
+                    .collect(),
+            ),
+            0xa3 => {
+                let values = parse_seq(content);
+                Filter::Eq(parse_str(values[0].1), parse_str(values[1].1))
+            }
+            0x87 => Filter::Present(parse_str(content)),
+            _ => Filter::Other(tag),
+        }
+    }
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut data = vec![tag];
+        let len = content.len();
+        if len < 0x80 {
+            data.push(len as u8);
+        } else {
+            data.extend(&[0x82, (len >> 8) as u8, len as u8]);
+        }
+        data.extend(content);
+        data
+    }
+
+    fn int(tag: u8, n: i64) -> Vec<u8> {
+        let bytes = n.to_be_bytes();
+        let mut start = 0;
+        while start < 7
+            && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+                || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+        {
+            start += 1;
+        }
+        tlv(tag, &bytes[start..])
+    }
+
+    fn ldap_result(tag: u8, rc: u8) -> Vec<u8> {
+        tlv(
+            tag,
+            &[int(0x0a, rc as i64), tlv(0x04, b""), tlv(0x04, b"")].concat(),
+        )
+    }
+
+    fn search_entry(entry: &Entry) -> Vec<u8> {
+        let attrs = entry
+            .attrs
+            .iter()
+            .map(|(attr, values)| {
+                let values = values
+                    .iter()
+                    .map(|v| tlv(0x04, v.as_bytes()))
+                    .collect::<Vec<_>>()
+                    .concat();
+                tlv(
+                    0x30,
+                    &[tlv(0x04, attr.as_bytes()), tlv(0x31, &values)].concat(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        tlv(
+            0x64,
+            &[tlv(0x04, entry.dn.as_bytes()), tlv(0x30, &attrs)].concat(),
+        )
+    }
+
+    fn read_message(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).ok()?;
+        let len = if header[1] < 0x80 {
+            header[1] as usize
+        } else {
+            let mut len = vec![0; (header[1] & 0x7f) as usize];
+            stream.read_exact(&mut len).ok()?;
+            len.iter().fold(0, |len, b| (len << 8) | *b as usize)
+        };
+        let mut content = vec![0; len];
+        stream.read_exact(&mut content).ok()?;
+        Some(content)
+    }
+
+    fn serve(mut stream: TcpStream, entries: &[Entry], filters: &Mutex<Vec<Filter>>) {
+        while let Some(message) = read_message(&mut stream) {
+            let values = parse_seq(&message);
+            let id = parse_int(values[0].1);
+            let (op, content) = values[1];
+
+            let mut responses = Vec::new();
+            match op {
+                // BindRequest
+                0x60 => {
+                    let values = parse_seq(content);
+                    let (dn, password) = (parse_str(values[1].1), parse_str(values[2].1));
+                    let success = (dn.is_empty() && password.is_empty())
+                        || entries.iter().any(|entry| {
+                            entry.dn.eq_ignore_ascii_case(&dn)
+                                && entry.password.as_deref() == Some(password.as_str())
+                        });
+                    responses.push(ldap_result(0x61, if success { 0 } else { 49 }));
+                }
+                // SearchRequest
+                0x63 => {
+                    let values = parse_seq(content);
+                    let base = parse_str(values[0].1).to_lowercase();
+                    let filter = parse_filter(values[6].0, values[6].1);
+                    responses.extend(
+                        entries
+                            .iter()
+                            .filter(|entry| entry.dn.to_lowercase().ends_with(&base))
+                            .filter(|entry| entry.matches(&filter))
+                            .map(search_entry),
+                    );
+                    responses.push(ldap_result(0x65, 0));
+                    filters.lock().unwrap().push(filter);
+                }
+                // UnbindRequest
+                _ => return,
+            }
+
+            for response in responses {
+                let message = tlv(0x30, &[int(0x02, id), response].concat());
+                if stream.write_all(&message).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn start_stub() -> Stub {
+        let entries = Arc::new(vec![
+            Entry::new(SERVICE, Some("service"), &[]),
+            Entry::new(
+                ALICE,
+                Some("secret"),
+                &[
+                    ("uid", &["Alice"]),
+                    ("cn", &["Alice Liddell"]),
+                    ("memberOf", &["cn=Admins,ou=groups,dc=example,dc=org"]),
+                ],
+            ),
+            Entry::new(
+                "cn=dup1,ou=people,dc=example,dc=org",
+                Some("secret"),
+                &[("uid", &["dup"])],
+            ),
+            Entry::new(
+                "cn=dup2,ou=people,dc=example,dc=org",
+                Some("secret"),
+                &[("uid", &["dup"])],
+            ),
+            Entry::new(
+                "cn=ops,ou=groups,dc=example,dc=org",
+                None,
+                &[("member", &[ALICE])],
+            ),
+            Entry::new(
+                "cn=dev,ou=groups,dc=example,dc=org",
+                None,
+                &[("uniqueMember", &["cn=bob,ou=people,dc=example,dc=org"])],
+            ),
+        ]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stub = Stub {
+            url: format!("ldap://{}", listener.local_addr().unwrap()),
+            connections: Arc::default(),
+            filters: Arc::default(),
+        };
+
+        let connections = stub.connections.clone();
+        let filters = stub.filters.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                connections.fetch_add(1, Ordering::SeqCst);
+                let entries = entries.clone();
+                let filters = filters.clone();
+                thread::spawn(move || serve(stream.unwrap(), &entries, &filters));
+            }
+        });
+
+        stub
+    }
+
+    fn directory(url: &str) -> LdapDirectory {
+        serde_json::from_value(json!({
+            "url": url,
+            "bind_dn": SERVICE,
+            "bind_password": "service",
+            "user_base_dn": PEOPLE,
+            "member_of_attr": "memberOf",
+            "group_base_dn": GROUPS,
+            "role_mapping": {
+                "CN=admins,OU=groups,DC=example,DC=org": "admin",
+                "cn=ops,ou=groups,dc=example,dc=org": "operator",
+                "cn=dev,ou=groups,dc=example,dc=org": "developer",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_are_escaped() {
+        let directory = directory("ldap://127.0.0.1:389");
+
+        assert_eq!(directory.format_user_filter("alice"), "(uid=alice)");
+        assert_eq!(
+            directory.format_user_filter("*)(uid=*"),
+            r"(uid=\2a\29\28uid=\2a)"
+        );
+        assert_eq!(directory.format_user_filter(r"a\00b"), r"(uid=a\5c00b)");
+        assert_eq!(
+            directory.format_group_filter(ALICE),
+            r"(|(member=cn=Alice \28Ops\29,ou=people,dc=example,dc=org)(uniqueMember=cn=Alice \28Ops\29,ou=people,dc=example,dc=org))"
+        );
+    }
+
+    #[test]
+    fn mapped_roles_ignore_case() {
+        let directory = directory("ldap://127.0.0.1:389");
+
+        let user = LdapUser {
+            dn: ALICE.into(),
+            username: "alice".into(),
+            nickname: None,
+            groups: vec![
+                "cn=Admins,ou=groups,dc=example,dc=org".into(),
+                "cn=unmapped,ou=groups,dc=example,dc=org".into(),
+            ],
+        };
+        assert_eq!(directory.mapped_roles(&user), vec!["admin".to_string()]);
+
+        let mut managed = directory.managed_roles();
+        managed.sort();
+        assert_eq!(managed, vec!["admin", "developer", "operator"]);
+    }
+
+    #[actix_rt::test]
+    async fn authenticate_user() {
+        let stub = start_stub();
+        let directory = directory(&stub.url);
+
+        let user = directory
+            .authenticate("alice", "secret")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.dn, ALICE);
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.nickname.as_deref(), Some("Alice Liddell"));
+        assert_eq!(
+            user.groups,
+            vec![
+                "cn=Admins,ou=groups,dc=example,dc=org",
+                "cn=ops,ou=groups,dc=example,dc=org",
+            ]
+        );
+
+        let mut roles = directory.mapped_roles(&user);
+        roles.sort();
+        assert_eq!(roles, vec!["admin", "operator"]);
+
+        // 分组过滤器中的 DN 以原值传给目录服务，括号不会改变过滤器的结构
+        assert_eq!(
+            *stub.filters.lock().unwrap(),
+            vec![
+                Filter::Eq("uid".into(), "alice".into()),
+                Filter::Or(vec![
+                    Filter::Eq("member".into(), ALICE.into()),
+                    Filter::Eq("uniqueMember".into(), ALICE.into()),
+                ]),
+            ]
+        );
+
+        // 定期同步时不校验密码
+        let user = directory.find_user("ALICE").await.unwrap().unwrap();
+        assert_eq!(user.username, "alice");
+    }
+
+    #[actix_rt::test]
+    async fn authenticate_failures() {
+        let stub = start_stub();
+        let directory = directory(&stub.url);
+
+        assert!(directory
+            .authenticate("alice", "wrong")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(directory
+            .authenticate("nobody", "secret")
+            .await
+            .unwrap()
+            .is_none());
+        // 匹配到多个条目
+        assert!(directory
+            .authenticate("dup", "secret")
+            .await
+            .unwrap()
+            .is_none());
+
+        // 服务账号密码错误
+        let mut directory = directory;
+        directory.bind_password = Some("wrong".into());
+        let e = directory.authenticate("alice", "secret").await.unwrap_err();
+        assert_eq!(e.kind().code(), Kind::LDAP_ERROR.code());
+    }
+
+    #[actix_rt::test]
+    async fn authenticate_escapes_username() {
+        let stub = start_stub();
+        let directory = directory(&stub.url);
+
+        for username in &["*", "*)(uid=*", "alice)(|(uid=*"] {
+            assert!(directory
+                .authenticate(username, "secret")
+                .await
+                .unwrap()
+                .is_none());
+        }
+
+        // 用户名整体作为一个等值断言的值，不会变成存在性或组合过滤器
+        assert_eq!(
+            *stub.filters.lock().unwrap(),
+            vec![
+                Filter::Eq("uid".into(), "*".into()),
+                Filter::Eq("uid".into(), "*)(uid=*".into()),
+                Filter::Eq("uid".into(), "alice)(|(uid=*".into()),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn authenticate_rejects_empty_credentials() {
+        let stub = start_stub();
+        let directory = directory(&stub.url);
+
+        assert!(directory.authenticate("alice", "").await.unwrap().is_none());
+        assert!(directory
+            .authenticate("", "secret")
+            .await
+            .unwrap()
+            .is_none());
+
+        // 空密码不会被发送给目录服务，以免被当作匿名绑定
+        assert_eq!(stub.connections.load(Ordering::SeqCst), 0);
+    }
+}
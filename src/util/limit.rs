@@ -0,0 +1,93 @@
+//! 基于 Redis 的频率限制
+//!
+//! 1. `Limit`: 滑动窗口限流，使用有序集合记录窗口内每次请求的时间戳，
+//!    超出次数上限时返回 `TOO_MANY_REQUESTS`，并告知客户端最早可重试的时间；
+//! 2. `cooldown`: 两次操作之间的冷却时间，冷却期内返回 `AUTH_CODE_COOLDOWN`。
+//!
+use crate::error::{Error, Kind};
+use deadpool_redis::{cmd, Connection};
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 滑动窗口限流的 Lua 脚本，保证「清理过期记录 - 计数 - 记录本次请求」的原子性
+///
+/// 返回 0 表示允许本次请求，否则返回需等待的毫秒数
+const SLIDING_WINDOW_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[3]) then
+    local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+    return tonumber(oldest[2]) + window - now
+end
+redis.call('ZADD', KEYS[1], now, ARGV[4])
+redis.call('PEXPIRE', KEYS[1], window)
+return 0
+";
+
+/// 滑动窗口限流规则：任意 `window` 秒内最多 `max` 次
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub max: u64,
+    pub window: u64,
+}
+
+impl Limit {
+    pub const fn new(max: u64, window: u64) -> Self {
+        Self { max, window }
+    }
+
+    /// 记录一次请求，超出限制时返回错误，且本次请求不计入窗口
+    pub async fn hit(&self, redis: &mut Connection, key: &str) -> Result<(), Error> {
+        let now = now_millis();
+        let wait: u64 = cmd("EVAL")
+            .arg(SLIDING_WINDOW_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(now)
+            .arg(self.window * 1000)
+            .arg(self.max)
+            .arg(format!("{}-{}", now, OsRng.gen::<u32>()))
+            .query_async(redis)
+            .await?;
+
+        if wait == 0 {
+            Ok(())
+        } else {
+            Err(Error::from(Kind::TOO_MANY_REQUESTS).with_retry_after(wait.div_ceil(1000)))
+        }
+    }
+}
+
+/// 在 `seconds` 秒内只允许操作一次，冷却期内返回错误
+pub async fn cooldown(redis: &mut Connection, key: &str, seconds: u64) -> Result<(), Error> {
+    let set: Option<String> = cmd("SET")
+        .arg(key)
+        .arg(1)
+        .arg("EX")
+        .arg(seconds)
+        .arg("NX")
+        .query_async(redis)
+        .await?;
+
+    if set.is_some() {
+        return Ok(());
+    }
+
+    let ttl: i64 = cmd("TTL").arg(key).query_async(redis).await?;
+
+    Err(Error::from(Kind::AUTH_CODE_COOLDOWN).with_retry_after(ttl.max(1) as u64))
+}
+
+/// 清除冷却状态，用于操作失败后允许立即重试
+pub async fn reset_cooldown(redis: &mut Connection, key: &str) -> Result<(), Error> {
+    Ok(cmd("DEL").arg(key).execute_async(redis).await?)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
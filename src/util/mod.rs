@@ -2,5 +2,13 @@
 pub mod crypto;
 pub mod db;
 pub mod http;
+pub mod jwt;
+pub mod ldap;
+pub mod limit;
+pub mod oidc;
+pub mod permission;
+pub mod sender;
+pub mod storage;
+pub mod totp;
 pub mod types;
 pub mod user;
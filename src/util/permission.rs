@@ -0,0 +1,432 @@
+//! 权限校验中间件
+//!
+//! 可以挂在 `web::resource` 或 `Scope` 上，声明访问其中路由所需的权限名，
+//! 每次收到 HTTP 请求时通过 `UserService` 查询当前用户的有效权限
+//! (`user_role` → `role_permission` → `permission`)，不满足时直接返回错误。
+//!
+//! 拥有管理权限的用户还必须已启用两步验证（见 `TotpService`）；
+//! 使用个人访问令牌认证时，所需权限还必须在令牌的 `scopes` 中；
+//! 使用 JWT 认证时，JWT 中的权限版本必须是最新的，否则删除 JWT 并返回错误，客户端重试即可。
+//!
+//! # Example
+//!
+//! ```no_run
+//! web::scope("/role").wrap(PermissionFactory::new("role:write").read("role:read"))
+//! ```
+//!
+use crate::error::{Error, Kind};
+use crate::model::Id;
+use crate::service::totp::TotpService;
+use crate::service::user::UserService;
+use crate::util::user::{
+    get_service_identity, get_service_perm_version, get_service_scopes, set_service_jwt_stale,
+};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error as ActixError;
+use failure::_core::cell::RefCell;
+use futures::future::LocalBoxFuture;
+use futures::task::{Context, Poll};
+use futures::{future, FutureExt};
+use std::rc::Rc;
+
+struct PermissionInner {
+    /// 非安全方法(POST/PUT/PATCH/DELETE 等)所需的权限
+    write: String,
+    /// 安全方法(GET/HEAD/OPTIONS/TRACE)所需的权限，为 `None` 时与 `write` 相同
+    read: Option<String>,
+}
+
+impl PermissionInner {
+    fn required(&self, req: &ServiceRequest) -> &str {
+        match &self.read {
+            Some(read) if req.method().is_safe() => read,
+            _ => &self.write,
+        }
+    }
+}
+
+/// 权限校验中间件工厂
+pub struct PermissionFactory {
+    inner: Rc<PermissionInner>,
+}
+
+impl PermissionFactory {
+    /// 访问所有路由都需要权限 `permission_name`
+    pub fn new<T: Into<String>>(permission_name: T) -> Self {
+        Self {
+            inner: Rc::new(PermissionInner {
+                write: permission_name.into(),
+                read: None,
+            }),
+        }
+    }
+
+    /// 访问安全方法(GET/HEAD 等只读请求)的路由改为需要权限 `permission_name`
+    pub fn read<T: Into<String>>(mut self, permission_name: T) -> PermissionFactory {
+        Rc::get_mut(&mut self.inner).unwrap().read = Some(permission_name.into());
+        self
+    }
+}
+
+impl<S, B> Transform<S> for PermissionFactory
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Transform = PermissionMiddleware<S>;
+    type InitError = ();
+    type Future = future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(PermissionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.inner.clone(),
+        })
+    }
+}
+
+/// 权限校验中间件
+pub struct PermissionMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    inner: Rc<PermissionInner>,
+}
+
+/// 检查当前用户是否拥有权限 `permission_name`，且已启用两步验证
+async fn check_permission(req: &ServiceRequest, permission_name: &str) -> Result<(), Error> {
+    let user_id: Id = get_service_identity(req).ok_or(Kind::USER_NOT_SIGNED_IN)?;
+
+    if let Some(scopes) = get_service_scopes(req) {
+        if !scopes.iter().any(|scope| scope == permission_name) {
+            return Err(Kind::NO_PERMISSION.into());
+        }
+    }
+
+    let user_svc = req.app_data::<UserService>().ok_or(Kind::UNKNOWN)?;
+
+    if let Some(perm_version) = get_service_perm_version(req) {
+        if perm_version != user_svc.query_perm_version(user_id).await? {
+            set_service_jwt_stale(req);
+            return Err(Kind::PERMISSION_CHANGED.into());
+        }
+    }
+
+    let permissions = user_svc.query_user_perm(user_id).await?;
+
+    if !permissions
+        .iter()
+        .any(|p| p.permission_name == permission_name)
+    {
+        return Err(Kind::NO_PERMISSION.into());
+    }
+
+    let totp_svc = req.app_data::<TotpService>().ok_or(Kind::UNKNOWN)?;
+
+    if totp_svc.is_enabled(user_id).await? {
+        Ok(())
+    } else {
+        Err(Kind::TOTP_NOT_ENABLED.into())
+    }
+}
+
+impl<S, B> Service for PermissionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let svc = self.service.clone();
+        let inner = self.inner.clone();
+
+        async move {
+            if let Err(e) = check_permission(&req, inner.required(&req)).await {
+                return Ok(req.error_response(e));
+            }
+
+            let fut = svc.borrow_mut().call(req);
+            fut.await
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{Opts, PasswordOpts, SenderOpts};
+    use crate::util::crypto::PasswordHasher;
+    use crate::util::user::{set_service_token, set_service_user, SessionStore};
+    use actix_web::body::MessageBody;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, web, App, HttpResponse};
+    use serde_json::Value;
+
+    /// 测试请求使用的认证方式：`x-test-auth` 头为 `user:{id}`、`jwt:{id}:{perm_version}`
+    /// 或 `token:{id}:{scope},{scope}`，不带此头时为游客
+    fn set_test_auth(req: &ServiceRequest) {
+        let auth = match req.headers().get("x-test-auth") {
+            Some(auth) => auth.to_str().unwrap().to_owned(),
+            None => return,
+        };
+        let parts = auth.splitn(3, ':').collect::<Vec<_>>();
+        match parts[0] {
+            "user" => set_service_user(req, parts[1], None),
+            "jwt" => set_service_user(req, parts[1], Some(parts[2].parse().unwrap())),
+            "token" => set_service_token(
+                req,
+                parts[1],
+                parts[2].split(',').map(String::from).collect(),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    /// 返回响应的状态码及错误码，未返回错误时错误码为 `None`
+    async fn parse_response<B: MessageBody>(
+        response: ServiceResponse<B>,
+    ) -> (StatusCode, Option<i64>) {
+        let status = response.status();
+        let body = test::read_body(response).await;
+        if body.as_ref() == b"ok" {
+            (status, None)
+        } else {
+            let error: Value = serde_json::from_slice(&body).unwrap();
+            (status, error["code"].as_i64())
+        }
+    }
+
+    macro_rules! call {
+        ($app:expr, $method:expr, $auth:expr) => {{
+            let mut req = test::TestRequest::with_uri("/r").method($method);
+            let auth: Option<String> = $auth;
+            if let Some(auth) = auth {
+                req = req.header("x-test-auth", auth);
+            }
+            parse_response(test::call_service($app, req.to_request()).await).await
+        }};
+    }
+
+    macro_rules! init_app {
+        ($configure:expr) => {
+            test::init_service(
+                App::new()
+                    .configure($configure)
+                    .service(
+                        web::resource("/r")
+                            .wrap(PermissionFactory::new("ptest:write").read("ptest:read"))
+                            .route(web::get().to(|| HttpResponse::Ok().body("ok")))
+                            .route(web::post().to(|| HttpResponse::Ok().body("ok"))),
+                    )
+                    .wrap_fn(|req, srv| {
+                        set_test_auth(&req);
+                        srv.call(req)
+                    }),
+            )
+            .await
+        };
+    }
+
+    #[test]
+    fn required_permission_by_method() {
+        let inner = PermissionInner {
+            write: "role:write".into(),
+            read: Some("role:read".into()),
+        };
+        for (method, required) in &[
+            (Method::GET, "role:read"),
+            (Method::HEAD, "role:read"),
+            (Method::OPTIONS, "role:read"),
+            (Method::POST, "role:write"),
+            (Method::PUT, "role:write"),
+            (Method::PATCH, "role:write"),
+            (Method::DELETE, "role:write"),
+        ] {
+            let req = test::TestRequest::default()
+                .method(method.clone())
+                .to_srv_request();
+            assert_eq!(inner.required(&req), *required, "{}", method);
+        }
+
+        let inner = PermissionInner {
+            write: "role:write".into(),
+            read: None,
+        };
+        let req = test::TestRequest::default().to_srv_request();
+        assert_eq!(inner.required(&req), "role:write");
+    }
+
+    #[actix_rt::test]
+    async fn reject_without_querying_services() {
+        // 未注册任何服务，需要查询数据库时会返回 `UNKNOWN`
+        let mut app = init_app!(|_| ());
+
+        assert_eq!(
+            call!(&mut app, Method::GET, None),
+            (
+                StatusCode::UNAUTHORIZED,
+                Some(Kind::USER_NOT_SIGNED_IN.code())
+            )
+        );
+
+        // 令牌的 scopes 中没有所需的权限
+        for (method, scopes) in &[
+            (Method::GET, "ptest:write"),
+            (Method::POST, "ptest:read"),
+            (Method::POST, "role:write,ptest:writer"),
+        ] {
+            assert_eq!(
+                call!(
+                    &mut app,
+                    method.clone(),
+                    Some(format!("token:1:{}", scopes))
+                ),
+                (StatusCode::UNAUTHORIZED, Some(Kind::NO_PERMISSION.code())),
+                "{} {}",
+                method,
+                scopes
+            );
+        }
+
+        // scopes 满足时继续查询用户的权限
+        assert_eq!(
+            call!(
+                &mut app,
+                Method::POST,
+                Some("token:1:ptest:read,ptest:write".into())
+            )
+            .1,
+            Some(Kind::UNKNOWN.code())
+        );
+    }
+
+    const CLEAN_UP: &str = "\
+        delete from user_totp where user_id in (select id from user_info where username like 'ptest\\_%'); \
+        delete from user_role where user_id in (select id from user_info where username like 'ptest\\_%'); \
+        delete from user_info where username like 'ptest\\_%'; \
+        delete from role_permission where role_id in (select id from role where name = 'ptest_reader'); \
+        delete from role where name = 'ptest_reader'; \
+        delete from permission where permission_name = 'ptest:read';";
+
+    #[actix_rt::test]
+    async fn check_user_permissions() {
+        let opts = match Opts::open_test().await {
+            Some(opts) => opts,
+            None => return,
+        };
+        let pg_pool = opts.db.create_pool().unwrap();
+        let redis_pool = opts.redis.create_pool().unwrap();
+
+        // 两个拥有 `ptest:read` 权限的用户，其中 `ptest_totp` 已启用两步验证
+        let pg = pg_pool.get().await.unwrap();
+        pg.batch_execute(CLEAN_UP).await.unwrap();
+        pg.batch_execute(
+            "insert into permission(permission_name) values('ptest:read'); \
+             insert into role(name) values('ptest_reader'); \
+             insert into role_permission(role_id, permission_id) \
+                 select role.id, permission.id from role, permission \
+                 where role.name = 'ptest_reader' and permission.permission_name = 'ptest:read'; \
+             insert into user_info(username, nickname) values('ptest_totp', 'ptest'), ('ptest_nototp', 'ptest'); \
+             insert into user_role(user_id, role_id) \
+                 select user_info.id, role.id from user_info, role \
+                 where user_info.username like 'ptest\\_%' and role.name = 'ptest_reader'; \
+             insert into user_totp(user_id, secret) \
+                 select id, 'GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ' from user_info where username = 'ptest_totp';",
+        )
+        .await
+        .unwrap();
+        let user_id = |username: &'static str| {
+            let pg = &pg;
+            async move {
+                pg.query_one("select id from user_info where username = $1", &[&username])
+                    .await
+                    .unwrap()
+                    .get::<_, Id>(0)
+            }
+        };
+        let totp_user = user_id("ptest_totp").await;
+        let no_totp_user = user_id("ptest_nototp").await;
+
+        let user_svc = UserService::new(
+            pg_pool.clone(),
+            redis_pool.clone(),
+            SessionStore::default(),
+            SenderOpts::default(),
+            PasswordOpts::default().create_policy().unwrap(),
+            PasswordHasher::default(),
+        );
+        let perm_version = user_svc.query_perm_version(totp_user).await.unwrap();
+        let totp_svc = TotpService::new(pg_pool.clone(), redis_pool.clone());
+
+        let mut app = init_app!(move |cfg: &mut web::ServiceConfig| {
+            cfg.data(user_svc).data(totp_svc);
+        });
+
+        let user = Some(format!("user:{}", totp_user));
+        assert_eq!(
+            call!(&mut app, Method::GET, user.clone()),
+            (StatusCode::OK, None)
+        );
+        assert_eq!(
+            call!(&mut app, Method::POST, user).1,
+            Some(Kind::NO_PERMISSION.code())
+        );
+
+        // 令牌的 scopes 不能超出用户拥有的权限
+        let token = Some(format!("token:{}:ptest:read,ptest:write", totp_user));
+        assert_eq!(
+            call!(&mut app, Method::GET, token.clone()),
+            (StatusCode::OK, None)
+        );
+        assert_eq!(
+            call!(&mut app, Method::POST, token).1,
+            Some(Kind::NO_PERMISSION.code())
+        );
+
+        // JWT 中的权限版本必须是最新的
+        assert_eq!(
+            call!(
+                &mut app,
+                Method::GET,
+                Some(format!("jwt:{}:{}", totp_user, perm_version))
+            ),
+            (StatusCode::OK, None)
+        );
+        assert_eq!(
+            call!(
+                &mut app,
+                Method::GET,
+                Some(format!("jwt:{}:{}", totp_user, perm_version + 1))
+            ),
+            (
+                StatusCode::UNAUTHORIZED,
+                Some(Kind::PERMISSION_CHANGED.code())
+            )
+        );
+
+        assert_eq!(
+            call!(
+                &mut app,
+                Method::GET,
+                Some(format!("user:{}", no_totp_user))
+            ),
+            (StatusCode::FORBIDDEN, Some(Kind::TOTP_NOT_ENABLED.code()))
+        );
+
+        pg.batch_execute(CLEAN_UP).await.unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! 验证码发送
+//!
+//! `CodeSender` 负责将验证码送达手机号或电子邮箱，目前有三种实现：
+//!
+//! 1. `SmtpSender`: 通过 SMTP 服务器发送邮件；
+//! 2. `HttpSmsSender`: 通过通用的 HTTP 短信网关发送短信；
+//! 3. `DevSender`: 开发调试用，将验证码写入文件或标准输出。
+//!
+//! 具体使用哪种实现由配置文件中的 `[sender.phone]` 和 `[sender.email]` 决定，详见 `opt::SenderOpts`。
+//!
+use crate::error::{Error, Kind};
+use crate::util::types::AuthCode;
+use actix_web::client::Client;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use async_trait::async_trait;
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::prelude::*;
+
+/// 验证码发送器
+#[async_trait(?Send)]
+pub trait CodeSender {
+    /// 将验证码 `auth_code` 发送给 `to`（E.164 格式的手机号或电子邮箱）
+    async fn send(&self, to: &str, auth_code: &AuthCode) -> Result<(), Error>;
+}
+
+/// 发送失败的原因
+#[derive(Debug)]
+struct SendError(String);
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SendError {}
+
+fn send_failed<E: fmt::Display>(e: E) -> Error {
+    Kind::SEND_AUTH_CODE_FAILED.with_detail(SendError(e.to_string()))
+}
+
+/// 将模板中的 `{to}` 和 `{code}` 替换为实际的接收者和验证码
+fn render(template: &str, to: &str, auth_code: &AuthCode) -> String {
+    template
+        .replace("{to}", to)
+        .replace("{code}", &auth_code.code)
+}
+
+/// SMTP 连接的加密方式
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// 直接使用 TLS 连接（通常为 465 端口）
+    Tls,
+    /// 先建立明文连接，再使用 STARTTLS 升级（通常为 587 端口）
+    StartTls,
+    /// 不加密，仅用于本地测试
+    None,
+}
+
+/// 通过 SMTP 服务器发送验证码邮件
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SmtpSender {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub subject: String,
+    /// 邮件正文模板，`{to}` 和 `{code}` 会被替换为实际的接收者和验证码
+    pub body: String,
+}
+
+impl SmtpSender {
+    /// 同步发送邮件，需在线程池中执行
+    fn send_blocking(&self, to: &str, auth_code: &AuthCode) -> Result<(), Error> {
+        let email = EmailBuilder::new()
+            .to(to)
+            .from(self.from.as_str())
+            .subject(self.subject.as_str())
+            .text(render(&self.body, to, auth_code))
+            .build()
+            .map_err(send_failed)?;
+
+        let security = match self.security {
+            SmtpSecurity::None => ClientSecurity::None,
+            SmtpSecurity::Tls | SmtpSecurity::StartTls => {
+                let connector = TlsConnector::new().map_err(send_failed)?;
+                let params = ClientTlsParameters::new(self.host.clone(), connector);
+                if self.security == SmtpSecurity::Tls {
+                    ClientSecurity::Wrapper(params)
+                } else {
+                    ClientSecurity::Required(params)
+                }
+            }
+        };
+
+        let mut client =
+            SmtpClient::new((self.host.as_str(), self.port), security).map_err(send_failed)?;
+
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            client = client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        client
+            .transport()
+            .send(email.into())
+            .map(|_| ())
+            .map_err(send_failed)
+    }
+}
+
+#[async_trait(?Send)]
+impl CodeSender for SmtpSender {
+    async fn send(&self, to: &str, auth_code: &AuthCode) -> Result<(), Error> {
+        let sender = self.clone();
+        let to = to.to_owned();
+        let auth_code = auth_code.clone();
+
+        web::block(move || sender.send_blocking(&to, &auth_code))
+            .await
+            .map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => Kind::WORKER_THREAD_ERROR.into(),
+            })
+    }
+}
+
+/// 通过通用的 HTTP 短信网关发送验证码短信
+///
+/// 向 `url` 发送 POST 请求，请求头为 `headers`，请求体为替换了占位符的 `body`，
+/// 网关返回 2XX 状态码即视为发送成功
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpSmsSender {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// 请求体模板，`{to}` 和 `{code}` 会被替换为实际的手机号和验证码
+    pub body: String,
+}
+
+#[async_trait(?Send)]
+impl CodeSender for HttpSmsSender {
+    async fn send(&self, to: &str, auth_code: &AuthCode) -> Result<(), Error> {
+        let mut request = Client::default().post(&self.url);
+
+        for (name, value) in self.headers.iter() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        let response = request
+            .send_body(render(&self.body, to, auth_code))
+            .await
+            .map_err(send_failed)?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(send_failed(format!("短信网关返回 {}", response.status())))
+        }
+    }
+}
+
+/// 开发调试用，将验证码追加写入文件 `path`，未配置 `path` 时输出到标准输出
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DevSender {
+    pub path: Option<PathBuf>,
+}
+
+#[async_trait(?Send)]
+impl CodeSender for DevSender {
+    async fn send(&self, to: &str, auth_code: &AuthCode) -> Result<(), Error> {
+        let line = format!("{} {}\n", to, auth_code);
+
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(send_failed)?;
+                file.write_all(line.as_bytes()).await.map_err(send_failed)?;
+                // tokio 的文件写入在线程池中完成，须等待写入结束，否则关闭文件时可能丢失数据
+                file.flush().await.map_err(send_failed)?;
+            }
+            None => print!("{}", line),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpRequest, HttpResponse};
+    use std::sync::{Arc, Mutex};
+
+    /// 短信网关收到的请求：路径、`x-api-key` 请求头及请求体
+    type Received = Arc<Mutex<Vec<(String, Option<String>, String)>>>;
+
+    /// 启动模拟的短信网关，`/ok` 返回 200，`/fail` 返回 500
+    fn mock_gateway(received: Received) -> test::TestServer {
+        test::start(move || {
+            let received = received.clone();
+            App::new().default_service(web::post().to(move |req: HttpRequest, body: String| {
+                let key = req
+                    .headers()
+                    .get("x-api-key")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned);
+                received
+                    .lock()
+                    .unwrap()
+                    .push((req.path().to_owned(), key, body));
+                let response = if req.path() == "/ok" {
+                    HttpResponse::Ok().finish()
+                } else {
+                    HttpResponse::InternalServerError().finish()
+                };
+                futures::future::ready(response)
+            }))
+        })
+    }
+
+    fn sms_sender(url: String) -> HttpSmsSender {
+        let mut headers = HashMap::new();
+        headers.insert("x-api-key".to_owned(), "secret".to_owned());
+        HttpSmsSender {
+            url,
+            headers,
+            body: r#"{"mobile":"{to}","text":"验证码 {code}，{code} 五分钟内有效"}"#.into(),
+        }
+    }
+
+    #[test]
+    fn render_replaces_all_placeholders() {
+        let auth_code = AuthCode::new("012345").unwrap();
+        assert_eq!(
+            render(
+                "{to}: {code} {code} {unknown}",
+                "+8613800000000",
+                &auth_code
+            ),
+            "+8613800000000: 012345 012345 {unknown}"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn http_sms_sender_renders_body_and_headers() {
+        let received = Received::default();
+        let srv = mock_gateway(received.clone());
+        let auth_code = AuthCode::new("654321").unwrap();
+
+        sms_sender(srv.url("/ok"))
+            .send("+8613800000000", &auth_code)
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, "/ok");
+        assert_eq!(received[0].1.as_deref(), Some("secret"));
+        assert_eq!(
+            received[0].2,
+            r#"{"mobile":"+8613800000000","text":"验证码 654321，654321 五分钟内有效"}"#
+        );
+    }
+
+    #[actix_rt::test]
+    async fn http_sms_sender_rejects_non_success_status() {
+        let received = Received::default();
+        let srv = mock_gateway(received.clone());
+        let auth_code = AuthCode::new("654321").unwrap();
+
+        let e = sms_sender(srv.url("/fail"))
+            .send("+8613800000000", &auth_code)
+            .await
+            .unwrap_err();
+
+        assert_eq!(e.kind().code(), Kind::SEND_AUTH_CODE_FAILED.code());
+        assert!(e.detail().unwrap().to_string().contains("500"));
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn http_sms_sender_reports_unreachable_gateway() {
+        let auth_code = AuthCode::new("654321").unwrap();
+
+        let e = sms_sender("http://127.0.0.1:1/ok".into())
+            .send("+8613800000000", &auth_code)
+            .await
+            .unwrap_err();
+
+        assert_eq!(e.kind().code(), Kind::SEND_AUTH_CODE_FAILED.code());
+    }
+
+    #[actix_rt::test]
+    async fn dev_sender_appends_to_file() {
+        let path = std::env::temp_dir().join(format!("admino-dev-sender-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sender = DevSender {
+            path: Some(path.clone()),
+        };
+
+        sender
+            .send("user@example.com", &AuthCode::new("111111").unwrap())
+            .await
+            .unwrap();
+        sender
+            .send("+8613800000000", &AuthCode::new("222222").unwrap())
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, "user@example.com 111111\n+8613800000000 222222\n");
+    }
+}
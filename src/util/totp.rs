@@ -0,0 +1,206 @@
+//! 基于时间的一次性密码(TOTP, RFC 6238)
+//!
+//! 使用 HMAC-SHA1、30 秒步长、6 位数字，与 Google Authenticator 等常见应用兼容。
+//! 密钥以 Base32 编码保存在 `user_totp` 表中，与用户的登录方式无关。
+//!
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::iter;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// otpauth URI 中的发行方名称，会显示在验证器应用中
+pub const ISSUER: &str = "admino";
+/// 时间步长（秒）
+pub const STEP: u64 = 30;
+/// 校验时允许的前后时间步数，用于容忍客户端时钟误差
+const SKEW: u64 = 1;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// 生成随机密钥，返回 Base32 编码
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    base32::encode(BASE32, &secret)
+}
+
+/// 生成供验证器应用扫码添加的 otpauth URI
+pub fn otpauth_uri(account: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(ISSUER, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        utf8_percent_encode(account, NON_ALPHANUMERIC),
+        secret,
+        issuer,
+        DIGITS,
+        STEP
+    )
+}
+
+/// 当前时间所在的时间步
+pub fn current_step() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+        / STEP
+}
+
+/// 计算时间步 `step` 对应的一次性密码
+fn code_at(key: &[u8], step: u64) -> Option<String> {
+    let mut mac = Hmac::<Sha1>::new_varkey(key).ok()?;
+    mac.input(&step.to_be_bytes());
+    let hash = mac.result().code();
+
+    // RFC 4226 5.3 动态截断
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+/// 校验一次性密码，成功时返回匹配的时间步，用于防止同一密码被重复使用
+///
+/// 只接受大于 `last_step` 的时间步
+pub fn verify(secret: &str, code: &str, last_step: Option<u64>) -> Option<u64> {
+    verify_at(secret, code, last_step, current_step())
+}
+
+/// 以 `now` 为当前时间步校验一次性密码，见 `verify`
+fn verify_at(secret: &str, code: &str, last_step: Option<u64>, now: u64) -> Option<u64> {
+    let key = base32::decode(BASE32, secret)?;
+
+    (now.saturating_sub(SKEW)..=now + SKEW)
+        .filter(|&step| match last_step {
+            Some(last) => step > last,
+            None => true,
+        })
+        .find(|&step| code_at(&key, step).as_deref() == Some(code))
+}
+
+/// 生成 `count` 个一次性恢复码，格式为 `xxxxx-xxxxx`
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let code: String = iter::repeat(())
+                .map(|()| OsRng.sample(Alphanumeric))
+                .take(10)
+                .collect::<String>()
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// 恢复码的 hash，恢复码本身熵足够高，无需使用慢 hash
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code.trim().to_lowercase();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 6238 附录 B 中 SHA1 使用的密钥 `12345678901234567890` 的 Base32 编码
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    /// RFC 6238 附录 B 的 SHA1 测试向量：(Unix 时间, 8 位一次性密码)
+    const RFC_VECTORS: &[(u64, &str)] = &[
+        (59, "94287082"),
+        (1_111_111_109, "07081804"),
+        (1_111_111_111, "14050471"),
+        (1_234_567_890, "89005924"),
+        (2_000_000_000, "69279037"),
+        (20_000_000_000, "65353130"),
+    ];
+
+    #[test]
+    fn rfc6238_vectors() {
+        let key = base32::decode(BASE32, RFC_SECRET).unwrap();
+        assert_eq!(key, b"12345678901234567890");
+
+        for (time, code) in RFC_VECTORS.iter() {
+            // 6 位一次性密码即 8 位的后 6 位
+            assert_eq!(code_at(&key, time / STEP).unwrap(), code[2..]);
+        }
+    }
+
+    #[test]
+    fn verify_allows_clock_skew() {
+        let key = base32::decode(BASE32, RFC_SECRET).unwrap();
+        let step = 1_234_567_890 / STEP;
+        let code = code_at(&key, step).unwrap();
+
+        assert_eq!(verify_at(RFC_SECRET, &code, None, step), Some(step));
+        assert_eq!(verify_at(RFC_SECRET, &code, None, step - 1), Some(step));
+        assert_eq!(verify_at(RFC_SECRET, &code, None, step + 1), Some(step));
+        assert_eq!(verify_at(RFC_SECRET, &code, None, step - 2), None);
+        assert_eq!(verify_at(RFC_SECRET, &code, None, step + 2), None);
+        assert_eq!(verify_at(RFC_SECRET, "000000", None, step), None);
+    }
+
+    #[test]
+    fn verify_rejects_replay() {
+        let key = base32::decode(BASE32, RFC_SECRET).unwrap();
+        let step = 1_234_567_890 / STEP;
+        let code = code_at(&key, step).unwrap();
+        let previous = code_at(&key, step - 1).unwrap();
+
+        // 同一个一次性密码不能使用两次
+        assert_eq!(verify_at(RFC_SECRET, &code, Some(step), step), None);
+        // 使用过较新的一次性密码后，较旧的（仍在误差范围内）也不能再使用
+        assert_eq!(verify_at(RFC_SECRET, &previous, Some(step), step), None);
+        assert_eq!(
+            verify_at(RFC_SECRET, &code, Some(step - 1), step),
+            Some(step)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_invalid_secret() {
+        assert_eq!(verify_at("not base32!", "123456", None, 1), None);
+    }
+
+    #[test]
+    fn secret_and_uri() {
+        let secret = generate_secret();
+        assert_eq!(base32::decode(BASE32, &secret).unwrap().len(), SECRET_LEN);
+
+        assert_eq!(
+            otpauth_uri("gt@example", RFC_SECRET),
+            "otpauth://totp/admino:gt%40example?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=admino&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes(10);
+        assert_eq!(codes.len(), 10);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 11 && &code[5..6] == "-"));
+
+        assert_eq!(
+            hash_recovery_code(" ABCDE-12345 "),
+            hash_recovery_code("abcde-12345")
+        );
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+}
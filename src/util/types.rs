@@ -1,11 +1,13 @@
 //! 各种工具类型
 use crate::error::{Error, Exception, Kind};
+use chrono::{NaiveDate, Utc};
 use phonenumber::{Mode, PhoneNumber};
 use postgres_types::private::BytesMut;
 use postgres_types::{FromSql, IsNull, ToSql, Type};
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
 use std::fmt;
 use std::str::FromStr;
 
@@ -244,3 +246,215 @@ impl Username {
 sql_str_val!(Username, "用户名");
 impl_se!(Username);
 impl_de!(Username);
+
+/// 昵称，首尾的空白字符会被去除
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Nickname(String);
+
+impl FromStr for Nickname {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if !(1..=32).contains(&s.chars().count()) || s.chars().any(char::is_control) {
+            Err(Kind::INVALID_NICKNAME.into())
+        } else {
+            Ok(Self(s.into()))
+        }
+    }
+}
+
+impl Nickname {
+    pub fn new(s: &str) -> Result<Self, Error> {
+        Self::from_str(s)
+    }
+}
+
+sql_str_val!(Nickname, "昵称");
+impl_se!(Nickname);
+impl_de!(Nickname);
+
+/// 校验出生日期，不能晚于今天（UTC）
+pub fn check_birthday(birthday: &NaiveDate) -> Result<(), Error> {
+    if *birthday > Utc::now().naive_utc().date() {
+        Err(Kind::INVALID_BIRTHDAY.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// 密码格式错误的具体原因
+#[derive(Debug)]
+struct PasswordError(String);
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+/// 密码策略，由配置文件中的 `[password]` 生成，见 `opt::PasswordOpts`
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// 至少须包含几类字符（大写字母、小写字母、数字、符号）
+    pub min_char_classes: usize,
+    /// 禁止使用的常见/已泄露密码，全部为小写
+    pub blocklist: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// 内置的常见弱密码，总是包含在 `blocklist` 中
+    pub const COMMON_PASSWORDS: &'static [&'static str] = &[
+        "123456",
+        "12345678",
+        "123456789",
+        "1234567890",
+        "111111",
+        "000000",
+        "password",
+        "password1",
+        "passw0rd",
+        "p@ssw0rd",
+        "qwerty",
+        "qwerty123",
+        "qwertyuiop",
+        "1qaz2wsx",
+        "1q2w3e4r",
+        "abc123",
+        "abcd1234",
+        "a123456",
+        "iloveyou",
+        "admin",
+        "admin123",
+        "welcome",
+        "letmein",
+        "woaini1314",
+    ];
+
+    fn check(&self, s: &str) -> Result<(), PasswordError> {
+        let len = s.chars().count();
+        if len < self.min_length || len > self.max_length {
+            return Err(PasswordError(format!(
+                "密码长度须为 {} 到 {} 个字符",
+                self.min_length, self.max_length
+            )));
+        }
+
+        let char_classes = [
+            s.chars().any(|c| c.is_ascii_uppercase()),
+            s.chars().any(|c| c.is_ascii_lowercase()),
+            s.chars().any(|c| c.is_ascii_digit()),
+            s.chars().any(|c| !c.is_ascii_alphanumeric()),
+        ]
+        .iter()
+        .filter(|&&has| has)
+        .count();
+        if char_classes < self.min_char_classes {
+            return Err(PasswordError(format!(
+                "密码须至少包含大写字母、小写字母、数字、符号中的 {} 类",
+                self.min_char_classes
+            )));
+        }
+
+        if self.blocklist.contains(&s.to_lowercase()) {
+            return Err(PasswordError("密码过于常见".into()));
+        }
+
+        Ok(())
+    }
+}
+
+/// 符合密码策略的明文密码
+#[derive(PartialEq, Eq, Clone)]
+pub struct Password(String);
+
+impl Password {
+    pub fn new(s: &str, policy: &PasswordPolicy) -> Result<Self, Error> {
+        policy
+            .check(s)
+            .map_err(|e| Kind::INVALID_PASSWORD.with_detail(e))?;
+        Ok(Self(s.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// 避免在日志中输出明文密码
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password(******)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::PasswordOpts;
+
+    fn policy(min_char_classes: usize) -> PasswordPolicy {
+        PasswordOpts {
+            min_char_classes,
+            ..PasswordOpts::default()
+        }
+        .create_policy()
+        .unwrap()
+    }
+
+    #[test]
+    fn password_length_boundaries() {
+        let policy = policy(2);
+
+        assert!(policy.check("abcdef1").is_err());
+        assert!(policy.check("abcdefg1").is_ok());
+        assert!(policy.check(&format!("{}1", "a".repeat(63))).is_ok());
+        assert!(policy.check(&format!("{}1", "a".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn password_length_counts_chars() {
+        let policy = policy(2);
+
+        // 7 个字符，21 个字节
+        assert!(policy.check("密码密码密码1").is_err());
+        // 8 个字符，非 ASCII 字符计为符号
+        assert!(policy.check("密码密码密码密1").is_ok());
+    }
+
+    #[test]
+    fn password_char_classes() {
+        assert!(policy(2).check("abcdefgh").is_err());
+        assert!(policy(2).check("abcdefg1").is_ok());
+        assert!(policy(2).check("abcdefg!").is_ok());
+        assert!(policy(4).check("Abcdefg1").is_err());
+        assert!(policy(4).check("Abcdef1!").is_ok());
+    }
+
+    #[test]
+    fn password_blocklist_ignores_case() {
+        let policy = policy(2);
+
+        assert!(policy.check("password1").is_err());
+        assert!(policy.check("Password1").is_err());
+        assert!(policy.check("PASSW0RD").is_err());
+        assert!(policy.check("Password2").is_ok());
+    }
+
+    #[test]
+    fn password_error_and_debug() {
+        let policy = policy(2);
+
+        let e = Password::new("short", &policy).unwrap_err();
+        assert_eq!(e.kind().code(), Kind::INVALID_PASSWORD.code());
+        assert!(e.detail().is_some());
+
+        let password = Password::new("!23QweAsd", &policy).unwrap();
+        assert_eq!(password.as_str(), "!23QweAsd");
+        assert_eq!(format!("{:?}", password), "Password(******)");
+    }
+}
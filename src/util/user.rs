@@ -3,12 +3,33 @@
 //! 使用 Redis 存储 Session，
 //! 每次收到 HTTP 请求都尝试使用 Cookie 中的 Key 从 Redis 中取出身份信息
 //!
+//! 每个会话另有一份元数据（创建时间、最近访问时间、IP、User-Agent），
+//! 并按身份标识建立索引，用于列出及注销用户的所有会话。
+//!
+//! 请求带有 `Authorization: Bearer` 头时，改为使用其中的个人访问令牌认证（见 `TokenService`），
+//! 此时忽略 Cookie，且只能使用令牌 `scopes` 中的权限。
+//!
+//! 会话模式为 `jwt` 时，Redis 中的会话作为刷新令牌使用，另外签发短期有效的 JWT 保存在名为
+//! `{name}_jwt` 的 Cookie 中，JWT 有效期内只从 Redis 中读取权限版本，不读取会话（见 `util::jwt`）。
+//!
+//! 用户被禁用或删除时，其所有会话被注销且权限版本递增（见 `UserService::set_user_status`），
+//! 之后的请求按游客处理；JWT 模式下，权限版本与 JWT 中的不一致时 JWT 立即失效，
+//! 同样因会话已被注销而按游客处理。
+//!
+//! 会话同时受空闲超时和绝对超时限制：每次访问都会延长有效期（为减少 Redis 写入，至多每分钟一次），
+//! 但不会超过登录时确定的绝对过期时间。
+//!
 use crate::error::{Error, Kind};
-use actix_web::cookie::{Cookie, CookieJar, Key};
-use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use crate::model::Session;
+use crate::service::token::TokenService;
+use crate::util::http::ClientIp;
+use crate::util::jwt::Jwt;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{Extensions, Payload, Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::http::{header, HeaderValue};
 use actix_web::{Error as ActixError, FromRequest, HttpMessage, HttpRequest};
-use deadpool_redis::{cmd, Pool as RedisPool};
+use chrono::{NaiveDateTime, Utc};
+use deadpool_redis::{cmd, Connection, Pool as RedisPool};
 use failure::_core::cell::RefCell;
 use futures::future::LocalBoxFuture;
 use futures::task::{Context, Poll};
@@ -18,6 +39,8 @@ use rand::rngs::OsRng;
 use rand::Rng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::iter;
 use std::rc::Rc;
 use time::Duration;
@@ -38,31 +61,29 @@ impl FromRequest for User {
 }
 
 impl User {
-    const DEFAULT_DURATION_SEC: i64 = 24 * 3600;
-
-    pub fn default_ttl() -> Duration {
-        Duration::seconds(Self::DEFAULT_DURATION_SEC)
-    }
-
     /// 获取身份标识
     ///
     /// TODO: Deserialize<'de> 玩不转，只能用 DeserializeOwned，是跟 extensions() 返回值的生命周期有关，还是我用法不对
     pub fn get<T: Serialize + DeserializeOwned>(&self) -> Option<T> {
-        if let Some(cache) = self.0.extensions().get::<UserCache>() {
-            match cache {
-                UserCache::User { identity, .. } => serde_json::from_str(identity).ok(),
-                UserCache::Guest { .. } => None,
-            }
-        } else {
-            None
+        get_identity(&self.0.extensions())
+    }
+
+    /// 获取通过会话登录的身份标识，修改密码、管理令牌等敏感操作应使用此方法
+    ///
+    /// 未登录时返回 `USER_NOT_SIGNED_IN`，使用个人访问令牌认证时返回 `SESSION_REQUIRED`
+    pub fn get_session<T: Serialize + DeserializeOwned>(&self) -> Result<T, Error> {
+        if self.is_access_token() {
+            return Err(Kind::SESSION_REQUIRED.into());
         }
+
+        self.get().ok_or_else(|| Kind::USER_NOT_SIGNED_IN.into())
     }
 
     /// 判断当前用户是否已登录
     pub fn is_user(&self) -> bool {
         if let Some(cache) = self.0.extensions().get::<UserCache>() {
             match cache {
-                UserCache::User { .. } => true,
+                UserCache::User { .. } | UserCache::Token { .. } => true,
                 UserCache::Guest { .. } => false,
             }
         } else {
@@ -70,16 +91,23 @@ impl User {
         }
     }
 
-    /// 登录
-    #[allow(dead_code)]
+    /// 判断当前请求是否使用个人访问令牌认证
+    pub fn is_access_token(&self) -> bool {
+        matches!(
+            self.0.extensions().get::<UserCache>(),
+            Some(UserCache::Token { .. })
+        )
+    }
+
+    /// 登录，会话在 `ttl` 后过期，期间不受空闲超时限制，Cookie 在浏览器关闭后仍然保留（“记住我”）
     pub fn sign_in_ttl<T: Serialize + DeserializeOwned>(
         &self,
         id: T,
         ttl: Duration,
-    ) -> Result<(), serde_json::Error> {
+    ) -> Result<(), Error> {
         self.0.extensions_mut().insert(UserCache::Guest {
             action: Some(SignIn {
-                identity: serde_json::to_string(&id)?,
+                identity: to_identity(&id)?,
                 ttl: Some(ttl),
             }),
         });
@@ -87,19 +115,26 @@ impl User {
         Ok(())
     }
 
-    /// 登录
+    /// 登录，会话受中间件配置的空闲超时及绝对超时限制，Cookie 在浏览器关闭后失效
     pub fn sign_in<T: Serialize + DeserializeOwned>(&self, id: T) -> Result<(), Error> {
         self.0.extensions_mut().insert(UserCache::Guest {
             action: Some(SignIn {
-                identity: serde_json::to_string(&id)
-                    .map_err(|e| Kind::DATA_FORMAT.with_detail(e))?,
-                ttl: Some(Self::default_ttl()),
+                identity: to_identity(&id)?,
+                ttl: None,
             }),
         });
 
         Ok(())
     }
 
+    /// 当前会话的令牌，未登录时返回 `None`
+    pub fn token(&self) -> Option<String> {
+        match self.0.extensions().get::<UserCache>() {
+            Some(UserCache::User { token, .. }) => Some(token.clone()),
+            _ => None,
+        }
+    }
+
     /// 登出
     pub fn sign_out(&self) {
         if let Some(cache) = self.0.extensions_mut().get_mut::<UserCache>() {
@@ -110,20 +145,108 @@ impl User {
     }
 }
 
+/// 在中间件中获取身份标识，需在 `UserMiddleware` 之后调用
+pub fn get_service_identity<T: Serialize + DeserializeOwned>(req: &ServiceRequest) -> Option<T> {
+    get_identity(&req.extensions())
+}
+
+/// 在中间件中获取 JWT 中的权限版本，未使用 JWT 认证时返回 `None`，需在 `UserMiddleware` 之后调用
+pub fn get_service_perm_version(req: &ServiceRequest) -> Option<u64> {
+    match req.extensions().get::<UserCache>() {
+        Some(UserCache::User {
+            jwt: JwtState::Valid { perm_version },
+            ..
+        }) => Some(*perm_version),
+        _ => None,
+    }
+}
+
+/// 在中间件中标记 JWT 的权限版本已过期，响应时将删除 JWT
+pub fn set_service_jwt_stale(req: &ServiceRequest) {
+    if let Some(UserCache::User { jwt, .. }) = req.extensions_mut().get_mut::<UserCache>() {
+        *jwt = JwtState::Stale;
+    }
+}
+
+/// 在中间件中获取个人访问令牌的 `scopes`，未使用令牌认证时返回 `None`，需在 `UserMiddleware` 之后调用
+pub fn get_service_scopes(req: &ServiceRequest) -> Option<Vec<String>> {
+    match req.extensions().get::<UserCache>() {
+        Some(UserCache::Token { scopes, .. }) => Some(scopes.clone()),
+        _ => None,
+    }
+}
+
+/// 测试中代替 `UserMiddleware` 设置通过会话认证的用户，`perm_version` 为 `Some` 时视为通过 JWT 认证
+#[cfg(test)]
+pub(crate) fn set_service_user(req: &ServiceRequest, identity: &str, perm_version: Option<u64>) {
+    req.extensions_mut().insert(UserCache::User {
+        identity: identity.into(),
+        token: String::new(),
+        jwt: match perm_version {
+            Some(perm_version) => JwtState::Valid { perm_version },
+            None => JwtState::Disabled,
+        },
+        action: None,
+    });
+}
+
+/// 测试中代替 `UserMiddleware` 设置通过个人访问令牌认证的用户
+#[cfg(test)]
+pub(crate) fn set_service_token(req: &ServiceRequest, identity: &str, scopes: Vec<String>) {
+    req.extensions_mut().insert(UserCache::Token {
+        identity: identity.into(),
+        scopes,
+    });
+}
+
+fn get_identity<T: Serialize + DeserializeOwned>(extensions: &Extensions) -> Option<T> {
+    if let Some(cache) = extensions.get::<UserCache>() {
+        match cache {
+            UserCache::User { identity, .. } | UserCache::Token { identity, .. } => {
+                serde_json::from_str(identity).ok()
+            }
+            UserCache::Guest { .. } => None,
+        }
+    } else {
+        None
+    }
+}
+
 /// 缓存当前身份标识及下一步操作，放在 HttpRequest 的 Extension 中
 enum UserCache {
     User {
         identity: String,
+        token: String,
+        jwt: JwtState,
         action: Option<SignOut>,
     },
     Guest {
         action: Option<SignIn>,
     },
+    /// 使用个人访问令牌认证，不能登出
+    Token {
+        identity: String,
+        scopes: Vec<String>,
+    },
+}
+
+/// JWT 模式下访问令牌的状态
+#[derive(Clone, Copy)]
+enum JwtState {
+    /// 未启用 JWT
+    Disabled,
+    /// 通过 JWT 认证，`perm_version` 为 JWT 中的权限版本
+    Valid { perm_version: u64 },
+    /// JWT 不存在或已过期，已通过 Redis 中的会话认证，响应时签发新的 JWT
+    Reissue,
+    /// JWT 中的权限版本已过期，响应时删除 JWT，下次请求将通过 Redis 中的会话认证
+    Stale,
 }
 
 /// 登录
 struct SignIn {
     identity: String,
+    /// 固定的有效期，为 `None` 时使用空闲超时及绝对超时
     ttl: Option<Duration>,
 }
 
@@ -134,7 +257,69 @@ struct UserInner {
     key: Key,
     secure: bool,
     name: String,
-    default_ttl: Duration,
+    same_site: Option<SameSite>,
+    domain: Option<String>,
+    path: String,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+    store: SessionStore,
+    jwt: Option<Jwt>,
+}
+
+impl UserInner {
+    /// 创建带有配置的属性的 Cookie，登录及登出时使用的 Cookie 属性须保持一致
+    fn cookie(&self, name: String, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::new(name, value);
+        cookie.set_path(self.path.clone());
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if let Some(same_site) = self.same_site {
+            cookie.set_same_site(same_site);
+        }
+        cookie.set_secure(self.secure);
+        cookie.set_http_only(true);
+        cookie
+    }
+
+    fn jwt_cookie_name(&self) -> String {
+        format!("{}_jwt", self.name)
+    }
+
+    /// 为身份标识签发 JWT 并加入 `jar`，未启用 JWT 时忽略
+    async fn add_jwt_cookie(
+        &self,
+        conn: &mut Connection,
+        identity: &str,
+        jar: &mut CookieJar,
+    ) -> Result<(), Error> {
+        if let Some(jwt) = &self.jwt {
+            let perm_version = self.store.perm_version(conn, identity).await?;
+            let mut cookie =
+                self.cookie(self.jwt_cookie_name(), jwt.issue(identity, perm_version)?);
+            cookie.set_max_age(Duration::seconds(jwt.ttl()));
+            jar.add(cookie);
+        }
+
+        Ok(())
+    }
+
+    /// 删除 JWT，未启用 JWT 时忽略
+    fn remove_jwt_cookie(&self, jar: &mut CookieJar) {
+        if self.jwt.is_some() {
+            let cookie = self.cookie(self.jwt_cookie_name(), String::new());
+            jar.add_original(cookie.clone());
+            jar.remove(cookie);
+        }
+    }
+
+    /// 设置会话 Cookie 及 JWT 立即失效
+    fn remove_cookies(&self, jar: &mut CookieJar) {
+        let cookie = self.cookie(self.name.clone(), String::new());
+        jar.add_original(cookie.clone());
+        jar.signed(&self.key).remove(cookie);
+        self.remove_jwt_cookie(jar);
+    }
 }
 
 /// 身份标识中间件工厂
@@ -144,6 +329,9 @@ pub struct UserFactory {
 }
 
 impl UserFactory {
+    const DEFAULT_IDLE_TIMEOUT_SEC: i64 = 2 * 3600;
+    const DEFAULT_ABSOLUTE_TIMEOUT_SEC: i64 = 24 * 3600;
+
     /// 使用 Redis 线程池创建一个身份标识中间件工厂
     pub fn new(key: &[u8], redis_pool: RedisPool) -> Self {
         let key: Vec<u8> = key.iter().chain([1, 0, 0, 0].iter()).cloned().collect();
@@ -152,28 +340,71 @@ impl UserFactory {
                 key: Key::from_master(&key),
                 secure: false,
                 name: "user-cookie".into(),
-                default_ttl: User::default_ttl(),
+                same_site: None,
+                domain: None,
+                path: "/".into(),
+                idle_timeout: Duration::seconds(Self::DEFAULT_IDLE_TIMEOUT_SEC),
+                absolute_timeout: Duration::seconds(Self::DEFAULT_ABSOLUTE_TIMEOUT_SEC),
+                store: SessionStore::default(),
+                jwt: None,
             }),
             pool: redis_pool,
         }
     }
 
+    /// Cookie 名称
     pub fn name<T: Into<String>>(mut self, value: T) -> UserFactory {
         Rc::get_mut(&mut self.inner).unwrap().name = value.into();
         self
     }
 
-    #[allow(dead_code)]
-    pub fn default_ttl(mut self, ttl: Duration) -> UserFactory {
-        Rc::get_mut(&mut self.inner).unwrap().default_ttl = ttl;
+    /// 空闲超时，超过该时长未访问则会话失效
+    pub fn idle_timeout(mut self, timeout: Duration) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().idle_timeout = timeout;
+        self
+    }
+
+    /// 绝对超时，自登录起超过该时长会话即失效，无论是否活跃
+    pub fn absolute_timeout(mut self, timeout: Duration) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().absolute_timeout = timeout;
         self
     }
 
-    #[allow(dead_code)]
+    /// 是否只允许通过 HTTPS 发送 Cookie
     pub fn secure(mut self, secure: bool) -> UserFactory {
         Rc::get_mut(&mut self.inner).unwrap().secure = secure;
         self
     }
+
+    /// Cookie 的 SameSite 属性，为 `None` 时不设置
+    pub fn same_site(mut self, same_site: Option<SameSite>) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().same_site = same_site;
+        self
+    }
+
+    /// Cookie 的 Domain 属性，为 `None` 时只发送给当前域名
+    pub fn domain(mut self, domain: Option<String>) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().domain = domain;
+        self
+    }
+
+    /// Cookie 的 Path 属性
+    pub fn path<T: Into<String>>(mut self, path: T) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().path = path.into();
+        self
+    }
+
+    /// 会话在 Redis 中的存储，须与服务使用的 `SessionStore` 一致
+    pub fn store(mut self, store: SessionStore) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().store = store;
+        self
+    }
+
+    /// 启用 JWT 模式，为 `None` 时每个请求都从 Redis 中读取会话
+    pub fn jwt(mut self, jwt: Option<Jwt>) -> UserFactory {
+        Rc::get_mut(&mut self.inner).unwrap().jwt = jwt;
+        self
+    }
 }
 
 impl<S, B> Transform<S> for UserFactory
@@ -199,14 +430,317 @@ where
     }
 }
 
-const IDENTITY_KEY_PREFIX: &str = "user:identity:";
 const IDENTITY_KEY_RAND_LEN: usize = 32;
+const SESSION_ID_LEN: usize = 16;
+/// 最近访问时间及会话有效期的最小刷新间隔（秒），避免每个请求都写一次 Redis
+const REFRESH_INTERVAL: i64 = 60;
+
+fn to_identity<T: Serialize>(id: &T) -> Result<String, Error> {
+    serde_json::to_string(id).map_err(|e| Kind::DATA_FORMAT.with_detail(e))
+}
 
-fn make_redis_key(token: &str) -> String {
-    let mut redis_key = String::with_capacity(IDENTITY_KEY_PREFIX.len() + IDENTITY_KEY_RAND_LEN);
-    redis_key += IDENTITY_KEY_PREFIX;
-    redis_key += token;
-    redis_key
+/// 会话在 Redis 中的存储，所有 Key 均以 `prefix` 开头：
+///
+/// * `{prefix}identity:{token}`: 身份标识；
+/// * `{prefix}session:{token}`: 会话元数据；
+/// * `{prefix}sessions:{identity}`: 会话索引，会话 ID 到令牌的映射；
+/// * `{prefix}permVersion:{identity}`: 权限版本，注销所有会话时递增，用于使已签发的 JWT 失效。
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    prefix: String,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new("user:")
+    }
+}
+
+impl SessionStore {
+    pub fn new<T: Into<String>>(prefix: T) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn identity_key(&self, token: &str) -> String {
+        format!("{}identity:{}", self.prefix, token)
+    }
+
+    fn session_key(&self, token: &str) -> String {
+        format!("{}session:{}", self.prefix, token)
+    }
+
+    fn index_key(&self, identity: &str) -> String {
+        format!("{}sessions:{}", self.prefix, identity)
+    }
+
+    fn perm_version_key(&self, identity: &str) -> String {
+        format!("{}permVersion:{}", self.prefix, identity)
+    }
+
+    async fn perm_version(&self, conn: &mut Connection, identity: &str) -> Result<u64, Error> {
+        let version: Option<u64> = cmd("GET")
+            .arg(self.perm_version_key(identity))
+            .query_async(conn)
+            .await?;
+
+        Ok(version.unwrap_or_default())
+    }
+
+    /// 查询身份标识 `id` 的权限版本
+    pub async fn query_perm_version<T: Serialize>(
+        &self,
+        conn: &mut Connection,
+        id: &T,
+    ) -> Result<u64, Error> {
+        self.perm_version(conn, &to_identity(id)?).await
+    }
+
+    /// 登录时保存会话元数据（包括 `request` 的 IP 及 User-Agent），并加入该身份标识的会话索引
+    ///
+    /// 元数据与身份信息的过期时间相同，初始为 `idle_timeout`；
+    /// 索引的过期时间取其中会话绝对过期时间的最大值
+    async fn create(
+        &self,
+        conn: &mut Connection,
+        identity: &str,
+        token: &str,
+        idle_timeout: i64,
+        absolute_timeout: i64,
+        request: &HttpRequest,
+    ) -> Result<(), Error> {
+        let ip = ClientIp::of(request).map(|ip| ip.to_string());
+        let user_agent = request
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|ua| ua.to_str().ok());
+
+        let session_id: String = iter::repeat(())
+            .map(|()| OsRng.sample(Alphanumeric))
+            .take(SESSION_ID_LEN)
+            .collect();
+        let now = Utc::now().timestamp();
+        let session_key = self.session_key(token);
+
+        let mut hset = cmd("HSET");
+        hset.arg(&session_key)
+            .arg("id")
+            .arg(&session_id)
+            .arg("identity")
+            .arg(identity)
+            .arg("created_at")
+            .arg(now)
+            .arg("last_seen")
+            .arg(now)
+            .arg("expires_at")
+            .arg(now + absolute_timeout)
+            .arg("idle_timeout")
+            .arg(idle_timeout);
+        if let Some(ip) = ip {
+            hset.arg("ip").arg(ip);
+        }
+        if let Some(user_agent) = user_agent {
+            hset.arg("user_agent").arg(user_agent);
+        }
+        hset.execute_async(conn).await?;
+
+        cmd("EXPIRE")
+            .arg(&session_key)
+            .arg(idle_timeout)
+            .execute_async(conn)
+            .await?;
+
+        let index_key = self.index_key(identity);
+        cmd("HSET")
+            .arg(&index_key)
+            .arg(&session_id)
+            .arg(token)
+            .execute_async(conn)
+            .await?;
+
+        // 没有设置过期时间时 TTL 返回 -1
+        let index_ttl: i64 = cmd("TTL").arg(&index_key).query_async(conn).await?;
+        if index_ttl < absolute_timeout {
+            cmd("EXPIRE")
+                .arg(&index_key)
+                .arg(absolute_timeout)
+                .execute_async(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// 登出时删除会话元数据并从索引中移除
+    async fn remove(&self, conn: &mut Connection, token: &str) -> Result<(), Error> {
+        let session_key = self.session_key(token);
+        let (session_id, identity): (Option<String>, Option<String>) = cmd("HMGET")
+            .arg(&session_key)
+            .arg("id")
+            .arg("identity")
+            .query_async(conn)
+            .await?;
+
+        cmd("DEL").arg(&session_key).execute_async(conn).await?;
+
+        if let (Some(session_id), Some(identity)) = (session_id, identity) {
+            cmd("HDEL")
+                .arg(self.index_key(&identity))
+                .arg(session_id)
+                .execute_async(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// 更新会话的最近访问时间，并将有效期延长为 `idle_timeout`，但不超过绝对过期时间
+    ///
+    /// 距上次刷新不足 `REFRESH_INTERVAL` 时忽略
+    async fn refresh(&self, conn: &mut Connection, token: &str) -> Result<(), Error> {
+        let session_key = self.session_key(token);
+        let (last_seen, expires_at, idle_timeout): (Option<i64>, Option<i64>, Option<i64>) =
+            cmd("HMGET")
+                .arg(&session_key)
+                .arg("last_seen")
+                .arg("expires_at")
+                .arg("idle_timeout")
+                .query_async(conn)
+                .await?;
+
+        let now = Utc::now().timestamp();
+        if let (Some(last_seen), Some(expires_at), Some(idle_timeout)) =
+            (last_seen, expires_at, idle_timeout)
+        {
+            if now - last_seen < REFRESH_INTERVAL {
+                return Ok(());
+            }
+
+            cmd("HSET")
+                .arg(&session_key)
+                .arg("last_seen")
+                .arg(now)
+                .execute_async(conn)
+                .await?;
+
+            let ttl = idle_timeout.min(expires_at - now);
+            if ttl > 0 {
+                for key in [self.identity_key(token), session_key].iter() {
+                    cmd("EXPIRE").arg(key).arg(ttl).execute_async(conn).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 列出身份标识 `id` 的所有会话，按最近访问时间倒序，`current_token` 对应的会话标记为当前会话
+    ///
+    /// 已过期的会话会顺便从索引中清除
+    pub async fn list<T: Serialize>(
+        &self,
+        conn: &mut Connection,
+        id: &T,
+        current_token: Option<&str>,
+    ) -> Result<Vec<Session>, Error> {
+        let identity = to_identity(id)?;
+        let index_key = self.index_key(&identity);
+        let index: HashMap<String, String> =
+            cmd("HGETALL").arg(&index_key).query_async(conn).await?;
+
+        let mut sessions = Vec::with_capacity(index.len());
+        for (session_id, token) in index {
+            let fields: HashMap<String, String> = cmd("HGETALL")
+                .arg(self.session_key(&token))
+                .query_async(conn)
+                .await?;
+
+            if fields.get("identity") != Some(&identity) {
+                cmd("HDEL")
+                    .arg(&index_key)
+                    .arg(&session_id)
+                    .execute_async(conn)
+                    .await?;
+                continue;
+            }
+
+            let time = |name: &str| {
+                let secs = fields.get(name).and_then(|t| t.parse().ok()).unwrap_or(0);
+                NaiveDateTime::from_timestamp(secs, 0)
+            };
+
+            sessions.push(Session {
+                created_at: time("created_at"),
+                last_seen: time("last_seen"),
+                expires_at: time("expires_at"),
+                ip: fields.get("ip").cloned(),
+                user_agent: fields.get("user_agent").cloned(),
+                current: current_token == Some(token.as_str()),
+                id: session_id,
+            });
+        }
+
+        sessions.sort_by_key(|session| Reverse(session.last_seen));
+
+        Ok(sessions)
+    }
+
+    /// 注销身份标识 `id` 的指定会话，会话不存在时返回 `EMPTY_RESULT`
+    pub async fn revoke<T: Serialize>(
+        &self,
+        conn: &mut Connection,
+        id: &T,
+        session_id: &str,
+    ) -> Result<(), Error> {
+        let index_key = self.index_key(&to_identity(id)?);
+        let token: Option<String> = cmd("HGET")
+            .arg(&index_key)
+            .arg(session_id)
+            .query_async(conn)
+            .await?;
+        let token = token.ok_or(Kind::EMPTY_RESULT)?;
+
+        cmd("DEL")
+            .arg(self.identity_key(&token))
+            .arg(self.session_key(&token))
+            .execute_async(conn)
+            .await?;
+        cmd("HDEL")
+            .arg(&index_key)
+            .arg(session_id)
+            .execute_async(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// 注销身份标识 `id` 的所有会话，并使已签发的 JWT 失效
+    pub async fn revoke_all<T: Serialize>(
+        &self,
+        conn: &mut Connection,
+        id: &T,
+    ) -> Result<(), Error> {
+        let identity = to_identity(id)?;
+
+        cmd("INCR")
+            .arg(self.perm_version_key(&identity))
+            .execute_async(conn)
+            .await?;
+
+        let index_key = self.index_key(&identity);
+        let tokens: Vec<String> = cmd("HVALS").arg(&index_key).query_async(conn).await?;
+
+        let mut del = cmd("DEL");
+        del.arg(&index_key);
+        for token in tokens.iter() {
+            del.arg(self.identity_key(token))
+                .arg(self.session_key(token));
+        }
+        del.execute_async(conn).await?;
+
+        Ok(())
+    }
 }
 
 /// 身份标识中间件
@@ -238,6 +772,13 @@ where
         let pool = self.pool.clone();
         let inner = self.inner.clone();
 
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|value| value.trim().to_owned());
+
         let token = if let Some(cookie) = req.cookie(&inner.name) {
             let mut jar = CookieJar::new();
             jar.add_original(cookie.clone());
@@ -248,26 +789,99 @@ where
             None
         };
 
+        // JWT 模式下，JWT 有效时只需从 Redis 中读取权限版本，无需读取会话
+        let claims = inner.jwt.as_ref().and_then(|jwt| {
+            req.cookie(&inner.jwt_cookie_name())
+                .and_then(|cookie| jwt.verify(cookie.value()))
+        });
+
         Box::pin(
             async move {
-                // 如果 cookie 中存在 key，尝试从 redis 中取出
-                if let Some(token) = &token {
+                // 使用个人访问令牌时不处理 cookie，令牌无效时直接返回错误
+                if let Some(bearer) = &bearer {
+                    let token_svc = req
+                        .app_data::<TokenService>()
+                        .ok_or_else(|| Error::from(Kind::UNKNOWN))
+                        .map_err(ActixError::from)?;
+
+                    let access_token = match token_svc.authenticate(bearer).await {
+                        Ok(access_token) => access_token,
+                        Err(e) => return Ok(req.error_response(e)),
+                    };
+
+                    req.extensions_mut().insert(UserCache::Token {
+                        identity: to_identity(&access_token.user_id)?,
+                        scopes: access_token.scopes,
+                    });
+
+                    return svc.call(req).await;
+                }
+
+                // 每个请求都比对 JWT 中的权限版本，用户被禁用、删除或权限变化后 JWT 立即失效，
+                // 改为使用 Redis 中的会话认证：会话已被注销时按游客处理，否则重新签发 JWT
+                let mut jwt_stale = false;
+                let claims = match (&token, claims) {
+                    (Some(_), Some(claims)) => {
+                        let mut conn = pool
+                            .get()
+                            .await
+                            .map_err(Error::from)
+                            .map_err(ActixError::from)?;
+                        let perm_version = inner
+                            .store
+                            .perm_version(&mut conn, &claims.sub)
+                            .await
+                            .map_err(ActixError::from)?;
+
+                        if perm_version == claims.pv {
+                            Some(claims)
+                        } else {
+                            jwt_stale = true;
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+
+                if let (Some(token), Some(claims)) = (&token, claims) {
+                    req.extensions_mut().insert(UserCache::User {
+                        identity: claims.sub,
+                        token: token.clone(),
+                        jwt: JwtState::Valid {
+                            perm_version: claims.pv,
+                        },
+                        action: None,
+                    });
+                } else if let Some(token) = &token {
+                    // 如果 cookie 中存在 key，尝试从 redis 中取出
                     let mut conn = pool
                         .get()
                         .await
                         .map_err(Error::from)
                         .map_err(ActixError::from)?;
                     let id: Option<String> = cmd("GET")
-                        .arg(make_redis_key(token))
+                        .arg(inner.store.identity_key(token))
                         .query_async(&mut conn)
                         .await
                         .map_err(Error::from)
                         .map_err(ActixError::from)?;
 
                     if let Some(identity) = id {
+                        inner
+                            .store
+                            .refresh(&mut conn, token)
+                            .await
+                            .map_err(ActixError::from)?;
+
                         // 如果取成功了，在 HttpRequest 的 extensions 中插入用户身份标识
                         req.extensions_mut().insert(UserCache::User {
                             identity,
+                            token: token.clone(),
+                            jwt: if inner.jwt.is_some() {
+                                JwtState::Reissue
+                            } else {
+                                JwtState::Disabled
+                            },
                             action: None,
                         });
                     } else {
@@ -301,47 +915,95 @@ where
                                     .map_err(Error::from)
                                     .map_err(ActixError::from)?;
                                 cmd("DEL")
-                                    .arg(make_redis_key(token))
+                                    .arg(inner.store.identity_key(token))
                                     .execute_async(&mut conn)
                                     .await
                                     .map_err(Error::from)
                                     .map_err(ActixError::from)?;
+                                inner
+                                    .store
+                                    .remove(&mut conn, token)
+                                    .await
+                                    .map_err(ActixError::from)?;
                             }
 
                             // 设置cookie立即失效
-                            let cookie = Cookie::new(inner.name.clone(), "");
-                            jar.add_original(cookie.clone());
-                            jar.signed(key).remove(cookie);
+                            inner.remove_cookies(&mut jar);
                         }
+                        UserCache::User {
+                            identity,
+                            jwt: JwtState::Reissue,
+                            ..
+                        } => {
+                            let mut conn = pool
+                                .get()
+                                .await
+                                .map_err(Error::from)
+                                .map_err(ActixError::from)?;
+                            inner
+                                .add_jwt_cookie(&mut conn, identity, &mut jar)
+                                .await
+                                .map_err(ActixError::from)?;
+                        }
+                        UserCache::User {
+                            jwt: JwtState::Stale,
+                            ..
+                        } => inner.remove_jwt_cookie(&mut jar),
                         UserCache::Guest { action: Some(si) } => {
                             // 如果是游客并且有登陆动作
                             let token: String = iter::repeat(())
                                 .map(|()| OsRng.sample(Alphanumeric))
-                                .take(32)
+                                .take(IDENTITY_KEY_RAND_LEN)
                                 .collect();
 
-                            let ttl = si.ttl.unwrap_or(inner.default_ttl);
+                            // 指定了有效期时不受空闲超时限制，并设置持久 Cookie
+                            let (idle_timeout, absolute_timeout, max_age) = match si.ttl {
+                                Some(ttl) => (ttl, ttl, Some(ttl)),
+                                None => (inner.idle_timeout, inner.absolute_timeout, None),
+                            };
                             let mut conn = pool
                                 .get()
                                 .await
                                 .map_err(Error::from)
                                 .map_err(ActixError::from)?;
                             cmd("SETEX")
-                                .arg(&make_redis_key(&token))
-                                .arg(ttl.num_seconds())
+                                .arg(inner.store.identity_key(&token))
+                                .arg(idle_timeout.num_seconds())
                                 .arg(&si.identity)
                                 .execute_async(&mut conn)
                                 .await
                                 .map_err(Error::from)
                                 .map_err(ActixError::from)?;
 
+                            inner
+                                .store
+                                .create(
+                                    &mut conn,
+                                    &si.identity,
+                                    &token,
+                                    idle_timeout.num_seconds(),
+                                    absolute_timeout.num_seconds(),
+                                    response.request(),
+                                )
+                                .await
+                                .map_err(ActixError::from)?;
+
+                            inner
+                                .add_jwt_cookie(&mut conn, &si.identity, &mut jar)
+                                .await
+                                .map_err(ActixError::from)?;
+
                             // 设置 cookie
-                            let mut cookie = Cookie::new(inner.name.to_owned(), token);
-                            cookie.set_max_age(ttl);
-                            cookie.set_secure(inner.secure);
-                            cookie.set_http_only(true);
+                            let mut cookie = inner.cookie(inner.name.clone(), token);
+                            if let Some(max_age) = max_age {
+                                cookie.set_max_age(max_age);
+                            }
                             jar.signed(key).add(cookie);
                         }
+                        // 权限版本已过期且会话已被注销
+                        UserCache::Guest { action: None } if jwt_stale => {
+                            inner.remove_jwt_cookie(&mut jar)
+                        }
                         _ => {}
                     }
                 };